@@ -1,16 +1,33 @@
 use glow::*;
 use crate::lib::kinput::*;
 use crate::lib::kimg::*;
+use crate::lib::kfont::*;
+use crate::lib::kconf::*;
+use crate::lib::katlas::*;
+use crate::lib::kaction::*;
+#[cfg(target_os = "linux")]
+use crate::lib::kpad::*;
 use crate::krenderer::*;
+use crate::kaudio::*;
+use crate::ksynth::*;
+use crate::music::*;
+use crate::replay::*;
 use crate::game::*;
+use crate::scores::*;
 use glutin::event::{Event, WindowEvent};
+use std::rc::Rc;
 
 pub struct Application {
     gl: glow::Context,
     window: glutin::WindowedContext<glutin::PossiblyCurrent>,
 
     renderer: KRenderer,
+    atlas_info: Rc<AtlasInfo>,
     event_aggregator: EventAggregator,
+    audio: Audio,
+    game_audio: GameAudio,
+    recorder: Option<Recorder>,
+    cursor: CursorMode,
 
     pub xres: f32,
     pub yres: f32,
@@ -18,6 +35,9 @@ pub struct Application {
     game: Game,
 }
 
+pub const CONTROLS_PATH: &str = "controls.conf";
+pub const SCORES_PATH: &str = "scores.conf";
+
 pub fn load_file(paths: &[&str]) -> String {
     for path in paths {
         if let Ok(s) = std::fs::read_to_string(path) {
@@ -29,10 +49,15 @@ pub fn load_file(paths: &[&str]) -> String {
 
 impl Application {
     pub fn new(event_loop: &glutin::event_loop::EventLoop<()>) -> Application {
+        // logical size, the window comes out bigger in pixels on a hidpi screen
         let default_xres = 1600.0;
         let default_yres = 900.0;
 
         let (gl, window) = unsafe { opengl_boilerplate(default_xres, default_yres, event_loop) };
+        let size = window.window().inner_size();
+        let scale_factor = window.window().scale_factor();
+        let (xres, yres) = (size.width as f32, size.height as f32);
+        unsafe { gl.viewport(0, 0, size.width as i32, size.height as i32) };
         
         let uvv = &[
             "src/uv.vert",
@@ -52,36 +77,90 @@ impl Application {
             .or(ImageBufferA::new_from_file("atlas.png")))
             .expect("couldn't load atlas from ./atlas.png");
 
+        // named regions, without it the hard coded rects in krenderer get used
+        let regions = load_conf(&["src/atlas.manifest", "../../src/atlas.manifest", "atlas.manifest"], "");
+        let mut regions = Manifest::from_conf(&regions).map(|m| m.uv_map()).unwrap_or_default();
+        regions.entry("blank".to_owned()).or_insert(blank_clip);
+        regions.entry("font".to_owned()).or_insert(text_clip);
+
+        // sprites get packed in under the hand made atlas, then bitmap fonts under that
+        let sprites = load_png_dir("sprites").or_else(|_| load_png_dir("../../sprites")).unwrap_or_default();
+        let atlas = if sprites.is_empty() {
+            atlas
+        } else {
+            let (atlas, r) = append_sheet(&atlas, &regions, &sprites, 1);
+            regions = r;
+            atlas
+        };
+        let fonts = load_font_dir(&["fonts", "../../fonts"]);
+        let (atlas, base_uv, fonts) = append_fonts(&atlas, &fonts);
+        let atlas_info = Rc::new(AtlasInfo { base_uv, fonts, regions });
+
         let renderer = KRenderer::new(&gl, uv_shader, atlas);
 
+        let audio = Audio::start_default();
+        let mut game = Game::new(0);
+        game.set_high_scores(HighScores::load(&[SCORES_PATH]));
+        let game_audio = GameAudio::new(&mut audio.mixer.lock().unwrap(), game.mood(), &load_patches());
+
+        let mut event_aggregator = EventAggregator::new(xres, yres, scale_factor, PLAYFIELD_ASPECT);
+        let bindings = Bindings::load(&[CONTROLS_PATH]);
+        for (a, b, input) in bindings.conflicts() {
+            println!("{}: {} is bound to both {} and {}", CONTROLS_PATH, input.name(), a.name(), b.name());
+        }
+        event_aggregator.set_bindings(bindings);
+        #[cfg(target_os = "linux")]
+        event_aggregator.set_pad_backend(Box::new(JsPads::new()));
+
         Application {
             gl,
             window,
             renderer,
-            event_aggregator: EventAggregator::new(default_xres, default_yres),
+            atlas_info,
+            event_aggregator,
+            audio,
+            game_audio,
+            recorder: None,
+            cursor: CursorMode::default(),
 
-            game: Game::new(0),
+            game,
+
+            xres,
+            yres,
+        }
+    }
+
+    // every frame of input from here on goes to path, gball render-audio plays it back
+    pub fn record_to(&mut self, path: &str) -> std::io::Result<()> {
+        self.recorder = Some(Recorder::create(path, &self.game, self.event_aggregator.bindings())?);
+        Ok(())
+    }
 
-            xres: default_xres,
-            yres: default_yres,
+    fn set_cursor(&mut self, mode: CursorMode) {
+        self.window.window().set_cursor_visible(mode.visible);
+        if let Err(e) = self.window.window().set_cursor_grab(mode.grab) {
+            println!("couldnt grab the cursor: {}", e);
         }
+        self.cursor = mode;
+    }
+
+    fn resize(&mut self, physical_size: glutin::dpi::PhysicalSize<u32>) {
+        self.window.resize(physical_size);
+        self.xres = physical_size.width as f32;
+        self.yres = physical_size.height as f32;
+        unsafe {self.gl.viewport(0, 0, physical_size.width as i32, physical_size.height as i32)};
     }
 
     pub fn handle_event(&mut self, event: &glutin::event::Event<()>) {
         match event {
             Event::WindowEvent { ref event, .. } => match event {
                 WindowEvent::Resized(physical_size) => {
-                    self.window.resize(*physical_size);
-                    self.xres = physical_size.width as f32;
-                    self.yres = physical_size.height as f32;
-                    unsafe {self.gl.viewport(0, 0, physical_size.width as i32, physical_size.height as i32)};
+                    self.resize(*physical_size);
                 },
-                WindowEvent::Focused(false) => {
-                    self.game.paused = true;
+                // we take the size winit suggests, new_inner_size is left alone
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    self.resize(**new_inner_size);
                 },
-                WindowEvent::Focused(true) => {
-                    self.game.paused = false;
-                }
                 _ => {},
             _ => {},
             }
@@ -96,10 +175,34 @@ impl Application {
             } 
 
             let mut kc = KRCanvas::new();
+            kc.set_atlas(self.atlas_info.clone());
+            kc.set_viewport_px(self.xres, self.yres);
 
             self.game.frame(&inputs, &mut kc);
 
-            self.renderer.send(&self.gl, &kc.bytes());
+            if let Some(bindings) = self.game.take_bindings() {
+                if let Err(e) = bindings.save(CONTROLS_PATH) {
+                    println!("couldnt save {}: {}", CONTROLS_PATH, e);
+                }
+                self.event_aggregator.set_bindings(bindings);
+            }
+            if let Some(scores) = self.game.take_high_scores() {
+                if let Err(e) = scores.save(SCORES_PATH) {
+                    println!("couldnt save {}: {}", SCORES_PATH, e);
+                }
+            }
+            let cursor = self.game.cursor_mode();
+            if cursor != self.cursor {
+                self.set_cursor(cursor);
+            }
+            self.game_audio.update(&mut self.audio.mixer.lock().unwrap(), &self.game);
+            // once it fails give up on it rather than complaining every frame
+            if let Some(Err(e)) = self.recorder.as_mut().map(|r| r.frame(&inputs)) {
+                println!("recording failed, stopping: {}", e);
+                self.recorder = None;
+            }
+
+            self.renderer.send(&self.gl, &kc.draw_list());
 
             self.window.swap_buffers().unwrap();
         }
@@ -147,7 +250,7 @@ fn  make_shader(gl: &glow::Context, vert_paths: &[&str], frag_paths: &[&str]) ->
 unsafe fn opengl_boilerplate(xres: f32, yres: f32, event_loop: &glutin::event_loop::EventLoop<()>) -> (glow::Context, glutin::WindowedContext<glutin::PossiblyCurrent>) {
     let window_builder = glutin::window::WindowBuilder::new()
         .with_title("gball")
-        .with_inner_size(glutin::dpi::PhysicalSize::new(xres, yres));
+        .with_inner_size(glutin::dpi::LogicalSize::new(xres, yres));
     let window = glutin::ContextBuilder::new()
         // .with_depth_buffer(0)
         // .with_srgb(true)
@@ -0,0 +1,168 @@
+use std::f32::consts::PI;
+
+use crate::lib::kmath::*;
+use crate::krenderer::*;
+
+// Wraps KRCanvas::set_camera with some juice. Every effect has its own
+// strength so they can be turned down or off individually, 0 is off.
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub follow: f32,
+    pub shake: f32,
+    pub zoom: f32,
+    pub flip: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            follow: 0.15,
+            shake: 1.0,
+            zoom: 1.0,
+            flip: 1.0,
+        }
+    }
+}
+
+pub struct Camera {
+    pub settings: CameraSettings,
+    offset: Vec2,
+    trauma: f32,
+    zoom: f32,
+    flip_t: f32,
+    flip_target: f32,
+    t: f32,
+    seed: u32,
+}
+
+// max shake at full trauma, in screen heights / radians
+const SHAKE_OFFSET: f32 = 0.03;
+const SHAKE_ANGLE: f32 = 0.05;
+
+impl Camera {
+    pub fn new(settings: CameraSettings, seed: u32) -> Camera {
+        Camera {
+            settings,
+            offset: Vec2::new(0.0, 0.0),
+            trauma: 0.0,
+            zoom: 0.0,
+            flip_t: 0.0,
+            flip_target: 0.0,
+            t: 0.0,
+            seed,
+        }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    pub fn punch_zoom(&mut self, amount: f32) {
+        self.zoom = self.zoom.max(amount);
+    }
+
+    pub fn set_flipped(&mut self, flipped: bool) {
+        self.flip_target = if flipped {1.0} else {0.0};
+    }
+
+    // eases towards looking at target, relative to the middle of the view
+    pub fn follow(&mut self, view: Rect, target: Vec2, dt: f32) {
+        let want = (target - view.centroid()) * self.settings.follow;
+        let k = 1.0 - (-6.0 * dt).exp();
+        self.offset = self.offset.lerp(want, k);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.t += dt;
+        self.trauma = (self.trauma - 1.2 * dt).max(0.0);
+        self.zoom = (self.zoom - 0.4 * dt).max(0.0);
+        let flip_speed = 4.0;
+        if self.flip_t < self.flip_target {
+            self.flip_t = (self.flip_t + flip_speed * dt).min(self.flip_target);
+        } else {
+            self.flip_t = (self.flip_t - flip_speed * dt).max(self.flip_target);
+        }
+    }
+
+    // few summed sines with seeded phases, smooth enough and cheap
+    fn noise(&self, channel: u32) -> f32 {
+        let s = khash(self.seed.wrapping_add(channel * 1928371));
+        let p1 = krand(s) * 2.0 * PI;
+        let p2 = krand(s.wrapping_mul(3)) * 2.0 * PI;
+        ((self.t * 37.0 + p1).sin() + (self.t * 23.0 + p2).sin() * 0.5) / 1.5
+    }
+
+    // camera rect, rotation and scale for the view this frame
+    pub fn transform(&self, view: Rect) -> (Rect, f32, Vec2) {
+        let shake = self.trauma * self.trauma * self.settings.shake;
+        let shake_offset = Vec2::new(self.noise(0), self.noise(1)) * (shake * SHAKE_OFFSET);
+        let zoom = 1.0 + self.zoom * self.settings.zoom;
+        let centre = view.centroid() + self.offset + shake_offset;
+
+        // squash through flat to mirrored, with a bit of a lean while its going
+        let f = smoothstep(self.flip_t) * self.settings.flip;
+        let scale_y = (f * PI).cos();
+        let lean = (f * PI).sin() * 0.08;
+        (Rect::centered(centre, view.w / zoom, view.h / zoom), shake * SHAKE_ANGLE * self.noise(2) + lean, Vec2::new(1.0, scale_y))
+    }
+
+    pub fn apply(&self, kc: &mut KRCanvas, view: Rect) {
+        let (r, angle, scale) = self.transform(view);
+        kc.set_camera(r);
+        kc.set_view_transform(angle, scale);
+    }
+}
+
+#[test]
+pub fn test_camera() {
+    let view = Rect::new(0.0, 0.0, 16.0/9.0, 1.0);
+    let step = |cam: &mut Camera, secs: f32| for _ in 0..(secs * 60.0) as u32 { cam.update(1.0 / 60.0); };
+
+    // shake goes off centre then dies down within a second
+    let mut cam = Camera::new(CameraSettings::default(), 3);
+    cam.add_trauma(0.7);
+    cam.add_trauma(0.7);
+    assert_eq!(cam.trauma, 1.0);
+    step(&mut cam, 0.1);
+    let (r, angle, _) = cam.transform(view);
+    assert!((r.centroid() - view.centroid()).magnitude() > 0.0 || angle != 0.0);
+    step(&mut cam, 1.0);
+    assert_eq!(cam.trauma, 0.0);
+    let (r, angle, _) = cam.transform(view);
+    assert!((r.centroid() - view.centroid()).magnitude() < 0.0001);
+    assert_eq!(angle, 0.0);
+
+    // strength 0 turns an effect off, half strength is half the zoom
+    let mut off = Camera::new(CameraSettings { shake: 0.0, zoom: 0.0, ..Default::default() }, 3);
+    off.add_trauma(1.0);
+    off.punch_zoom(0.2);
+    off.update(0.01);
+    let (r, angle, _) = off.transform(view);
+    assert_eq!((r.w, angle), (view.w, 0.0));
+    let mut half = Camera::new(CameraSettings { zoom: 0.5, ..Default::default() }, 3);
+    half.punch_zoom(0.2);
+    assert!((half.transform(view).0.h - 1.0 / 1.1).abs() < 0.0001);
+
+    // zoom punch only goes up and comes back in half a second
+    let mut cam = Camera::new(CameraSettings::default(), 3);
+    cam.punch_zoom(0.2);
+    cam.punch_zoom(0.1);
+    assert!(cam.transform(view).0.h < view.h);
+    step(&mut cam, 0.55);
+    assert_eq!(cam.transform(view).0.h, view.h);
+
+    // flip eases to mirrored in a quarter second, passing flat halfway, and back
+    cam.set_flipped(true);
+    cam.update(0.125);
+    assert!(cam.transform(view).2.y.abs() < 0.05);
+    step(&mut cam, 0.2);
+    assert_eq!(cam.transform(view).2, Vec2::new(1.0, -1.0));
+    cam.set_flipped(false);
+    step(&mut cam, 0.3);
+    assert_eq!(cam.transform(view).2, Vec2::new(1.0, 1.0));
+    let mut no_flip = Camera::new(CameraSettings { flip: 0.0, ..Default::default() }, 3);
+    no_flip.set_flipped(true);
+    step(&mut no_flip, 0.3);
+    assert_eq!(no_flip.transform(view).2, Vec2::new(1.0, 1.0));
+}
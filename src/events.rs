@@ -0,0 +1,143 @@
+use crate::lib::kmath::*;
+
+// Things that happen in a run. Game::frame pushes them as the physics finds
+// them and reacts to them itself afterwards (particles, camera), anything
+// outside the game (audio, stats, tests) reads Game::events once the frame is
+// done. The queue only holds the one frame.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickupKind {
+    Score,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeathCause {
+    Wall,
+    OutOfBounds,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    Flipped { grav_dir: f32 },
+    PickupCollected { kind: PickupKind, pos: Vec2 },
+    Died { cause: DeathCause },
+    WallPassed,
+    NearMiss,
+    ScoreMilestone(u32),
+    ThemeChanged,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventQueue {
+    events: Vec<GameEvent>,
+}
+
+impl EventQueue {
+    pub fn push(&mut self, e: GameEvent) {
+        self.events.push(e);
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &GameEvent> {
+        self.events.iter()
+    }
+
+    pub fn count(&self, f: impl Fn(&GameEvent) -> bool) -> usize {
+        self.events.iter().filter(|e| f(e)).count()
+    }
+
+    // for carrying a frames events over a reset
+    pub fn take(&mut self) -> EventQueue {
+        std::mem::take(self)
+    }
+}
+
+#[test]
+pub fn test_game_events() {
+    use crate::game::*;
+    use crate::krenderer::*;
+    use crate::replay::*;
+    use crate::lib::kinput::*;
+    use crate::lib::kaction::*;
+
+    // flip once early then nothing, gravity takes it out the bottom
+    let mut game = Game::new(99);
+    let mut seen = Vec::new();
+    let mut t = 0.0;
+    for i in 0..600u32 {
+        let actions = if i == 3 {vec![(Action::Flip, KeyStatus::JustPressed)]} else {vec![]};
+        let f = RecordedFrame { dt: 1.0 / 60.0, seed: khash(i), actions, edges: vec![], ..Default::default() };
+        t += f.dt;
+        game.frame(&f.to_inputs(i, t), &mut KRCanvas::new());
+        seen.extend(game.events().iter().copied());
+        if seen.iter().any(|e| matches!(e, GameEvent::Died { .. })) {
+            break;
+        }
+    }
+    assert_eq!(seen.iter().filter(|e| matches!(e, GameEvent::Flipped { .. })).count(), 1);
+    assert!(matches!(seen[0], GameEvent::Flipped { grav_dir } if grav_dir < 0.0));
+    let deaths: Vec<_> = seen.iter().filter(|e| matches!(e, GameEvent::Died { .. })).collect();
+    assert_eq!(deaths.len(), 1);
+    assert_eq!(*deaths[0], GameEvent::Died { cause: DeathCause::OutOfBounds });
+
+    // queue only holds the one frame
+    let f = RecordedFrame { dt: 1.0 / 60.0, seed: 0, actions: vec![], edges: vec![], ..Default::default() };
+    game.frame(&f.to_inputs(1000, t), &mut KRCanvas::new());
+    assert_eq!(game.events().count(|e| matches!(e, GameEvent::Died { .. })), 0);
+
+    // a bot that steers through the gaps and grabs pickups until the first
+    // milestone, then flies into the next wall. update only, drawing 50s of
+    // game is slow in debug
+    let mut game = Game::new(7);
+    let mut seen = Vec::new();
+    let (mut grav, mut last_y) = (1.0, game.player_pos().y);
+    let gap_h = 0.4;
+    for i in 0..20000u32 {
+        let pos = game.player_pos();
+        let vel = (pos.y - last_y) * 60.0;
+        last_y = pos.y;
+        let skim = seen.iter().filter(|e| matches!(e, GameEvent::WallPassed)).count() == 3;
+        let milestone = seen.iter().any(|e| matches!(e, GameEvent::ScoreMilestone(_)));
+        let wall = game.walls.iter().filter(|w| w.y < 0.0 && w.right() > pos.x - 0.03).min_by(|a, b| a.x.partial_cmp(&b.x).unwrap()).copied();
+        // only pickups before the next wall and not too far off its gap
+        let pickup = game.pickups.iter().find(|p| p.x > pos.x && wall.map(|w| p.x < w.x && (p.y - w.bot() - gap_h / 2.0).abs() < 0.25).unwrap_or(true)).copied();
+        let target = match (wall, pickup) {
+            (_, Some(p)) if !milestone => p.y,
+            (Some(w), _) if milestone => if w.bot() > 0.2 {w.bot() / 2.0} else {(w.bot() + gap_h + 1.0) / 2.0},
+            // skim the top of one gap for a near miss
+            (Some(w), _) if skim => w.bot() + 0.03,
+            (Some(w), _) => w.bot() + gap_h / 2.0,
+            _ => 0.5,
+        };
+        // full gravity towards the target until its time to brake, 3.2 is a bit under 2 * GRAVITY
+        let d = target - pos.y;
+        let want = if vel * d > 0.0 && vel * vel / 3.2 >= d.abs() {-vel.signum()} else {d.signum()};
+        let flip = want != grav;
+        let actions = if flip {vec![(Action::Flip, KeyStatus::JustPressed)]} else {vec![]};
+        let f = RecordedFrame { dt: 1.0 / 60.0, seed: khash(i), actions, edges: vec![], ..Default::default() };
+        t += f.dt;
+        game.update(&f.to_inputs(i, t));
+        for e in game.events().iter() {
+            if let GameEvent::Flipped { grav_dir } = e {
+                grav = *grav_dir;
+            }
+            seen.push(*e);
+        }
+        if seen.iter().any(|e| matches!(e, GameEvent::Died { .. })) {
+            break;
+        }
+    }
+    let count = |f: fn(&GameEvent) -> bool| seen.iter().filter(|e| f(e)).count();
+    assert!(count(|e| matches!(e, GameEvent::PickupCollected { kind: PickupKind::Score, .. })) > 0);
+    assert!(count(|e| matches!(e, GameEvent::WallPassed)) > 0);
+    assert_eq!(count(|e| matches!(e, GameEvent::ScoreMilestone(_))), 1);
+    // the skim, not the crash at the end
+    let near = seen.iter().position(|e| *e == GameEvent::NearMiss).unwrap();
+    assert!(near < seen.iter().position(|e| matches!(e, GameEvent::ScoreMilestone(_))).unwrap());
+    assert_eq!(seen[near + 1..].iter().find(|e| !matches!(e, GameEvent::Flipped { .. })), Some(&GameEvent::WallPassed));
+    assert!(seen.contains(&GameEvent::ScoreMilestone(SCORE_MILESTONE as u32)));
+    assert_eq!(seen.last(), Some(&GameEvent::Died { cause: DeathCause::Wall }));
+}
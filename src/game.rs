@@ -1,10 +1,25 @@
 use std::f32::consts::PI;
+use std::collections::VecDeque;
 
 use crate::lib::kinput::*;
 use crate::lib::kmath::*;
+use crate::lib::ktext::*;
+use crate::lib::kanim::*;
+use crate::lib::kconf::*;
 use crate::krenderer::*;
+use crate::particles::*;
+use crate::parallax::*;
+use crate::sky::*;
+use crate::theme::*;
+use crate::camera::*;
+use crate::music::*;
+use crate::events::*;
+use crate::rebind::*;
+use crate::lib::kaction::*;
+use crate::lib::kpath::*;
+use crate::lib::kedit::*;
+use crate::scores::*;
 
-use glutin::event::VirtualKeyCode;
 
 // yea maybe the event system cleans up the spawning situation
 
@@ -13,6 +28,7 @@ use glutin::event::VirtualKeyCode;
 
 // Can't trigger more than once per frame
 
+#[derive(Clone)]
 pub struct RngSequence {
     seed: u32,
 }
@@ -25,7 +41,7 @@ impl RngSequence {
     }
     pub fn sample(&mut self) -> u32 {
         let res = khash(self.seed);
-        self.seed = khash(self.seed + 394712377);
+        self.seed = khash(self.seed.wrapping_add(394712377));
         res
     }
     pub fn peek(&self) -> u32 {
@@ -33,6 +49,7 @@ impl RngSequence {
     }
 }
 
+#[derive(Clone)]
 pub struct RepeatTimer {
     t: f64,
     t_next: f64,
@@ -58,35 +75,122 @@ impl RepeatTimer {
     }
 }
 
+// playfield is this wide and 1 tall whatever the window, the rest is bars
+pub const PLAYFIELD_ASPECT: f32 = 16.0 / 9.0;
+
+pub const DEFAULT_ANIMS: &str = include_str!("../sprites/anims.conf");
+
+pub const BASE_WALL_SPEED: f32 = 0.45;
+// the music picks up over this much distance, up to double. the walls dont
+pub const MOOD_RAMP_DISTANCE: f32 = 100.0;
+pub const SCORE_MILESTONE: f64 = 10000.0;
+
+// physics runs in steps this long whatever the frame rate, so a flip can be
+// applied at the step it was pressed in rather than the next frame boundary
+pub const SIM_DT: f64 = 1.0 / 240.0;
+
+const GRAVITY: f32 = 1.8;
+const PLAYER_X: f32 = 0.5;
+const PLAYER_RADIUS: f32 = 0.02;
+const FORGIVE_RADIUS: f32 = 0.01;
+const PICKUP_RADIUS: f32 = 0.02;
+const PICKUP_SCORE: f64 = 1000.0;
+const GAP_H: f32 = 0.4;
+const WALL_W: f32 = 0.2;
+const NEAR_MISS_DIST: f32 = 0.015;
+// how long the score takes to slide down to the middle when you die
+const SCORE_LERP_TIME: f32 = 1.0;
+// a snapshot every this many steps (30 a second), keeping 5s of them for rewind
+const REWIND_STEPS: u32 = 8;
+const REWIND_KEEP: usize = 150;
+
+pub fn load_anims() -> Vec<Anim> {
+    parse_anims(&load_conf(&["sprites/anims.conf", "../../sprites/anims.conf"], DEFAULT_ANIMS))
+}
+
 pub struct Game {
     player_position: f32,
     player_velocidad: f32,
     player_current_anim_r: f32,
+    player_anim: Animator,
 
     grav_dir: f32,
     
     t: f64,
+    distance: f32,
 
     score: f64,
 
     wall_sequence: RngSequence,
     wall_spawn_timer: RepeatTimer,
 
-    walls: Vec<Rect>,
-    pickups: Vec<Vec2>,
+    // top halves of walls are the ones with y < 0, the gap starts at their bottom
+    pub walls: Vec<Rect>,
+    pub pickups: Vec<Vec2>,
 
-    clouds_far: Vec<(u32, f32)>,
-    clouds_mid: Vec<(u32, f32)>,
-    clouds_near: Vec<(u32, f32)>,
+    layers: Vec<ParallaxLayer>,
+    layers_warm: bool,
+    sky: Sky,
+    theme: Theme,
+    theme_toast: f32,
+    seed: u32,
 
-    cloud_spawn_timer: RepeatTimer,
+    pub camera: Camera,
+    near_miss: bool,
+    combo: u32,
+    danger: f32,
 
     score_lerp_timer: f32,
 
+    particles: ParticleSystem,
+    trail: Emitter,
+    flip_burst: Emitter,
+    pickup_burst: Emitter,
+    death_burst: Emitter,
+
     tutorial_phase: i32,
 
     pub paused: bool,
     dead: bool,
+
+    events: EventQueue,
+    milestone: u32,
+
+    rebind: Option<RebindScreen>,
+    new_bindings: Option<Bindings>,
+
+    high_scores: HighScores,
+    new_scores: Option<HighScores>,
+    name_field: Option<TextField>,  // up while typing a name for the table
+    name_asked: bool,
+    clipboard: Box<dyn Clipboard>,
+
+    sim_acc: f64,               // frame time not stepped yet
+    pending_flips: Vec<f64>,    // press times, input clock
+    clearance: f32,
+
+    history: VecDeque<Snapshot>,
+    history_steps: u32,         // steps since the last snapshot
+    rewind_acc: f64,            // rewinding time not played back yet
+}
+
+// the sim at one point, what rewind goes back to. nothing thats only for show
+#[derive(Clone)]
+struct Snapshot {
+    player_position: f32,
+    player_velocidad: f32,
+    grav_dir: f32,
+    t: f64,
+    distance: f32,
+    score: f64,
+    wall_sequence: RngSequence,
+    wall_spawn_timer: RepeatTimer,
+    walls: Vec<Rect>,
+    pickups: Vec<Vec2>,
+    combo: u32,
+    milestone: u32,
+    near_miss: bool,    // these two so a near miss still fires once going back over it
+    clearance: f32,
 }
 
 impl Game {
@@ -95,225 +199,573 @@ impl Game {
             player_position: 0.3,
             player_velocidad: 0.0,
             player_current_anim_r: 0.0,
+            player_anim: Animator::new(load_anims()),
 
             grav_dir: 1.0,
 
             t: 0.0,
+            distance: 0.0,
 
             paused: false,
 
             score: 0.0,
 
-            wall_sequence: RngSequence::new(seed * 34982349),
+            wall_sequence: RngSequence::new(seed.wrapping_mul(34982349)),
             wall_spawn_timer: RepeatTimer::new(2.0),
             walls: Vec::new(),
             pickups: Vec::new(),
 
-            clouds_far: Vec::new(),
-            clouds_mid: Vec::new(),
-            clouds_near: Vec::new(),
+            layers: load_layers().into_iter().map(|def| ParallaxLayer::new(def, seed)).collect(),
+            layers_warm: false,
+            sky: Sky::new(seed),
+            theme: Theme::builtin(),
+            theme_toast: 0.0,
+            seed,
 
-            cloud_spawn_timer: RepeatTimer::new(1.0),
+            camera: Camera::new(CameraSettings::default(), seed),
+            near_miss: false,
+            combo: 0,
+            danger: 0.0,
 
             score_lerp_timer: 0.0,
 
+            particles: ParticleSystem::new(512, seed),
+            trail: Emitter::new(EmitterKind::Trail(60.0), TRAIL, PI),
+            flip_burst: Emitter::new(EmitterKind::Burst(12), FLIP, 0.0),
+            pickup_burst: Emitter::new(EmitterKind::Burst(24), PICKUP, 0.0),
+            death_burst: Emitter::new(EmitterKind::Burst(64), DEATH, 0.0),
+
             tutorial_phase: 0,
 
             dead: false,
+
+            events: EventQueue::default(),
+            milestone: 0,
+
+            rebind: None,
+            new_bindings: None,
+
+            high_scores: HighScores::default(),
+            new_scores: None,
+            name_field: None,
+            name_asked: false,
+            clipboard: Box::new(SystemClipboard),
+
+            sim_acc: 0.0,
+            pending_flips: Vec::new(),
+            clearance: f32::INFINITY,
+
+            history: VecDeque::new(),
+            history_steps: 0,
+            rewind_acc: 0.0,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            player_position: self.player_position,
+            player_velocidad: self.player_velocidad,
+            grav_dir: self.grav_dir,
+            t: self.t,
+            distance: self.distance,
+            score: self.score,
+            wall_sequence: self.wall_sequence.clone(),
+            wall_spawn_timer: self.wall_spawn_timer.clone(),
+            walls: self.walls.clone(),
+            pickups: self.pickups.clone(),
+            combo: self.combo,
+            milestone: self.milestone,
+            near_miss: self.near_miss,
+            clearance: self.clearance,
         }
     }
+
+    fn restore(&mut self, s: Snapshot) {
+        self.player_position = s.player_position;
+        self.player_velocidad = s.player_velocidad;
+        self.grav_dir = s.grav_dir;
+        self.t = s.t;
+        self.distance = s.distance;
+        self.score = s.score;
+        self.wall_sequence = s.wall_sequence;
+        self.wall_spawn_timer = s.wall_spawn_timer;
+        self.walls = s.walls;
+        self.pickups = s.pickups;
+        self.combo = s.combo;
+        self.milestone = s.milestone;
+        self.near_miss = s.near_miss;
+        self.clearance = s.clearance;
+    }
     
-    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas) {
-        let gravity = 1.8;
-        let player_x = 0.5;
-        let player_radius = 0.02;
-        
-        let forgive_radius = 0.01;
-        let pickup_radius = 0.02;
-        let pickup_score = 1000.0;
+    // new run but keep the stuff the player picked
+    fn reset(&mut self, seed: u32) {
+        let theme = self.theme.clone();
+        let camera_settings = self.camera.settings;
+        let events = self.events.take();
+        let high_scores = std::mem::take(&mut self.high_scores);
+        let new_scores = self.new_scores.take();
+        let clipboard = std::mem::replace(&mut self.clipboard, Box::new(SystemClipboard));
+        *self = Game::new(seed);
+        self.events = events;
+        self.high_scores = high_scores;
+        self.new_scores = new_scores;
+        self.clipboard = clipboard;
+        self.set_theme(theme);
+        self.camera.settings = camera_settings;
+    }
 
-        let wall_speed = 0.45;
-        let gap_h = 0.4;
-        let wall_w = 0.2;
+    // what happened last frame
+    pub fn events(&self) -> &EventQueue {
+        &self.events
+    }
 
-        let score_time = 1.0;
+    // set when the controls screen closes, for the application to apply and save
+    pub fn take_bindings(&mut self) -> Option<Bindings> {
+        self.new_bindings.take()
+    }
 
-        let game_dt = if self.paused || self.dead {
-            0.0
-        } else {
-            inputs.dt
-        };
+    // deaths go to the death screen from here on, for tests that want it without flying through walls first
+    #[cfg(test)]
+    pub fn skip_tutorial(&mut self) {
+        self.tutorial_phase = 2;
+    }
 
-        
-        if inputs.just_pressed(VirtualKeyCode::Space) || inputs.lmb == KeyStatus::JustPressed {
-            // self.player_velocidad = -1.0;
-            self.grav_dir *= -1.0;
-            self.player_current_anim_r = 0.007;
+    pub fn high_scores(&self) -> &HighScores {
+        &self.high_scores
+    }
+
+    pub fn set_high_scores(&mut self, scores: HighScores) {
+        self.high_scores = scores;
+    }
+
+    // set when a name goes in the table, for the application to save
+    pub fn take_high_scores(&mut self) -> Option<HighScores> {
+        self.new_scores.take()
+    }
+
+    // pointer hidden while playing, back for menus and the death screen
+    pub fn cursor_mode(&self) -> CursorMode {
+        CursorMode { visible: self.paused || self.dead || self.rebind.is_some(), grab: false }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    // what the music should be doing
+    pub fn mood(&self) -> Mood {
+        Mood {
+            seed: self.seed,
+            speed: 1.0 + (self.distance / MOOD_RAMP_DISTANCE).min(1.0),
+            combo: self.combo,
+            danger: self.danger,
+            paused: self.paused,
+            dead: self.dead,
         }
-        self.player_current_anim_r = 0.0f32.max(self.player_current_anim_r - 0.05*game_dt as f32);
-        // self.player_current_anim_r *= 5.0 * game_dt as f32;
+    }
 
-        // if self.grav_dir < 0.0 {
-        //     kc.flip_y_h = Some(inputs.screen_rect.h);
-        // } else {
-        //     kc.flip_y_h = None;
-        // }
-        
-        self.t += game_dt;
-        self.score += game_dt * 100.0;
-        
-        if !self.paused && !self.dead {
-            self.player_velocidad += gravity * game_dt as f32 * self.grav_dir;
-            self.player_position += self.player_velocidad * game_dt as f32;
-            for wall in self.walls.iter_mut() {
-                wall.x -= wall_speed * game_dt as f32;
+    fn anim_events(&mut self, events: &[String]) {
+        for e in events {
+            if e == "pulse" {
+                self.player_current_anim_r = 0.007;
             }
-            for pickup in self.pickups.iter_mut() {
-                pickup.x -= wall_speed * game_dt as f32;
+        }
+    }
+
+    // the games own reactions to this frames events
+    fn react(&mut self, player_pos: Vec2) {
+        let events: Vec<GameEvent> = self.events.iter().copied().collect();
+        for e in events {
+            match e {
+                GameEvent::Flipped { grav_dir } => {
+                    // kick out the opposite way to the new gravity
+                    self.flip_burst.dir = if grav_dir > 0.0 {-PI/2.0} else {PI/2.0};
+                    self.flip_burst.fire(&mut self.particles, player_pos);
+                },
+                GameEvent::PickupCollected { pos, .. } => {
+                    self.pickup_burst.fire(&mut self.particles, pos);
+                    self.camera.punch_zoom(0.06);
+                    self.combo += 1;
+                },
+                GameEvent::Died { .. } => {
+                    self.death_burst.fire(&mut self.particles, player_pos);
+                    self.camera.add_trauma(0.8);
+                },
+                GameEvent::NearMiss => self.camera.add_trauma(0.3),
+                GameEvent::WallPassed | GameEvent::ScoreMilestone(_) | GameEvent::ThemeChanged => {},
             }
+        }
+    }
 
-            // spawn clouds
-            if self.cloud_spawn_timer.tick(game_dt) {
-                if chance(inputs.seed * 1295497987, 0.1) {
-                    self.clouds_near.push((inputs.seed * 982894397, inputs.screen_rect.right() + 0.2));
-                }
-                if chance(inputs.seed * 35873457, 0.15) {
-                    self.clouds_mid.push((inputs.seed * 3842348749, inputs.screen_rect.right() + 0.2));
-                }
-                if chance(inputs.seed * 576345763, 0.2) {
-                    self.clouds_far.push((inputs.seed * 934697577, inputs.screen_rect.right() + 0.2));
-                }
+    // covers whatever of the window isnt playfield, so nothing out there can be
+    // seen early. dimmed sky with the themes pattern over it
+    fn draw_bars(&self, kc: &mut KRCanvas, inputs: &FrameInputState, sky_state: &SkyState) {
+        let w = inputs.window_rect;
+        let p = inputs.screen_rect;
+        let bars = [
+            Rect::new(w.x, w.y, p.x - w.x, w.h),
+            Rect::new(p.right(), w.y, w.right() - p.right(), w.h),
+            Rect::new(p.x, w.y, p.w, p.y - w.y),
+            Rect::new(p.x, p.bot(), p.w, w.bot() - p.bot()),
+        ];
+        for bar in bars.iter().filter(|b| b.w > 0.0 && b.h > 0.0) {
+            kc.set_layer(RenderLayer::Bars);
+            kc.grad_rect_ud(*bar, sky_state.sky_top, sky_state.sky_bot);
+            self.theme.bars.draw(kc, *bar, *bar);
+        }
+    }
 
-            }
+    fn flip(&mut self) {
+        self.grav_dir *= -1.0;
+        self.events.push(GameEvent::Flipped { grav_dir: self.grav_dir });
+    }
 
-            // move clouds
-            for i in 0..self.clouds_near.len() {
-                let (seed, pos) = self.clouds_near[i];
-                self.clouds_near[i] = (seed, pos - game_dt as f32 * 0.1);
-            }
-            for i in 0..self.clouds_mid.len() {
-                let (seed, pos) = self.clouds_mid[i];
-                self.clouds_mid[i] = (seed, pos - game_dt as f32 * 0.05);
-            }
-            for i in 0..self.clouds_far.len() {
-                let (seed, pos) = self.clouds_far[i];
-                self.clouds_far[i] = (seed, pos - game_dt as f32 * 0.025);
+    pub fn player_pos(&self) -> Vec2 {
+        Vec2::new(PLAYER_X, self.player_position)
+    }
+
+    // one SIM_DT of physics
+    fn step(&mut self, inputs: &FrameInputState) {
+        let dt = SIM_DT as f32;
+        let wall_speed = BASE_WALL_SPEED;
+
+        self.t += SIM_DT;
+        self.distance += wall_speed * dt;
+        self.score += SIM_DT * 100.0;
+
+        self.player_velocidad += GRAVITY * dt * self.grav_dir;
+        self.player_position += self.player_velocidad * dt;
+        for wall in self.walls.iter_mut() {
+            let was_ahead = wall.right() >= PLAYER_X;
+            wall.x -= wall_speed * dt;
+            // top halves only so each wall counts once
+            if was_ahead && wall.right() < PLAYER_X && wall.y < 0.0 {
+                self.events.push(GameEvent::WallPassed);
+                self.tutorial_phase += 1;
             }
         }
+        for pickup in self.pickups.iter_mut() {
+            pickup.x -= wall_speed * dt;
+        }
 
-
-        if self.wall_spawn_timer.tick(game_dt) {
+        if self.wall_spawn_timer.tick(SIM_DT) {
             // let gap_h = kuniform(self.wall_sequence.peek() * 13912417, 0.5, 0.3);
-            let h = kuniform(self.wall_sequence.sample(), 0.0, inputs.screen_rect.bot() - gap_h);
-            self.walls.push(Rect::new(inputs.screen_rect.right(), -10.0, wall_w, 10.0 + h));
-            self.walls.push(Rect::new(inputs.screen_rect.right(), h + gap_h, wall_w, 10.4));
+            let h = kuniform(self.wall_sequence.sample(), 0.0, inputs.screen_rect.bot() - GAP_H);
+            self.walls.push(Rect::new(inputs.screen_rect.right(), -10.0, WALL_W, 10.0 + h));
+            self.walls.push(Rect::new(inputs.screen_rect.right(), h + GAP_H, WALL_W, 10.4));
             
             let halfway = ((self.wall_spawn_timer.period / 2.0) * wall_speed as f64) as f32;
-            if chance(self.wall_sequence.peek() * 3458793547, 0.5) {
+            if chance(self.wall_sequence.peek().wrapping_mul(3458793547), 0.5) {
                 // place a pickup
-                let h =  if chance(inputs.seed * 123891, 0.5) {inputs.screen_rect.top() + 0.2} else {inputs.screen_rect.bot() - 0.2};
-                let new_pickup = Vec2::new(inputs.screen_rect.right() + pickup_radius + halfway + wall_w/2.0, h);
+                let h =  if chance(inputs.seed.wrapping_mul(123891), 0.5) {inputs.screen_rect.top() + 0.2} else {inputs.screen_rect.bot() - 0.2};
+                let new_pickup = Vec2::new(inputs.screen_rect.right() + PICKUP_RADIUS + halfway + WALL_W/2.0, h);
                 self.pickups.push(new_pickup);
             } else {
                 // place an intermediate wall
-                if chance(self.wall_sequence.peek() * 548965757, 0.1) {
-                    let next_h = kuniform(self.wall_sequence.peek(), 0.0, inputs.screen_rect.bot() - gap_h);
+                if chance(self.wall_sequence.peek().wrapping_mul(548965757), 0.1) {
+                    let next_h = kuniform(self.wall_sequence.peek(), 0.0, inputs.screen_rect.bot() - GAP_H);
                     let h = (h + next_h)/2.0;
-                    self.walls.push(Rect::new(inputs.screen_rect.right() + halfway, -10.0, wall_w, 10.0 + h));
-                    self.walls.push(Rect::new(inputs.screen_rect.right() + halfway, h + gap_h, wall_w, 10.4));
+                    self.walls.push(Rect::new(inputs.screen_rect.right() + halfway, -10.0, WALL_W, 10.0 + h));
+                    self.walls.push(Rect::new(inputs.screen_rect.right() + halfway, h + GAP_H, WALL_W, 10.4));
                 }
             }
         }
 
-
-
         // player collides with walls
-        let player_pos = Vec2::new(player_x, self.player_position);
+        let player_pos = self.player_pos();
+        let mut clearance = f32::INFINITY;
+        let mut cause = None;
         for wall in self.walls.iter() {
             let closest_point = wall.snap(player_pos);
-            let penetration = player_radius - (closest_point - player_pos).magnitude();
+            let penetration = PLAYER_RADIUS - (closest_point - player_pos).magnitude();
             if penetration > 0.0 {
                 self.dead = true;
+                cause = Some(DeathCause::Wall);
             }
+            clearance = clearance.min(-penetration);
+        }
+        if !self.dead && clearance < NEAR_MISS_DIST && !self.near_miss {
+            self.near_miss = true;
+            self.events.push(GameEvent::NearMiss);
+        } else if clearance > NEAR_MISS_DIST * 2.0 {
+            self.near_miss = false;
         }
+        self.clearance = clearance;
         
-        if self.player_position < inputs.screen_rect.top() - player_radius - forgive_radius || self.player_position > inputs.screen_rect.bot() + player_radius + forgive_radius {
+        if self.player_position < inputs.screen_rect.top() - PLAYER_RADIUS - FORGIVE_RADIUS || self.player_position > inputs.screen_rect.bot() + PLAYER_RADIUS + FORGIVE_RADIUS {
             self.dead = true;
+            cause = cause.or(Some(DeathCause::OutOfBounds));
         }
 
         let mut i = self.pickups.len();
         while i > 0 {
             i = i - 1;
-            if self.pickups[i].dist(player_pos) < player_radius + pickup_radius + forgive_radius {
-                self.score += pickup_score;
+            if self.pickups[i].dist(player_pos) < PLAYER_RADIUS + PICKUP_RADIUS + FORGIVE_RADIUS {
+                self.score += PICKUP_SCORE;
+                self.events.push(GameEvent::PickupCollected { kind: PickupKind::Score, pos: self.pickups[i] });
                 self.pickups.swap_remove(i);
             } else {
-                if self.pickups[i].x - pickup_radius < 0.0 {
+                if self.pickups[i].x - PICKUP_RADIUS < 0.0 {
+                    // missed one
                     self.pickups.swap_remove(i);
+                    self.combo = 0;
                 }
             }
         }
         
         self.walls.retain(|w| w.right() > 0.0);
 
-        // bg
-        let (sky, ocean) = inputs.screen_rect.split_ud(0.7);
-        kc.set_camera(inputs.screen_rect);
-        kc.set_depth(1.0);
-        let col_top = Vec4::new(0.2, 0.2, 0.8, 1.0);
-        // let col_bot = Vec4::new(0.3, 0.3, 0.7, 1.0);
-        let col_bot = Vec4::new(0.3, 0.3, 1.0, 1.0);
+        if let (true, Some(cause)) = (self.dead, cause) {
+            self.events.push(GameEvent::Died { cause });
+        }
+    }
 
-        kc.grad_rect_ud(sky, col_top, col_bot);
-        
-        kc.set_depth(1.05);
-        let col_far = Vec4::new(0.2, 0.2, 0.55, 1.0);
-        let col_near = Vec4::new(0.2, 0.2, 0.65, 1.0);
-        kc.grad_rect_ud(ocean, col_far, col_near);
-
-        // clouds
-        kc.set_depth(1.1);
-        kc.set_colour(Vec4::new(0.6, 0.6, 0.7, 1.0));
-        for (seed, xpos) in &self.clouds_far {
-            kc.cloud(Rect::new(*xpos, 0.6, 0.1, 0.05), *seed)            
-        }
-        kc.set_depth(1.2);
-        kc.set_colour(Vec4::new(0.65, 0.65, 0.75, 1.0));
-        for (seed, xpos) in &self.clouds_mid {
-            kc.cloud(Rect::new(*xpos, 0.533, 0.15, 0.07), *seed)            
-        }
-        kc.set_depth(1.3);
-        kc.set_colour(Vec4::new(0.7, 0.7, 0.8, 1.0));
-        for (seed, xpos) in &self.clouds_near {
-            kc.cloud(Rect::new(*xpos, 0.467, 0.2, 0.09), *seed)            
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.sky = Sky::new_with_keys(self.seed, theme.sky_keys.clone());
+        self.theme = theme;
+    }
+
+    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas) {
+        self.update(inputs);
+        self.draw(inputs, kc);
+    }
+
+    // everything but drawing, tests and tools that dont need the picture can just call this
+    pub fn update(&mut self, inputs: &FrameInputState) {
+        // losing focus pauses and getting it back carries on, its in the inputs so replays see it too
+        if let Some(focused) = inputs.focus {
+            self.paused = !focused;
+        }
+        let game_dt = if self.paused || self.dead || self.rebind.is_some() {
+            0.0
+        } else {
+            inputs.dt
+        };
+        self.events.clear();
+
+        let menu_open = self.rebind.is_some();
+        // keys are the name while its up
+        let typing = self.name_field.is_some();
+        if let Some(screen) = self.rebind.as_mut() {
+            if let Some(bindings) = screen.update(inputs, rebind_rect(inputs)) {
+                self.new_bindings = Some(bindings);
+                self.rebind = None;
+                self.paused = false;
+            }
+        } else if !typing && inputs.action_just_pressed(Action::Rebind) {
+            self.rebind = Some(RebindScreen::new((*inputs.bindings).clone()));
+            self.paused = true;
+        } else if inputs.action_just_pressed(Action::Pause) && !self.dead {
+            self.paused = !self.paused;
+        }
+
+        // held, plays the last few seconds backwards instead of stepping
+        let rewinding = !menu_open && game_dt > 0.0 && inputs.action_pressed(Action::Rewind);
+
+        if !menu_open && !typing && !rewinding {
+            for t in inputs.presses(Action::Flip) {
+                let events = self.player_anim.play("flip");
+                self.anim_events(&events);
+                if game_dt > 0.0 {
+                    self.pending_flips.push(t);
+                } else {
+                    // self.player_velocidad = -1.0;
+                    self.grav_dir *= -1.0;
+                }
+            }
+        }
+        if !menu_open && !typing && inputs.action_just_pressed(Action::NextTheme) {
+            let next = Theme::next_after(&self.theme.name);
+            self.set_theme(next);
+            self.theme_toast = 2.0;
+            self.events.push(GameEvent::ThemeChanged);
+        }
+        self.theme_toast = (self.theme_toast - inputs.dt as f32).max(0.0);
+
+        self.player_current_anim_r = 0.0f32.max(self.player_current_anim_r - 0.05*game_dt as f32);
+        self.player_anim.speed = 1.0 + self.player_velocidad.abs();
+        let events = self.player_anim.update(game_dt as f32);
+        self.anim_events(&events);
+        if self.player_anim.playing() == Some("flip") && self.player_anim.finished() {
+            self.player_anim.play("roll");
+        }
+        // self.player_current_anim_r *= 5.0 * game_dt as f32;
+
+        // steps cover the input clock from where the last frame left off, a
+        // flip goes in at the start of the step it was pressed during
+        let mut step_start = inputs.t - inputs.dt - self.sim_acc;
+        if rewinding {
+            self.rewind_acc += game_dt;
+            let interval = REWIND_STEPS as f64 * SIM_DT;
+            while self.rewind_acc >= interval {
+                self.rewind_acc -= interval;
+                if let Some(s) = self.history.pop_back() {
+                    self.restore(s);
+                }
+            }
+            self.history_steps = 0;
+        } else {
+            self.rewind_acc = 0.0;
+            self.sim_acc += game_dt;
+        }
+        while self.sim_acc >= SIM_DT && !self.dead {
+            let step_end = step_start + SIM_DT;
+            let flips = self.pending_flips.iter().filter(|t| **t < step_end).count();
+            self.pending_flips.retain(|t| *t >= step_end);
+            for _ in 0..flips {
+                self.flip();
+            }
+            self.step(inputs);
+            self.sim_acc -= SIM_DT;
+            step_start = step_end;
+
+            self.history_steps += 1;
+            if self.history_steps == REWIND_STEPS {
+                self.history_steps = 0;
+                if self.history.len() == REWIND_KEEP {
+                    self.history.pop_front();
+                }
+                self.history.push_back(self.snapshot());
+            }
+        }
+        if game_dt == 0.0 || self.dead || rewinding {
+            self.sim_acc = 0.0;
+            self.pending_flips.clear();
+        }
+
+        self.camera.set_flipped(self.grav_dir < 0.0);
+
+        if !self.paused && !self.dead {
+            for layer in self.layers.iter_mut() {
+                layer.update(game_dt as f32, inputs.window_rect);
+            }
+        }
+
+        let player_pos = self.player_pos();
+        let danger = 1.0 - (self.clearance / 0.15).clamp(0.0, 1.0);
+        self.danger += (danger - self.danger) * (game_dt as f32 * 4.0).min(1.0);
+
+        let milestone = (self.score / SCORE_MILESTONE) as u32;
+        if milestone > self.milestone && !self.dead {
+            self.milestone = milestone;
+            self.events.push(GameEvent::ScoreMilestone(milestone * SCORE_MILESTONE as u32));
+        }
+        self.react(player_pos);
+
+        // particles keep going after death so the explosion plays out
+        let particle_dt = if self.paused {0.0} else {inputs.dt as f32};
+        // trail gets dragged back at wall speed so it reads as motion
+        let mut trail_col = self.theme.player_colour(self.player_velocidad.abs() * 0.6);
+        trail_col.w = 0.6;
+        self.trail.style.colour_start = trail_col;
+        trail_col.w = 0.0;
+        self.trail.style.colour_end = trail_col;
+        self.trail.style.speed_min = BASE_WALL_SPEED;
+        self.trail.style.speed_max = BASE_WALL_SPEED;
+        self.trail.active = !self.dead;
+        self.trail.tick(&mut self.particles, player_pos, game_dt as f32);
+        self.particles.update(particle_dt);
+
+        self.camera.follow(inputs.window_rect, player_pos, particle_dt);
+        self.camera.update(particle_dt);
+
+        if self.dead {
+            self.score_lerp_timer = (self.score_lerp_timer + inputs.dt as f32).min(SCORE_LERP_TIME);
+            let score = self.score as u32;
+            if self.score_lerp_timer >= SCORE_LERP_TIME && !self.name_asked {
+                self.name_asked = true;
+                if self.high_scores.qualifies(score) {
+                    self.name_field = Some(TextField::new(NAME_LEN));
+                }
+            }
+            if let Some(field) = self.name_field.as_mut() {
+                if field.update(inputs, self.clipboard.as_mut()) {
+                    self.high_scores.insert(field.text(), score);
+                    self.new_scores = Some(self.high_scores.clone());
+                    self.name_field = None;
+                } else if inputs.action_just_pressed(Action::Back) {
+                    self.name_field = None;
+                }
+            }
+            // a pad or a tap still restarts while typing, space or return from the keyboard dont
+            let restart_keys = inputs.bindings.get(Action::Restart);
+            let key_restart = typing && inputs.edges.iter().any(|e| e.pressed && matches!(e.input, Input::Key(_)) && restart_keys.contains(&e.input));
+            if self.score_lerp_timer >= SCORE_LERP_TIME && !menu_open && !key_restart && inputs.action_just_pressed(Action::Restart) {
+                self.reset(inputs.seed);
+            }
+        }
+        if self.dead && self.tutorial_phase < 2 {
+            self.reset(inputs.seed);
+        }
+    }
+
+    pub fn draw(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas) {
+        let player_pos = self.player_pos();
+        self.camera.apply(kc, inputs.window_rect);
+
+        // bg fills the whole window, bleeds past it so shake and the flip dont show the edges.
+        // horizon stays put relative to the playfield
+        let bg_margin = 0.15;
+        let bg = inputs.window_rect.dilate(bg_margin);
+        let (sky, ocean) = bg.split_ud((0.7 * inputs.screen_rect.h - bg.y) / bg.h);
+        if !self.layers_warm {
+            for layer in self.layers.iter_mut() {
+                layer.prewarm(inputs.window_rect);
+            }
+            self.layers_warm = true;
+        }
+        let sky_state = self.sky.sample(self.distance);
+        self.sky.draw_sky(kc, &sky_state, sky, self.t as f32);
+        self.sky.draw_ocean(kc, &sky_state, sky, ocean, self.t as f32);
+        if self.theme.ocean.pattern != Pattern::Solid {
+            kc.set_layer(RenderLayer::Ocean);
+            self.theme.ocean.draw(kc, ocean, ocean);
+        }
+
+        // parallax layers
+        let layer_tint = sky_state.layer_tint.mul_elem(self.theme.cloud_tint);
+        for layer in self.layers.iter() {
+            layer.draw(kc, layer_tint, self.theme.cloud_style);
         }
         
+        kc.set_layer(RenderLayer::Particles);
+        self.particles.draw(kc);
+
         // player
-        kc.set_depth(1.5);
-        kc.set_colour(Vec4::new(0.0, 0.9, 0.9, 1.0));
-
-        let r = (player_radius + forgive_radius) * 0.9;
-        if self.grav_dir > 0.0 {
-            kc.triangle(
-                r_theta_vec(r, PI/2.0, player_pos),
-                r_theta_vec(r, PI/2.0 + 2.0*PI/3.0, player_pos),
-                r_theta_vec(r, PI/2.0 + 4.0*PI/3.0, player_pos),
-            );
+        kc.set_layer(RenderLayer::Player);
+        let r = (PLAYER_RADIUS + FORGIVE_RADIUS) * 0.9;
+        let ball_r = PLAYER_RADIUS + FORGIVE_RADIUS + self.player_current_anim_r;
+        let ball_rect = Rect::new_centered(player_pos.x, player_pos.y, ball_r * 2.0, ball_r * 2.0);
+        let arrow_opts = SpriteOpts { flip_y: self.grav_dir < 0.0, tint: self.theme.player, ..Default::default() };
+        let ball_opts = SpriteOpts { tint: self.theme.player_colour(self.player_velocidad.abs() * 0.6), ..Default::default() };
+        let frame = self.player_anim.frame().unwrap_or("");
+        if kc.atlas.regions.contains_key("arrow") && kc.atlas.regions.contains_key(frame) {
+            kc.sprite_ex(frame, ball_rect, &ball_opts);
+            kc.sprite_ex("arrow", ball_rect, &arrow_opts);
         } else {
-            kc.triangle(
-                r_theta_vec(r, PI + PI/2.0, player_pos),
-                r_theta_vec(r, PI + PI/2.0 + 2.0*PI/3.0, player_pos),
-                r_theta_vec(r, PI + PI/2.0 + 4.0*PI/3.0, player_pos),
-            );
+            // no sprites/, the old circle with a triangle on it
+            kc.set_colour(self.theme.player_colour(self.player_velocidad.abs() * 0.6));
+            kc.circle(player_pos, ball_r);
+            kc.set_colour(self.theme.player);
+            if self.grav_dir > 0.0 {
+                kc.triangle(
+                    r_theta_vec(r, PI/2.0, player_pos),
+                    r_theta_vec(r, PI/2.0 + 2.0*PI/3.0, player_pos),
+                    r_theta_vec(r, PI/2.0 + 4.0*PI/3.0, player_pos),
+                );
+            } else {
+                kc.triangle(
+                    r_theta_vec(r, PI + PI/2.0, player_pos),
+                    r_theta_vec(r, PI + PI/2.0 + 2.0*PI/3.0, player_pos),
+                    r_theta_vec(r, PI + PI/2.0 + 4.0*PI/3.0, player_pos),
+                );
+            }
         }
-        let r = self.player_velocidad.abs() * 0.6;
-        kc.set_colour(Vec4::new(r, 0.0, 1.0 - r, 1.0));
-        kc.circle(player_pos, player_radius + forgive_radius + self.player_current_anim_r);
 
         // walls
-        kc.set_colour(Vec4::new(0.4, 0.0, 0.0, 1.0));
+        kc.set_layer(RenderLayer::Walls);
         for wall in self.walls.iter() {
-            kc.rect(*wall);
+            self.theme.wall.draw(kc, *wall, bg);
         }
         
         //     let (l, r) = wall.split_lr(0.5);
@@ -326,53 +778,203 @@ impl Game {
 
         // }
         // pickups
-        kc.set_colour(Vec4::new(0.8, 0.0, 0.0, 1.0));
+        kc.set_layer(RenderLayer::Pickups);
+        kc.set_colour(self.theme.pickup);
         for pickup in self.pickups.iter() {
             kc.circle(*pickup, 0.02);
         }
 
-        // paused overlay
+        // hud doesnt move with the camera
+        kc.set_camera(inputs.window_rect);
+        kc.reset_view_transform();
+
+        self.draw_bars(kc, inputs, &sky_state);
+
+        // paused overlay, with a play sign in the middle. stroking it round as well rounds the corners off
         if self.paused {
-            kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 0.5));
-            kc.set_depth(10.0);
-            kc.rect(inputs.screen_rect);
+            kc.set_colour(self.theme.pause_overlay);
+            kc.set_layer(RenderLayer::Overlay);
+            kc.rect(inputs.window_rect);
+            let c = inputs.screen_rect.centroid();
+            let s = inputs.screen_rect.h * 0.06;
+            let play = [c + Vec2::new(-s * 0.7, -s), c + Vec2::new(s, 0.0), c + Vec2::new(-s * 0.7, s)];
+            kc.set_layer(RenderLayer::Hud);
+            kc.set_colour(self.theme.text);
+            kc.fill_poly(&play);
+            kc.stroke(&play, &StrokeStyle::new(s * 0.3).join(LineJoin::Round).closed());
+        }
+
+        if let Some(screen) = &self.rebind {
+            screen.draw(kc, rebind_rect(inputs), self.theme.text);
         }
 
         // text + control flow
 
-        kc.set_depth(2.0);
-        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        kc.set_layer(RenderLayer::Hud);
+        kc.set_colour(self.theme.text);
+        kc.set_font(&self.theme.font);
+
+        if self.theme_toast > 0.0 {
+            let r = inputs.screen_rect.child(0.0, 0.93, 1.0, 0.04);
+            let label = format!("theme: {}", self.theme.name);
+            // pill behind it that fades out with the toast
+            let size = measure(&label, None, &TextStyle::new(r.h), kc.metrics());
+            let c = r.centroid();
+            kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 0.4 * self.theme_toast.min(1.0)));
+            kc.rounded_rect(Rect::new_centered(c.x, c.y, size.x + r.h, r.h * 1.6), r.h * 0.8);
+            kc.set_colour(self.theme.text);
+            kc.text_center(label.as_bytes(), r);
+        }
 
         let alive_score_rect = inputs.screen_rect.child(0.0, 0.0, 1.0, 0.05);
         let dead_score_rect = inputs.screen_rect.child(0.0, 0.4, 1.0, 0.2);
         if !self.dead {
             let sr = inputs.screen_rect.child(0.0, 0.0, 1.0, 0.05);
-            kc.text_center(format!("{:.0}", self.score).as_bytes(), sr);
+            let score = format!("{:.0}", self.score);
+            kc.text_center(score.as_bytes(), sr);
+
+            // ring next to the score filling up towards the next milestone
+            let size = measure(&score, None, &TextStyle::new(sr.h), kc.metrics());
+            let rad = sr.h * 0.3;
+            let c = sr.centroid() + Vec2::new(size.x / 2.0 + rad * 2.0, 0.0);
+            let progress = (self.score % SCORE_MILESTONE / SCORE_MILESTONE) as f32;
+            let t = self.theme.text;
+            kc.set_colour(Vec4::new(t.x, t.y, t.z, t.w * 0.25));
+            kc.pie(c, rad, 0.0, 2.0 * PI);
+            kc.set_colour(t);
+            if progress > 0.0 {
+                kc.arc(c, rad, -PI/2.0, -PI/2.0 + 2.0 * PI * progress, &StrokeStyle::new(rad * 0.35).cap(LineCap::Round));
+            }
         } else {
-            self.score_lerp_timer += inputs.dt as f32;
             let mut text_rect = inputs.screen_rect.dilate_pc(-0.2);
             text_rect.y += 0.2;
             
-            if self.score_lerp_timer/score_time > 1.0 {
-                self.score_lerp_timer = 1.0*score_time;
-                kc.text_center("You died, press space to reset".as_bytes(), text_rect); // bug ???
-                if inputs.just_pressed(VirtualKeyCode::Space) {
-                    *self = Game::new(inputs.seed);
-                }
+            let style = TextStyle::new(0.06).align(HAlign::Centre, VAlign::Middle);
+            if let Some(field) = self.name_field.as_ref() {
+                let skip = inputs.bindings.get(Action::Back).first().map(|i| i.name()).unwrap_or_else(|| "back".to_owned());
+                kc.text(&format!("New high score\ntype your name, {} to skip", skip), text_rect, &style);
+                let r = Rect::new_centered(inputs.screen_rect.centroid().x, text_rect.bot() - 0.1, 0.5, 0.07);
+                kc.text_field(field, r, self.theme.text, inputs.t);
+            } else if self.score_lerp_timer >= SCORE_LERP_TIME {
+                let restart = inputs.bindings.get(Action::Restart).first().map(|i| i.name()).unwrap_or_else(|| "restart".to_owned());
+                kc.text(&format!("You died\npress {} to reset", restart), text_rect, &style);
             }
-            let sr = alive_score_rect.lerp(dead_score_rect, self.score_lerp_timer/score_time);
+            let sr = alive_score_rect.lerp(dead_score_rect, self.score_lerp_timer/SCORE_LERP_TIME);
             kc.text_center(format!("{:.0}", self.score).as_bytes(), sr);
             
         }
-
-        if self.dead && self.tutorial_phase < 2 {
-            *self = Game::new(inputs.seed);
-        }
     }
 }
 
 // fade score in death screen
 
+fn rebind_rect(inputs: &FrameInputState) -> Rect {
+    inputs.screen_rect.dilate_pc(-0.1)
+}
+
 pub fn r_theta_vec(r: f32, theta: f32, orig: Vec2) -> Vec2 {
     Vec2 { x: orig.x + r * theta.cos(), y: orig.y + r * theta.sin() }
-}
\ No newline at end of file
+}
+#[test]
+pub fn test_sub_frame_flip() {
+    use crate::replay::*;
+    use crate::lib::kpad::*;
+
+    let step = 1.0 / 60.0;
+    let run = |frames: &[RecordedFrame]| {
+        let mut game = Game::new(5);
+        let mut t = 0.0;
+        for (i, f) in frames.iter().enumerate() {
+            t += f.dt;
+            game.frame(&f.to_inputs(i as u32, t), &mut KRCanvas::new());
+        }
+        assert!(!game.dead);
+        game.player_position
+    };
+    let frame = |dt, flip_at: Option<f64>| RecordedFrame {
+        dt,
+        seed: 0,
+        actions: if flip_at.is_some() {vec![(Action::Flip, KeyStatus::JustPressed)]} else {vec![]},
+        edges: flip_at.map(|t| ActionEdge { action: Action::Flip, pressed: true, t }).into_iter().collect(),
+        ..Default::default()
+    };
+
+    // pressed two and a half steps into frame 10
+    let offset = 2.5 * SIM_DT;
+    let mut precise: Vec<RecordedFrame> = (0..20).map(|_| frame(step, None)).collect();
+    precise[10] = frame(step, Some(offset));
+    // same thing with frame 10 cut in two where the press was, press at the start of the second half
+    let mut split = precise.clone();
+    split[10] = frame(offset, None);
+    split.insert(11, frame(step - offset, Some(0.0)));
+    // what it used to do, flip at the frame boundary
+    let mut late = precise.clone();
+    late[10] = frame(step, Some(0.0));
+
+    let (p, s, l) = (run(&precise), run(&split), run(&late));
+    assert!((p - s).abs() < 1e-5, "{} {}", p, s);
+    assert!((p - l).abs() > 1e-4, "{} {}", p, l);
+
+    // no timing in the inputs counts as the start of the frame
+    let mut untimed = late.clone();
+    untimed[10].edges.clear();
+    assert!((run(&untimed) - l).abs() < 1e-6);
+
+    // a pad press gets polled at the end of the frame but still flips in it
+    let mock = MockPads::new();
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, PLAYFIELD_ASPECT);
+    ea.set_pad_backend(Box::new(mock.clone()));
+    let t0 = std::time::Instant::now();
+    ea.handle_event_at(&glutin::event::Event::MainEventsCleared, t0);
+    mock.press(PadId(0), PadButton::South, true);
+    let inputs = ea.handle_event_at(&glutin::event::Event::MainEventsCleared, t0 + std::time::Duration::from_millis(16)).unwrap();
+    let mut game = Game::new(5);
+    game.update(&inputs);
+    assert_eq!(game.events().count(|e| matches!(e, GameEvent::Flipped { .. })), 1);
+
+}
+
+#[test]
+pub fn test_rewind() {
+    use crate::replay::*;
+
+    let step = 1.0 / 60.0;
+    let frame = RecordedFrame { dt: step, ..Default::default() };
+    // holding rewind for a quarter second goes back to where it was about a quarter second ago,
+    // near miss state included so going back over one doesnt lose it or count it twice
+    let mut game = Game::new(5);
+    let mut t = 0.0;
+    let mut before = Vec::new();
+    for i in 0..45 {
+        t += step;
+        game.update(&frame.to_inputs(i, t));
+        before.push((game.t, game.player_position, game.walls.clone(), game.near_miss, game.clearance));
+    }
+    let held = RecordedFrame { actions: vec![(Action::Rewind, KeyStatus::Pressed)], ..frame.clone() };
+    for i in 45..60 {
+        t += step;
+        game.update(&held.to_inputs(i, t));
+    }
+    assert!((game.t - 0.5).abs() < 0.05, "{}", game.t);
+    let then = before.iter().find(|b| b.0 == game.t).unwrap();
+    assert_eq!((then.1, &then.2, then.3, then.4), (game.player_position, &game.walls, game.near_miss, game.clearance));
+    assert!(!game.dead);
+}
+
+#[test]
+pub fn test_mood() {
+    use crate::replay::*;
+
+    // the music picks up with distance, the walls keep going the same speed
+    let mut game = Game::new(5);
+    assert_eq!(game.mood().speed, 1.0);
+    let inputs = RecordedFrame { dt: 1.0 / 60.0, ..Default::default() }.to_inputs(0, 1.0 / 60.0);
+    game.distance = MOOD_RAMP_DISTANCE / 2.0;
+    game.step(&inputs);
+    assert!((game.mood().speed - 1.5).abs() < 1e-3);
+    let before = game.distance;
+    game.step(&inputs);
+    assert!((game.distance - before - BASE_WALL_SPEED * SIM_DT as f32).abs() < 1e-5);
+    game.distance = MOOD_RAMP_DISTANCE * 10.0;
+    assert_eq!(game.mood().speed, 2.0);
+}
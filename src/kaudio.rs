@@ -1,21 +1,452 @@
-// pub struct Sound {
-//     data: Vec<f32>,
-// }
+use std::io::{self, Write, Seek, SeekFrom};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-// pub struct Mixer {
-//     sample_count: u64,
-//     playing_chunks: Vec<(&'static Sound, u64)>,
-// }
+// Audio. The Mixer owns voices and buses and renders interleaved stereo f32
+// blocks, a Sink takes those blocks somewhere. Time is counted in frames
+// since the mixer started so things can be scheduled to the sample. Audio
+// runs a mixer into a device sink on its own thread.
+//
+// No cpal in the dependency tree so the device sink pipes raw pcm to aplay or
+// pacat. If neither is around the game is just quiet.
 
-// impl Mixer {
-//     pub fn play_immediately(&mut self, s: &'static Sound) {
-//         self.playing_chunks.push((s, self.sample_count));
-//     }
+pub const SAMPLE_RATE: u32 = 44100;
+pub const BLOCK_FRAMES: usize = 512;
+const MAX_VOICES: usize = 64;
 
-//     pub fn next(n: u64) -> Vec<f32> {
+pub fn seconds_to_frames(t: f64) -> u64 {
+    (t * SAMPLE_RATE as f64).round().max(0.0) as u64
+}
 
-//     }
-// }
+// mono sample data, shared between however many voices are playing it
+pub struct Sound {
+    pub data: Vec<f32>,
+}
 
-// // I guess cpal synth example is pretty good and can do what I want probably
-// // a struct and a function called per sample
\ No newline at end of file
+// something a voice can play. fills out (mono) and returns false once its done,
+// anything it didnt fill should be left as zero
+pub trait Source: Send {
+    fn fill(&mut self, out: &mut [f32]) -> bool;
+}
+
+pub struct SoundSource {
+    sound: Arc<Sound>,
+    pos: usize,
+    looping: bool,
+}
+
+impl SoundSource {
+    pub fn new(sound: Arc<Sound>, looping: bool) -> SoundSource {
+        SoundSource { sound, pos: 0, looping }
+    }
+}
+
+impl Source for SoundSource {
+    fn fill(&mut self, out: &mut [f32]) -> bool {
+        let data = &self.sound.data;
+        if data.is_empty() {
+            return false;
+        }
+        for o in out.iter_mut() {
+            if self.pos >= data.len() {
+                if !self.looping {
+                    return false;
+                }
+                self.pos = 0;
+            }
+            *o = data[self.pos];
+            self.pos += 1;
+        }
+        self.looping || self.pos < data.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusId(pub usize);
+
+pub const MASTER: BusId = BusId(0);
+
+pub struct Bus {
+    pub name: String,
+    pub gain: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoiceId(u64);
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoiceParams {
+    pub gain: f32,
+    pub pan: f32,   // -1 left, 1 right
+    pub bus: BusId,
+    pub keep: bool, // never stolen to make room, for music and the like
+}
+
+impl Default for VoiceParams {
+    fn default() -> VoiceParams {
+        VoiceParams { gain: 1.0, pan: 0.0, bus: MASTER, keep: false }
+    }
+}
+
+struct Voice {
+    id: VoiceId,
+    source: Box<dyn Source>,
+    start: u64,
+    params: VoiceParams,
+}
+
+// balance law, centre is unity both sides, hard left is all left
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+pub struct Mixer {
+    now: u64,
+    buses: Vec<Bus>,
+    voices: Vec<Voice>,
+    next_id: u64,
+    scratch: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        Mixer {
+            now: 0,
+            buses: vec![Bus { name: "master".to_owned(), gain: 1.0 }],
+            voices: Vec::new(),
+            next_id: 0,
+            scratch: Vec::new(),
+        }
+    }
+
+    // frames rendered so far, schedule relative to this
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // buses all feed master, master gain goes on top
+    pub fn add_bus(&mut self, name: &str, gain: f32) -> BusId {
+        self.buses.push(Bus { name: name.to_owned(), gain });
+        BusId(self.buses.len() - 1)
+    }
+
+    pub fn bus(&self, name: &str) -> Option<BusId> {
+        self.buses.iter().position(|b| b.name == name).map(BusId)
+    }
+
+    pub fn set_bus_gain(&mut self, bus: BusId, gain: f32) {
+        if let Some(b) = self.buses.get_mut(bus.0) {
+            b.gain = gain;
+        }
+    }
+
+    pub fn play(&mut self, source: Box<dyn Source>, params: VoiceParams) -> VoiceId {
+        self.play_at(source, self.now, params)
+    }
+
+    // frame is absolute mixer time, anything in the past starts straight away.
+    // when its full the oldest voice thats not kept goes, if theyre all kept
+    // the new one doesnt play and its id is never is_playing
+    pub fn play_at(&mut self, source: Box<dyn Source>, frame: u64, params: VoiceParams) -> VoiceId {
+        let id = VoiceId(self.next_id);
+        self.next_id += 1;
+        if self.voices.len() >= MAX_VOICES {
+            match self.voices.iter().position(|v| !v.params.keep) {
+                Some(i) => {
+                    self.voices.remove(i);
+                },
+                None => return id,
+            }
+        }
+        self.voices.push(Voice { id, source, start: frame.max(self.now), params });
+        id
+    }
+
+    pub fn play_sound(&mut self, sound: &Arc<Sound>, params: VoiceParams) -> VoiceId {
+        self.play(Box::new(SoundSource::new(sound.clone(), false)), params)
+    }
+
+    pub fn stop(&mut self, id: VoiceId) {
+        self.voices.retain(|v| v.id != id);
+    }
+
+    pub fn is_playing(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|v| v.id == id)
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    // out is interleaved stereo, gets overwritten
+    pub fn render(&mut self, out: &mut [f32]) {
+        out.iter_mut().for_each(|x| *x = 0.0);
+        let frames = out.len() / 2;
+        let end = self.now + frames as u64;
+        let master = self.buses[0].gain;
+
+        let mut finished = Vec::new();
+        for (vi, v) in self.voices.iter_mut().enumerate() {
+            if v.start >= end {
+                continue;
+            }
+            let offset = (v.start.max(self.now) - self.now) as usize;
+            let n = frames - offset;
+            self.scratch.clear();
+            self.scratch.resize(n, 0.0);
+            if !v.source.fill(&mut self.scratch) {
+                finished.push(vi);
+            }
+            // voices straight on master only get master once
+            let bus_gain = if v.params.bus == MASTER {1.0} else {self.buses.get(v.params.bus.0).map(|b| b.gain).unwrap_or(0.0)};
+            let g = v.params.gain * bus_gain * master;
+            let (gl, gr) = pan_gains(v.params.pan);
+            for (i, s) in self.scratch.iter().enumerate() {
+                out[(offset + i) * 2] += s * g * gl;
+                out[(offset + i) * 2 + 1] += s * g * gr;
+            }
+        }
+        for vi in finished.into_iter().rev() {
+            self.voices.remove(vi);
+        }
+        self.now = end;
+    }
+}
+
+pub trait Sink: Send {
+    // interleaved stereo at SAMPLE_RATE
+    fn write(&mut self, block: &[f32]) -> io::Result<()>;
+}
+
+// throws it away, counts frames
+pub struct NullSink {
+    pub frames: u64,
+}
+
+impl Sink for NullSink {
+    fn write(&mut self, block: &[f32]) -> io::Result<()> {
+        self.frames += (block.len() / 2) as u64;
+        Ok(())
+    }
+}
+
+fn to_i16(x: f32) -> i16 {
+    (x.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn write_pcm16<W: Write>(w: &mut W, block: &[f32]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(block.len() * 2);
+    for x in block {
+        bytes.extend_from_slice(&to_i16(*x).to_le_bytes());
+    }
+    w.write_all(&bytes)
+}
+
+// 16 bit stereo wav, sizes in the header get filled in by finish
+pub struct WavSink<W: Write + Seek> {
+    w: W,
+    frames: u32,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut w: W) -> io::Result<WavSink<W>> {
+        let channels: u16 = 2;
+        let bits: u16 = 16;
+        let block_align = channels * bits / 8;
+        w.write_all(b"RIFF")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;     // pcm
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        Ok(WavSink { w, frames: 0 })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self.frames * 4;
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w.write_all(&(36 + data_len).to_le_bytes())?;
+        self.w.seek(SeekFrom::Start(40))?;
+        self.w.write_all(&data_len.to_le_bytes())?;
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write + Seek + Send> Sink for WavSink<W> {
+    fn write(&mut self, block: &[f32]) -> io::Result<()> {
+        self.frames += (block.len() / 2) as u32;
+        write_pcm16(&mut self.w, block)
+    }
+}
+
+// raw pcm into the stdin of something that plays it
+pub struct ProcessSink {
+    child: Child,
+}
+
+impl ProcessSink {
+    pub fn spawn(cmd: &str, args: &[&str]) -> io::Result<ProcessSink> {
+        let child = Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(ProcessSink { child })
+    }
+
+    // whichever player is installed
+    pub fn default_device() -> Option<ProcessSink> {
+        let rate = SAMPLE_RATE.to_string();
+        let players: [(&str, Vec<&str>); 2] = [
+            ("aplay", vec!["-q", "-t", "raw", "-f", "S16_LE", "-c", "2", "-r", &rate, "-B", "50000"]),
+            ("pacat", vec!["--raw", "--format=s16le", "--channels=2", "--rate", &rate, "--latency-msec=50"]),
+        ];
+        players.iter().find_map(|(cmd, args)| ProcessSink::spawn(cmd, args).ok())
+    }
+}
+
+impl Sink for ProcessSink {
+    fn write(&mut self, block: &[f32]) -> io::Result<()> {
+        match self.child.stdin.as_mut() {
+            Some(stdin) => write_pcm16(stdin, block),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "player has no stdin")),
+        }
+    }
+}
+
+impl Drop for ProcessSink {
+    fn drop(&mut self) {
+        self.child.stdin.take();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Mixer on a thread feeding a sink in real time. Lock the mixer to play things.
+pub struct Audio {
+    pub mixer: Arc<Mutex<Mixer>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Audio {
+    pub fn start(mut sink: Box<dyn Sink>) -> Audio {
+        let mixer = Arc::new(Mutex::new(Mixer::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let mixer = mixer.clone();
+            let running = running.clone();
+            std::thread::spawn(move || {
+                // stay a little ahead of the wall clock so scheduling latency is bounded
+                let lead = seconds_to_frames(0.05);
+                let t0 = Instant::now();
+                let mut block = vec![0.0; BLOCK_FRAMES * 2];
+                while running.load(Ordering::Relaxed) {
+                    let due = seconds_to_frames(t0.elapsed().as_secs_f64()) + lead;
+                    let now = mixer.lock().unwrap().now();
+                    if now >= due {
+                        std::thread::sleep(Duration::from_millis(2));
+                        continue;
+                    }
+                    mixer.lock().unwrap().render(&mut block);
+                    if sink.write(&block).is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+        Audio { mixer, running, thread: Some(thread) }
+    }
+
+    // the real device if theres one, otherwise a null sink so everything still ticks
+    pub fn start_default() -> Audio {
+        match ProcessSink::default_device() {
+            Some(sink) => Audio::start(Box::new(sink)),
+            None => {
+                println!("no audio player found (aplay or pacat), running silent");
+                Audio::start(Box::new(NullSink { frames: 0 }))
+            },
+        }
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+#[test]
+pub fn test_mixer() {
+    let click = Arc::new(Sound { data: vec![1.0, 0.5] });
+    let mut m = Mixer::new();
+    let sfx = m.add_bus("sfx", 0.5);
+
+    // lands mid block, hard left, on a half gain bus
+    m.play_at(Box::new(SoundSource::new(click.clone(), false)), 3, VoiceParams { gain: 0.5, pan: -1.0, bus: sfx, keep: false });
+    // centred, straight away
+    let v = m.play_sound(&click, VoiceParams::default());
+    let mut out = vec![0.0; 8];
+    m.render(&mut out);
+    assert_eq!(out, vec![1.0, 1.0, 0.5, 0.5, 0.0, 0.0, 0.25, 0.0]);
+    assert!(!m.is_playing(v));
+    // carries on into the next block
+    m.render(&mut out);
+    assert_eq!(out, vec![0.125, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(m.voice_count(), 0);
+    assert_eq!(m.now(), 8);
+
+    // looping voice keeps going until stopped, master gain on top
+    m.set_bus_gain(MASTER, 0.5);
+    let v = m.play(Box::new(SoundSource::new(click, true)), VoiceParams { pan: 1.0, ..Default::default() });
+    m.render(&mut out);
+    assert_eq!(out, vec![0.0, 0.5, 0.0, 0.25, 0.0, 0.5, 0.0, 0.25]);
+    m.stop(v);
+    m.render(&mut out);
+    assert!(out.iter().all(|x| *x == 0.0));
+    assert_eq!(m.bus("sfx"), Some(sfx));
+    assert_eq!(m.bus("nope"), None);
+
+    // full up, the oldest unkept voice makes room. once theyre all kept new ones just dont play
+    let looped = || Box::new(SoundSource::new(Arc::new(Sound { data: vec![0.1] }), true));
+    let first = m.play(looped(), VoiceParams::default());
+    let kept: Vec<VoiceId> = (1..MAX_VOICES).map(|_| m.play(looped(), VoiceParams { keep: true, ..Default::default() })).collect();
+    let v = m.play(looped(), VoiceParams { keep: true, ..Default::default() });
+    assert!(!m.is_playing(first));
+    assert!(m.is_playing(v));
+    let dropped = m.play(looped(), VoiceParams::default());
+    assert!(!m.is_playing(dropped));
+    assert!(kept.iter().all(|k| m.is_playing(*k)));
+    assert_eq!(m.voice_count(), MAX_VOICES);
+}
+
+#[test]
+pub fn test_wav_sink() {
+    let mut sink = WavSink::new(io::Cursor::new(Vec::new())).unwrap();
+    sink.write(&[0.5, -0.5, 2.0, 0.0]).unwrap();
+    let bytes = sink.finish().unwrap().into_inner();
+    assert_eq!(bytes.len(), 44 + 8);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]), 36 + 8);
+    assert_eq!(u32::from_le_bytes([bytes[40], bytes[41], bytes[42], bytes[43]]), 8);
+    let sample = |i: usize| i16::from_le_bytes([bytes[44 + i * 2], bytes[45 + i * 2]]);
+    assert_eq!(sample(0), 16383);
+    assert_eq!(sample(1), -16383);
+    assert_eq!(sample(2), i16::MAX);    // clipped
+}
@@ -1,11 +1,139 @@
 use crate::lib::kmath::*;
 use crate::lib::kimg::*;
+use crate::lib::ktext::*;
+use crate::lib::kfont::*;
+use crate::lib::kpath::*;
+use crate::lib::kedit::*;
+
+use std::rc::Rc;
+use std::collections::HashMap;
 
 use glow::*;
 
 
 pub const text_clip: Rect = Rect {x: 0.0, y: 6.0/10.0, w: 14.0/20.0, h: 1.5/10.0};
 pub const text_aspect: f32 = 7./8.;
+pub const blank_clip: Rect = Rect {x: 0.0, y: 0.0, w: 1.0/20.0, h: 1.0/20.0};
+
+const builtin_font: Monospace = Monospace { aspect: text_aspect, px: 8.0 };
+
+// Draw order, back to front. Each layer gets its own depth so the depth buffer
+// sorts opaque stuff for free. Anything translucent (alpha < 1 or textured) is
+// drawn after all the opaque stuff, layer by layer in this order, so it always
+// blends over whats behind it. Within a layer later draws go on top, except
+// translucent always goes over opaque.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenderLayer {
+    Sky,
+    Stars,
+    Sun,
+    MoonShadow,
+    Hills,
+    Ocean,
+    Reflections,
+    CloudsFar,
+    CloudsMid,
+    CloudsNear,
+    Particles,
+    Pickups,
+    Walls,
+    Player,
+    Bars,
+    Hud,
+    Overlay,
+}
+
+pub const RENDER_LAYER_COUNT: usize = RenderLayer::Overlay as usize + 1;
+
+impl RenderLayer {
+    pub fn from_name(s: &str) -> Option<RenderLayer> {
+        use RenderLayer::*;
+        Some(match s {
+            "sky" => Sky,
+            "stars" => Stars,
+            "sun" => Sun,
+            "moon_shadow" => MoonShadow,
+            "hills" => Hills,
+            "ocean" => Ocean,
+            "reflections" => Reflections,
+            "clouds_far" => CloudsFar,
+            "clouds_mid" => CloudsMid,
+            "clouds_near" => CloudsNear,
+            "particles" => Particles,
+            "pickups" => Pickups,
+            "walls" => Walls,
+            "player" => Player,
+            "bars" => Bars,
+            "hud" => Hud,
+            "overlay" => Overlay,
+            _ => return None,
+        })
+    }
+
+    fn depth(self) -> f32 {
+        1.0 + self as usize as f32 * 0.01
+    }
+}
+
+// what a canvas turns into, opaque verts first then translucent ones
+pub struct DrawList {
+    pub bytes: Vec<u8>,
+    pub opaque_verts: usize,
+}
+
+const VERTEX_BYTES: usize = 9 * 4;
+
+// how a sprite gets drawn. tint multiplies the texture, set_colour doesnt apply to sprites
+#[derive(Clone, Copy, Debug)]
+pub struct SpriteOpts {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rot: f32,       // radians about the middle of the rect
+    pub tint: Vec4,
+}
+
+impl Default for SpriteOpts {
+    fn default() -> SpriteOpts {
+        SpriteOpts {
+            flip_x: false,
+            flip_y: false,
+            rot: 0.0,
+            tint: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// What ended up where in the atlas texture. The hand made atlas.png, with
+// sprites packed under it, is at base_uv. The rects above and the named
+// regions are relative to that.
+pub struct AtlasInfo {
+    pub base_uv: Rect,
+    pub fonts: Vec<Font>,
+    pub regions: HashMap<String, Rect>,
+}
+
+impl AtlasInfo {
+    pub fn new() -> AtlasInfo {
+        AtlasInfo {
+            base_uv: Rect::new(0.0, 0.0, 1.0, 1.0),
+            fonts: Vec::new(),
+            regions: HashMap::new(),
+        }
+    }
+
+    pub fn base_clip(&self, r: Rect) -> Rect {
+        r.transform(Rect::new(0.0, 0.0, 1.0, 1.0), self.base_uv)
+    }
+
+    pub fn region(&self, name: &str) -> Option<Rect> {
+        self.regions.get(name).map(|r| self.base_clip(*r))
+    }
+
+    // old atlases without a manifest still work off the hard coded rects
+    pub fn region_or(&self, name: &str, default: Rect) -> Rect {
+        self.region(name).unwrap_or_else(|| self.base_clip(default))
+    }
+}
 
 
 // Stateful rendering
@@ -54,15 +182,22 @@ impl KRenderer {
         }
     }
 
-    pub fn send(&self, gl: &glow::Context, buf: &[u8]) {
+    pub fn send(&self, gl: &glow::Context, list: &DrawList) {
         unsafe {
             gl.use_program(Some(self.shader));
             gl.bind_texture(glow::TEXTURE_2D, Some(self.atlas));
             gl.bind_vertex_array(Some(self.vao));
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
-            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, buf, glow::DYNAMIC_DRAW);
-            let vert_count = buf.len() / (9*4);
-            gl.draw_arrays(glow::TRIANGLES, 0, vert_count as i32);
+            gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, &list.bytes, glow::DYNAMIC_DRAW);
+            let vert_count = list.bytes.len() / VERTEX_BYTES;
+            // equal depth is same layer, later one wins
+            gl.depth_func(glow::LEQUAL);
+            gl.depth_mask(true);
+            gl.draw_arrays(glow::TRIANGLES, 0, list.opaque_verts as i32);
+            // translucent pass is already back to front, dont let it hide itself
+            gl.depth_mask(false);
+            gl.draw_arrays(glow::TRIANGLES, list.opaque_verts as i32, (vert_count - list.opaque_verts) as i32);
+            gl.depth_mask(true);
         }
     }
 
@@ -76,38 +211,117 @@ impl KRenderer {
 }
 
 pub struct KRCanvas {
-    pub depth: f32,
+    layer: RenderLayer,
+    depth: f32,
     pub colour: Vec4,
-    pub buf: Vec<u8>,
+    passes: Vec<(Vec<u8>, Vec<u8>)>,    // opaque, translucent for each layer
+    tri: Vec<u8>,                       // verts of the triangle being built
+    tri_opaque: bool,
+    blank_uv: Rect,
     pub uv_clip: Rect,
     pub uv_from: Rect,
     pub from_rect: Rect,
     pub flip_y_h: Option<f32>,
-
+    pub view_rot: f32,
+    pub view_scale: Vec2,
+    pub atlas: Rc<AtlasInfo>,
+    pub font: Option<usize>,
+    viewport_px: Option<Vec2>,
 }
 
 impl KRCanvas {
     pub fn new() -> KRCanvas {
         KRCanvas {
-            depth: 1.0,
+            layer: RenderLayer::Sky,
+            depth: RenderLayer::Sky.depth(),
             colour: Vec4::new(0.0, 0.0, 0.0, 1.0), 
-            buf: Vec::new(),
-            uv_clip: Rect::new(0.0, 0.0, 1.0/20.0, 1.0/20.0),
+            passes: vec![(Vec::new(), Vec::new()); RENDER_LAYER_COUNT],
+            tri: Vec::with_capacity(3 * VERTEX_BYTES),
+            tri_opaque: true,
+            blank_uv: blank_clip,
+            uv_clip: blank_clip,
             uv_from: Rect::new(-1000.0, -1000.0, 2000.0, 2000.0),
             from_rect: Rect::new(-1.0, -1.0, 2.0, 2.0),
             flip_y_h: None,
+            view_rot: 0.0,
+            view_scale: Vec2::new(1.0, 1.0),
+            atlas: Rc::new(AtlasInfo::new()),
+            font: None,
+            viewport_px: None,
+        }
+    }
+    // physical size of what were drawing into, lets text snap to pixels
+    pub fn set_viewport_px(&mut self, w: f32, h: f32) {
+        self.viewport_px = Some(Vec2::new(w, h));
+    }
+    // world units per screen pixel, None if we dont know or its rotated/squashed
+    fn px_size(&self) -> Option<f32> {
+        let vp = self.viewport_px?;
+        if self.view_rot != 0.0 || self.view_scale != Vec2::new(1.0, 1.0) || self.flip_y_h.is_some() || vp.y <= 0.0 {
+            return None;
+        }
+        Some(self.from_rect.h / vp.y)
+    }
+    fn snap_to_px(&self, r: Rect) -> Rect {
+        let px = match self.px_size() {
+            Some(px) => px,
+            None => return r,
+        };
+        let o = self.from_rect.tl();
+        let f = |x: f32, o: f32| ((x - o) / px).round() * px + o;
+        let x0 = f(r.x, o.x);
+        let y0 = f(r.y, o.y);
+        Rect::new(x0, y0, f(r.right(), o.x) - x0, f(r.bot(), o.y) - y0)
+    }
+    fn crisp(&self, style: &TextStyle) -> TextStyle {
+        let mut style = *style;
+        if let Some(px) = self.px_size() {
+            style.char_h = crisp_char_h(style.char_h, px, self.metrics());
+        }
+        style
+    }
+    pub fn set_atlas(&mut self, atlas: Rc<AtlasInfo>) {
+        self.blank_uv = atlas.region_or("blank", blank_clip);
+        self.uv_clip = self.blank_uv;
+        self.atlas = atlas;
+    }
+    // by file name without the extension, anything not loaded goes back to the built in font
+    pub fn set_font(&mut self, name: &str) {
+        self.font = self.atlas.fonts.iter().position(|f| f.name == name);
+    }
+    pub fn metrics(&self) -> &dyn GlyphMetrics {
+        match self.font.and_then(|i| self.atlas.fonts.get(i)) {
+            Some(f) => f,
+            None => &builtin_font,
         }
     }
     pub fn set_colour(&mut self, c: Vec4) {
         self.colour = c;
     }
 
-    pub fn set_depth(&mut self, d: f32) {
-        self.depth = d;
+    pub fn set_layer(&mut self, layer: RenderLayer) {
+        self.layer = layer;
+        self.depth = layer.depth();
     }
     pub fn set_camera(&mut self, cam: Rect) {
         self.from_rect = cam;
     }
+    // scale then rotate about the middle of the camera rect
+    pub fn set_view_transform(&mut self, rot: f32, scale: Vec2) {
+        self.view_rot = rot;
+        self.view_scale = scale;
+    }
+    pub fn reset_view_transform(&mut self) {
+        self.set_view_transform(0.0, Vec2::new(1.0, 1.0));
+    }
+
+    // world -> ndc
+    fn project(&self, p: Vec2) -> Vec3 {
+        let c = self.from_rect.centroid();
+        let d = p - c;
+        let p = c + Vec2::new(d.x * self.view_scale.x, d.y * self.view_scale.y).rotate(self.view_rot);
+        p.transform(self.from_rect, Rect::new(0.0, 0.0, 1.0, 1.0)).promote(self.depth)
+    }
 
     pub fn triangle(&mut self, mut a: Vec2, mut b: Vec2, mut c: Vec2) {
         if let Some(h) = self.flip_y_h {
@@ -116,64 +330,35 @@ impl KRCanvas {
             c.y = h - c.y;
         }
         self.uv_from = Triangle{a,b,c}.aabb();
-        let write_float_bytes = |buf: &mut Vec<u8>, x: f32| {
-            for b in x.to_le_bytes() {
-                buf.push(b);
-            }
-        };
-        let write_vec2_bytes = |buf: &mut Vec<u8>, v: Vec2| {
-            write_float_bytes(buf, v.x);
-            write_float_bytes(buf, v.y);
-        };
-        let write_vec3_bytes = |buf: &mut Vec<u8>, v: Vec3| {
-            write_float_bytes(buf, v.x);
-            write_float_bytes(buf, v.y);
-            write_float_bytes(buf, v.z);
-        };
-        let write_vec4_bytes = |buf: &mut Vec<u8>, v: Vec4| {
-            write_float_bytes(buf, v.x);
-            write_float_bytes(buf, v.y);
-            write_float_bytes(buf, v.z);
-            write_float_bytes(buf, v.w);
-        };
-        // ndc
-        let to_rect = Rect::new(0.0, 0.0, 1.0, 1.0);
-
-        // a
-        write_vec3_bytes(&mut self.buf, a.transform(self.from_rect, to_rect).promote(self.depth));
-        write_vec4_bytes(&mut self.buf, self.colour);
-        write_vec2_bytes(&mut self.buf, a.transform(self.uv_from, self.uv_clip));
-        // b
-        write_vec3_bytes(&mut self.buf, b.transform(self.from_rect, to_rect).promote(self.depth));
-        write_vec4_bytes(&mut self.buf, self.colour);
-        write_vec2_bytes(&mut self.buf, b.transform(self.uv_from, self.uv_clip));
-        // c
-        write_vec3_bytes(&mut self.buf, c.transform(self.from_rect, to_rect).promote(self.depth));
-        write_vec4_bytes(&mut self.buf, self.colour);
-        write_vec2_bytes(&mut self.buf, c.transform(self.uv_from, self.uv_clip));
+        for p in [a, b, c] {
+            let uv = p.transform(self.uv_from, self.uv_clip);
+            self.vertex_uv(p, self.colour, uv);
+        }
     }
 
     pub fn vertex(&mut self, pos: Vec2, depth: f32, colour: Vec4) {
-        let write_float_bytes = |buf: &mut Vec<u8>, x: f32| {
-            for b in x.to_le_bytes() {
-                buf.push(b);
-            }
-        };
-        // ndc
-        let to_rect = Rect::new(0.0, 0.0, 1.0, 1.0);
-
-        let pos3 = pos.transform(self.from_rect, to_rect).promote(self.depth);
         let uv = pos.transform(self.uv_from, self.uv_clip);
+        self.vertex_uv(pos, colour, uv);
+    }
 
-        write_float_bytes(&mut self.buf, pos3.x);
-        write_float_bytes(&mut self.buf, pos3.y);
-        write_float_bytes(&mut self.buf, pos3.z);
-        write_float_bytes(&mut self.buf, colour.x);
-        write_float_bytes(&mut self.buf, colour.y);
-        write_float_bytes(&mut self.buf, colour.z);
-        write_float_bytes(&mut self.buf, colour.w);
-        write_float_bytes(&mut self.buf, uv.x);
-        write_float_bytes(&mut self.buf, uv.y);
+    // every third vertex finishes a triangle, which goes in the opaque or
+    // translucent pass of the current layer
+    fn vertex_uv(&mut self, pos: Vec2, colour: Vec4, uv: Vec2) {
+        let pos3 = self.project(pos);
+        for x in [pos3.x, pos3.y, pos3.z, colour.x, colour.y, colour.z, colour.w, uv.x, uv.y] {
+            self.tri.extend_from_slice(&x.to_le_bytes());
+        }
+        self.tri_opaque &= colour.w >= 1.0 && self.blank_uv.dilate(0.0001).contains(uv);
+        if self.tri.len() == 3 * VERTEX_BYTES {
+            let (opaque, translucent) = &mut self.passes[self.layer as usize];
+            if self.tri_opaque {
+                opaque.extend_from_slice(&self.tri);
+            } else {
+                translucent.extend_from_slice(&self.tri);
+            }
+            self.tri.clear();
+            self.tri_opaque = true;
+        }
     }
 
     pub fn grad_rect_ud(&mut self, r: Rect, col_top: Vec4, col_bot: Vec4) {
@@ -212,38 +397,168 @@ impl KRCanvas {
         }
     }
 
+    pub fn triangles(&mut self, tris: &[Triangle]) {
+        for t in tris {
+            self.triangle(t.a, t.b, t.c);
+        }
+    }
+
+    pub fn stroke(&mut self, points: &[Vec2], style: &StrokeStyle) {
+        self.triangles(&stroke(points, style));
+    }
+
+    // any simple polygon, doesnt have to be convex
+    pub fn fill_poly(&mut self, points: &[Vec2]) {
+        self.triangles(&triangulate(points));
+    }
+
+    pub fn arc(&mut self, c: Vec2, r: f32, a0: f32, a1: f32, style: &StrokeStyle) {
+        self.stroke(&arc_points(c, r, a0, a1), style);
+    }
+
+    pub fn pie(&mut self, c: Vec2, r: f32, a0: f32, a1: f32) {
+        self.triangles(&pie(c, r, a0, a1));
+    }
+
+    pub fn bezier(&mut self, p0: Vec2, c1: Vec2, c2: Vec2, p3: Vec2, style: &StrokeStyle) {
+        self.stroke(&bezier_points(p0, c1, c2, p3), style);
+    }
+
+    pub fn rounded_rect(&mut self, r: Rect, radius: f32) {
+        let points = rounded_rect_points(r, radius);
+        self.triangles(&fan(r.centroid(), &[points.as_slice(), &points[..1]].concat()));
+    }
+
+    pub fn rounded_rect_outline(&mut self, r: Rect, radius: f32, width: f32) {
+        self.stroke(&rounded_rect_points(r, radius), &StrokeStyle::new(width).closed());
+    }
+
     pub fn circle(&mut self, center: Vec2, radius: f32) {
         let n_sides = (radius as f32).sqrt() * 200.0;
         // let n_sides = 6;
         self.poly(center, radius, (n_sides as i32).max(6));
     }
 
-    pub fn text_left(&mut self, s: &[u8], r: Rect) {
-        let mut char_rect = Rect::new(r.x, r.y, r.h * text_aspect, r.h);
-        for c in s {
-            let idx = c - b' ';
-            let x = idx % 32;
-            let y = idx / 32;
-            let char_clip = text_clip.grid_child(x as i32, y as i32, 32, 3);
-            self.uv_clip = char_clip;
-            self.rect(char_rect);
-            char_rect.x += char_rect.w;
+    // named atlas region stretched over r, false and nothing drawn if its not in the atlas
+    pub fn sprite_ex(&mut self, name: &str, r: Rect, opts: &SpriteOpts) -> bool {
+        let uv = match self.atlas.region(name) {
+            Some(uv) => uv,
+            None => return false,
+        };
+        let (mut u0, mut u1, mut v0, mut v1) = (uv.left(), uv.right(), uv.top(), uv.bot());
+        if opts.flip_x {
+            std::mem::swap(&mut u0, &mut u1);
         }
+        if opts.flip_y {
+            std::mem::swap(&mut v0, &mut v1);
+        }
+        let c = r.centroid();
+        let corner = |p: Vec2| c + (p - c).rotate(opts.rot);
+        let mut tl = corner(r.tl());
+        let mut tr = corner(r.tr());
+        let mut bl = corner(r.bl());
+        let mut br = corner(r.br());
+        if let Some(h) = self.flip_y_h {
+            for p in [&mut tl, &mut tr, &mut bl, &mut br] {
+                p.y = h - p.y;
+            }
+        }
+        let col = opts.tint;
+        self.vertex_uv(tl, col, Vec2::new(u0, v0));
+        self.vertex_uv(tr, col, Vec2::new(u1, v0));
+        self.vertex_uv(bl, col, Vec2::new(u0, v1));
+        self.vertex_uv(bl, col, Vec2::new(u0, v1));
+        self.vertex_uv(tr, col, Vec2::new(u1, v0));
+        self.vertex_uv(br, col, Vec2::new(u1, v1));
+        true
     }
+
+    pub fn glyph(&mut self, c: char, r: Rect) {
+        let old_clip = self.uv_clip;
+        let font_uv = self.font.and_then(|i| self.atlas.fonts.get(i)).and_then(|f| f.glyphs.get(&c)).map(|g| g.uv);
+        self.uv_clip = match font_uv {
+            Some(uv) => uv,
+            None => {
+                let idx = glyph_index(c);
+                self.atlas.region_or("font", text_clip).grid_child((idx % 32) as i32, (idx / 32) as i32, 32, 3)
+            },
+        };
+        self.rect(r);
+        self.uv_clip = old_clip;
+    }
+
     pub fn text_center(&mut self, s: &[u8], r: Rect) {
-        let r = r.fit_aspect_ratio(s.len() as f32 * text_aspect);
-        self.text_left(s, r);
+        let spans: Vec<_> = s.iter().map(|c| (*c as char, None)).collect();
+        let w = layout_spans(&spans, Rect::new(0.0, 0.0, 0.0, 1.0), &TextStyle::new(1.0).no_wrap(), self.metrics()).bounds.w;
+        let h = r.fit_aspect_ratio(w.max(0.001)).h;
+        let style = self.crisp(&TextStyle::new(h).no_wrap().align(HAlign::Centre, VAlign::Middle));
+        let l = layout_spans(&spans, r, &style, self.metrics());
+        self.draw_layout(&l);
     }
 
-    pub fn bytes(self) -> Vec<u8> {
-        self.buf
+    // multi line, wrapped and aligned within r, returns the bounds of what was drawn
+    pub fn text(&mut self, s: &str, r: Rect, style: &TextStyle) -> Rect {
+        let style = self.crisp(style);
+        let l = layout(s, r, &style, self.metrics());
+        self.draw_layout(&l);
+        l.bounds
+    }
+
+    // a box with the fields text in, selection behind it and a caret that blinks off t.
+    // doesnt scroll, pick a max_len that fits
+    pub fn text_field(&mut self, f: &TextField, r: Rect, text: Vec4, t: f64) {
+        let inner = r.dilate(-r.h * 0.15);
+        let style = self.crisp(&TextStyle::new(inner.h * 0.8).no_wrap().align(HAlign::Left, VAlign::Middle));
+        let spans: Vec<_> = f.text().chars().map(|c| (c, None)).collect();
+        let x = |kc: &KRCanvas, i: usize| inner.x + layout_spans(&spans[..i], inner, &style, kc.metrics()).bounds.w;
+
+        let old = self.colour;
+        let radius = r.h * 0.2;
+        self.set_colour(Vec4::new(text.x, text.y, text.z, 0.08));
+        self.rounded_rect(r, radius);
+        self.set_colour(Vec4::new(text.x, text.y, text.z, if f.focused {0.6} else {0.25}));
+        self.rounded_rect_outline(r, radius, r.h * 0.04);
+        if let Some((a, b)) = f.selection() {
+            let (xa, xb) = (x(self, a), x(self, b));
+            self.set_colour(Vec4::new(text.x, text.y, text.z, 0.3));
+            self.rect(Rect::new(xa, inner.y, xb - xa, inner.h));
+        }
+        self.set_colour(text);
+        let l = layout_spans(&spans, inner, &style, self.metrics());
+        self.draw_layout(&l);
+        if f.focused && t.fract() < 0.5 {
+            let cx = x(self, f.cursor());
+            self.rect(Rect::new(cx, inner.y, inner.h * 0.06, inner.h));
+        }
+        self.colour = old;
+    }
+
+    pub fn draw_layout(&mut self, l: &TextLayout) {
+        let base_colour = self.colour;
+        for g in l.glyphs.iter() {
+            self.colour = g.colour.unwrap_or(base_colour);
+            self.glyph(g.c, self.snap_to_px(g.rect));
+        }
+        self.colour = base_colour;
+    }
+
+    pub fn draw_list(self) -> DrawList {
+        let mut bytes = Vec::new();
+        for (opaque, _) in self.passes.iter() {
+            bytes.extend_from_slice(opaque);
+        }
+        let opaque_verts = bytes.len() / VERTEX_BYTES;
+        for (_, translucent) in self.passes.iter() {
+            bytes.extend_from_slice(translucent);
+        }
+        DrawList { bytes, opaque_verts }
     }
 
     pub fn cloud(&mut self, r: Rect, seed: u32) {
         self.rect(r);
         let r1 = kuniform(seed, r.h, r.h*1.5);
-        let r2 = kuniform(seed * 129836125, r.h, r.h*2.0);
-        let r3 = kuniform(seed * 129316739, r.h, r.h*1.5);
+        let r2 = kuniform(seed.wrapping_mul(129836125), r.h, r.h*2.0);
+        let r3 = kuniform(seed.wrapping_mul(129316739), r.h, r.h*1.5);
         let c1 = Vec2::new(r.left(), r.bot() - r1);
         let c2 = Vec2::new(r.centroid().x, r.bot() - r2);
         let c3 = Vec2::new(r.right(), r.bot() - r3);
@@ -260,3 +575,30 @@ pub fn text_layout(len: usize, x: f32, y: f32, char_w: f32, char_h: f32) -> Rect
     return Rect::new(x, y, char_w * len as f32, char_h);
 }
 
+
+#[test]
+pub fn test_render_passes() {
+    let vert_z = |list: &DrawList, i: usize| {
+        let o = i * VERTEX_BYTES + 8;
+        f32::from_le_bytes([list.bytes[o], list.bytes[o+1], list.bytes[o+2], list.bytes[o+3]])
+    };
+    let mut kc = KRCanvas::new();
+    let r = Rect::new(0.0, 0.0, 1.0, 1.0);
+    // submitted front to back and mixed up
+    kc.set_layer(RenderLayer::Overlay);
+    kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 0.5));
+    kc.rect(r);
+    kc.set_layer(RenderLayer::Player);
+    kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+    kc.rect(r);
+    kc.set_layer(RenderLayer::Sky);
+    kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 0.5));
+    kc.rect(r);
+    let list = kc.draw_list();
+    assert_eq!(list.bytes.len(), 18 * VERTEX_BYTES);
+    // opaque first, then the translucent ones back to front
+    assert_eq!(list.opaque_verts, 6);
+    assert_eq!(vert_z(&list, 0), RenderLayer::Player.depth());
+    assert_eq!(vert_z(&list, 6), RenderLayer::Sky.depth());
+    assert_eq!(vert_z(&list, 12), RenderLayer::Overlay.depth());
+}
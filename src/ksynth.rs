@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use crate::lib::kmath::*;
+use crate::lib::kconf::*;
+use crate::kaudio::*;
+
+// Little FM synth for sound effects. A Patch is up to four operators with a
+// modulation matrix between them, a pitch sweep over the length of the sound,
+// an amp envelope and a phase wobble LFO. Patches come from sfx.conf and get
+// rendered to Sounds once at startup, so there are no audio files to ship.
+//
+// Modulation is phase modulation like the DX7 does it, each entry in the
+// matrix adds index * source output (radians) to the destinations phase.
+
+pub const MAX_OPS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wave {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    Noise,
+}
+
+impl Wave {
+    pub fn from_name(s: &str) -> Option<Wave> {
+        match s {
+            "sine" => Some(Wave::Sine),
+            "square" => Some(Wave::Square),
+            "saw" => Some(Wave::Saw),
+            "triangle" => Some(Wave::Triangle),
+            "noise" => Some(Wave::Noise),
+            _ => None,
+        }
+    }
+
+    // phase in cycles, noise is whatever white noise sample is current
+    pub fn sample(&self, phase: f32, noise: f32) -> f32 {
+        let p = phase.rem_euclid(1.0);
+        match self {
+            Wave::Sine => (TAU * p).sin(),
+            Wave::Square => if p < 0.5 {1.0} else {-1.0},
+            Wave::Saw => 2.0 * p - 1.0,
+            Wave::Triangle => 4.0 * (p - 0.5).abs() - 1.0,
+            Wave::Noise => noise,
+        }
+    }
+}
+
+// adsr, release runs into the end of the sound rather than from a note off
+#[derive(Clone, Copy, Debug)]
+pub struct Env {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+impl Env {
+    pub fn flat() -> Env {
+        Env { attack: 0.0, decay: 0.0, sustain: 1.0, release: 0.0 }
+    }
+
+    pub fn from_floats(f: &[f32]) -> Option<Env> {
+        match f {
+            [attack, decay, sustain, release] => Some(Env { attack: *attack, decay: *decay, sustain: *sustain, release: *release }),
+            _ => None,
+        }
+    }
+
+    pub fn level(&self, t: f32, length: f32) -> f32 {
+        let level = if t < self.attack {
+            t / self.attack
+        } else if t - self.attack < self.decay {
+            lerp(1.0, self.sustain, (t - self.attack) / self.decay)
+        } else {
+            self.sustain
+        };
+        let release = if self.release > 0.0 {((length - t) / self.release).clamp(0.0, 1.0)} else {1.0};
+        level * release
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Operator {
+    pub wave: Wave,
+    pub ratio: f32,     // of the swept base frequency
+    pub detune: f32,    // Hz on top
+    pub level: f32,
+    pub env: Env,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sweep {
+    Linear,
+    Exponential,
+}
+
+#[derive(Clone, Debug)]
+pub struct Patch {
+    pub name: String,
+    pub length: f32,
+    pub freq_start: f32,
+    pub freq_end: f32,
+    pub sweep: Sweep,
+    pub env: Env,
+    pub wobble_rate: f32,
+    pub wobble_depth: f32,  // cycles
+    pub gain: f32,
+    pub ops: Vec<Operator>,
+    pub matrix: [[f32; MAX_OPS]; MAX_OPS],  // [to][from], radians
+    pub outs: Vec<usize>,
+}
+
+impl Patch {
+    // one sine, no modulation. the conf just overrides bits of this
+    pub fn new(name: &str, length: f32, freq: f32) -> Patch {
+        Patch {
+            name: name.to_owned(),
+            length,
+            freq_start: freq,
+            freq_end: freq,
+            sweep: Sweep::Exponential,
+            env: Env::flat(),
+            wobble_rate: 0.0,
+            wobble_depth: 0.0,
+            gain: 0.5,
+            ops: vec![Operator { wave: Wave::Sine, ratio: 1.0, detune: 0.0, level: 1.0, env: Env::flat() }],
+            matrix: [[0.0; MAX_OPS]; MAX_OPS],
+            outs: vec![0],
+        }
+    }
+
+    pub fn from_conf(s: &ConfSection) -> Option<Patch> {
+        let mut p = Patch::new(s.get("name")?, s.f32_or("length", 0.2), 440.0);
+        if let Some(f) = s.floats("freq") {
+            p.freq_start = *f.first()?;
+            p.freq_end = *f.get(1).unwrap_or(&p.freq_start);
+        }
+        if s.str_or("sweep", "exp") == "lin" {
+            p.sweep = Sweep::Linear;
+        }
+        if let Some(env) = s.floats("env").as_deref().and_then(Env::from_floats) {
+            p.env = env;
+        }
+        let wobble = s.vec2_or("wobble", Vec2::new(0.0, 0.0));
+        p.wobble_rate = wobble.x;
+        p.wobble_depth = wobble.y;
+        p.gain = s.f32_or("gain", p.gain);
+
+        // op1 = wave ratio [level] [detune]
+        let mut ops = Vec::new();
+        for i in 1..=MAX_OPS {
+            let def = match s.get(&format!("op{}", i)) {
+                Some(def) => def,
+                None => break,
+            };
+            let mut words = def.split_whitespace();
+            let wave = Wave::from_name(words.next()?)?;
+            let nums: Vec<f32> = words.filter_map(|w| w.parse().ok()).collect();
+            ops.push(Operator {
+                wave,
+                ratio: *nums.first().unwrap_or(&1.0),
+                level: *nums.get(1).unwrap_or(&1.0),
+                detune: *nums.get(2).unwrap_or(&0.0),
+                env: s.floats(&format!("op{}_env", i)).as_deref().and_then(Env::from_floats).unwrap_or_else(Env::flat),
+            });
+        }
+        if !ops.is_empty() {
+            p.ops = ops;
+        }
+
+        // mod = from>to:index, operators numbered from 1 like the keys
+        for m in s.str_or("mod", "").split_whitespace() {
+            let (route, index) = m.split_once(':')?;
+            let (from, to) = route.split_once('>')?;
+            let (from, to): (usize, usize) = (from.parse().ok()?, to.parse().ok()?);
+            if from == 0 || to == 0 || from > p.ops.len() || to > p.ops.len() {
+                return None;
+            }
+            p.matrix[to - 1][from - 1] = index.parse().ok()?;
+        }
+        if let Some(outs) = s.get("out") {
+            p.outs = outs.split_whitespace().filter_map(|o| o.parse::<usize>().ok())
+                .filter(|o| *o >= 1 && *o <= p.ops.len())
+                .map(|o| o - 1)
+                .collect();
+        }
+        Some(p)
+    }
+
+    pub fn freq_at(&self, u: f32) -> f32 {
+        match self.sweep {
+            Sweep::Linear => lerp(self.freq_start, self.freq_end, u),
+            Sweep::Exponential if self.freq_start > 0.0 && self.freq_end > 0.0 =>
+                self.freq_start * (self.freq_end / self.freq_start).powf(u),
+            Sweep::Exponential => lerp(self.freq_start, self.freq_end, u),
+        }
+    }
+
+    // mono at SAMPLE_RATE. modulators read each others output from the last
+    // sample, which is also what makes feedback (n>n) work
+    pub fn render(&self) -> Vec<f32> {
+        let n = seconds_to_frames(self.length as f64) as usize;
+        let sr = SAMPLE_RATE as f32;
+        let mut out = Vec::with_capacity(n);
+        let mut phases = [0.0f32; MAX_OPS];
+        let mut prev = [0.0f32; MAX_OPS];
+        let out_scale = if self.outs.is_empty() {0.0} else {1.0 / self.outs.len() as f32};
+
+        for i in 0..n {
+            let t = i as f32 / sr;
+            let base = self.freq_at(t / self.length);
+            let wobble = self.wobble_depth * (TAU * self.wobble_rate * t).sin();
+            let noise = krand(i as u32) * 2.0 - 1.0;
+            let mut cur = [0.0f32; MAX_OPS];
+            for (k, op) in self.ops.iter().enumerate() {
+                let pm: f32 = (0..self.ops.len()).map(|j| self.matrix[k][j] * prev[j]).sum::<f32>() / TAU;
+                cur[k] = op.wave.sample(phases[k] + pm + wobble, noise) * op.level * op.env.level(t, self.length);
+                phases[k] = (phases[k] + (base * op.ratio + op.detune) / sr).fract();
+            }
+            prev = cur;
+            let s: f32 = self.outs.iter().map(|o| cur[*o]).sum::<f32>() * out_scale;
+            out.push((s * self.env.level(t, self.length) * self.gain).clamp(-1.0, 1.0));
+        }
+        out
+    }
+}
+
+pub fn parse_patches(sections: &[ConfSection]) -> Vec<Patch> {
+    sections.iter().filter(|s| s.name == "sfx").filter_map(Patch::from_conf).collect()
+}
+
+pub const DEFAULT_SFX: &str = include_str!("../sfx.conf");
+
+pub fn load_patches() -> Vec<Patch> {
+    parse_patches(&load_conf(&["sfx.conf", "../../sfx.conf"], DEFAULT_SFX))
+}
+
+// every patch rendered, played by name on the sfx bus
+pub struct SfxBank {
+    sounds: HashMap<String, Arc<Sound>>,
+    pub bus: BusId,
+}
+
+impl SfxBank {
+    pub fn new(patches: &[Patch], bus: BusId) -> SfxBank {
+        let sounds = patches.iter().map(|p| (p.name.clone(), Arc::new(Sound { data: p.render() }))).collect();
+        SfxBank { sounds, bus }
+    }
+
+    pub fn play(&self, mixer: &mut Mixer, name: &str) {
+        if let Some(sound) = self.sounds.get(name) {
+            mixer.play_sound(sound, VoiceParams { bus: self.bus, ..Default::default() });
+        }
+    }
+}
+
+#[test]
+pub fn test_synth() {
+    // plain sine, count upward zero crossings to get the pitch back
+    let p = Patch::new("a", 0.5, 440.0);
+    let s = p.render();
+    assert_eq!(s.len(), 22050);
+    let crossings = |s: &[f32]| s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+    assert!((crossings(&s) as i32 - 220).abs() <= 1);
+    assert!(s.iter().all(|x| x.abs() <= 0.5));
+
+    // octave down sweep, exponential is at the geometric mean halfway
+    let mut sweep = Patch::new("b", 1.0, 880.0);
+    sweep.freq_end = 440.0;
+    assert!((sweep.freq_at(0.5) - 622.25).abs() < 0.01);
+    sweep.sweep = Sweep::Linear;
+    assert_eq!(sweep.freq_at(0.5), 660.0);
+
+    let patches = parse_patches(&parse_conf("
+        [sfx]
+        name = fm
+        length = 0.5
+        freq = 441
+        op1 = sine 1
+        op2 = sine 2 1
+        mod = 2>1:3 1>1:0.5
+        out = 1
+        [sfx]
+        name = broken
+        op1 = sine 1
+        mod = 3>1:1
+    "));
+    assert_eq!(patches.len(), 1);
+    let fm = &patches[0];
+    assert_eq!(fm.matrix[0][1], 3.0);
+    assert_eq!(fm.matrix[0][0], 0.5);
+    // modulating doesnt change the pitch of a 1:2 pair, 441 Hz repeats every 100 samples
+    let fm_s = fm.render();
+    assert!((1000..2000).all(|i| (fm_s[i] - fm_s[i + 100]).abs() < 0.01));
+    // but it does change the shape
+    assert!(fm_s.iter().zip(s.iter()).any(|(a, b)| (a - b).abs() > 0.1));
+
+    // all the shipped presets load and make noise
+    let presets = parse_patches(&parse_conf(DEFAULT_SFX));
+    for name in ["flip", "pickup", "near_miss", "death", "theme"] {
+        let p = presets.iter().find(|p| p.name == name).unwrap();
+        assert!(p.render().iter().any(|x| x.abs() > 0.05), "{}", name);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use glutin::event::VirtualKeyCode;
+use glutin::event::VirtualKeyCode::*;
+
+use crate::lib::kconf::*;
+use crate::lib::kinput::KeyStatus;
+use crate::lib::kpad::PadButton;
+use crate::lib::ktouch::GestureKind;
+
+// Actions and what theyre bound to. The game asks FrameInputState about
+// actions, never keys, so everything can be rebound. Bindings live in kconf:
+//
+// [bindings]
+// flip = Space lmb
+// pause = P
+//
+// keys are winit names (Space, A, Key1, Escape, F1...), mouse buttons are lmb
+// rmb mmb mouse_back mouse_forward mouse_N, the wheel is wheel_up wheel_down, gamepad buttons are pad_south pad_start pad_up etc. (see kpad),
+// touch gestures are tap swipe_up swipe_down swipe_left swipe_right, touch is a
+// finger going down anywhere.
+// Any number per action.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Flip,
+    Pause,
+    Restart,
+    Confirm,
+    Back,
+    Rewind,
+    NextTheme,
+    Rebind,
+    MenuUp,
+    MenuDown,
+    Unbind,
+}
+
+// where an action gets read. two actions can share an input as long as theyre
+// never read at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    Play,
+    Dead,
+    Menu,
+    Global,
+}
+
+pub const ACTIONS: [Action; 11] = [
+    Action::Flip, Action::Pause, Action::Restart, Action::Confirm, Action::Back, Action::Rewind,
+    Action::NextTheme, Action::Rebind, Action::MenuUp, Action::MenuDown, Action::Unbind,
+];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Flip => "flip",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Confirm => "confirm",
+            Action::Back => "back",
+            Action::Rewind => "rewind",
+            Action::NextTheme => "next_theme",
+            Action::Rebind => "rebind",
+            Action::MenuUp => "menu_up",
+            Action::MenuDown => "menu_down",
+            Action::Unbind => "unbind",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|a| a.name() == s)
+    }
+
+    pub fn context(&self) -> Context {
+        match self {
+            Action::Flip | Action::Pause | Action::Rewind | Action::Rebind => Context::Play,
+            Action::Restart => Context::Dead,
+            Action::Confirm | Action::Back | Action::MenuUp | Action::MenuDown | Action::Unbind => Context::Menu,
+            Action::NextTheme => Context::Global,
+        }
+    }
+
+    // global is read alongside everything else
+    pub fn overlaps(&self, other: Action) -> bool {
+        let (a, b) = (self.context(), other.context());
+        a == b || a == Context::Global || b == Context::Global
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Lmb,
+    Rmb,
+    Mmb,
+    MouseBack,
+    MouseForward,
+    Mouse(u16),     // any other mouse button, winits number for it
+    WheelUp,
+    WheelDown,
+    Pad(PadButton),
+    Touch(GestureKind),
+    TouchDown,      // any finger, held while any are down
+}
+
+// what can go in a bindings file. winits list is huge, this is the sensible bit of it
+const KEYS: [VirtualKeyCode; 81] = [
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Space, Return, NumpadEnter, Back, Tab, Delete, Insert, Home, End, PageUp, PageDown,
+    Up, Down, Left, Right,
+    LShift, RShift, LControl, RControl, LAlt, RAlt,
+    Minus, Equals, LBracket, RBracket, Semicolon, Apostrophe, Grave, Backslash, Comma, Period, Slash,
+];
+
+impl Input {
+    pub fn name(&self) -> String {
+        match self {
+            Input::Key(k) => format!("{:?}", k),
+            Input::Lmb => "lmb".to_owned(),
+            Input::Rmb => "rmb".to_owned(),
+            Input::Mmb => "mmb".to_owned(),
+            Input::MouseBack => "mouse_back".to_owned(),
+            Input::MouseForward => "mouse_forward".to_owned(),
+            Input::Mouse(n) => format!("mouse_{}", n),
+            Input::WheelUp => "wheel_up".to_owned(),
+            Input::WheelDown => "wheel_down".to_owned(),
+            Input::Pad(b) => b.name(),
+            Input::Touch(g) => g.name().to_owned(),
+            Input::TouchDown => "touch".to_owned(),
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Input> {
+        match s {
+            "lmb" => Some(Input::Lmb),
+            "rmb" => Some(Input::Rmb),
+            "mmb" => Some(Input::Mmb),
+            "mouse_back" => Some(Input::MouseBack),
+            "mouse_forward" => Some(Input::MouseForward),
+            "wheel_up" => Some(Input::WheelUp),
+            "wheel_down" => Some(Input::WheelDown),
+            "touch" => Some(Input::TouchDown),
+            s if s.starts_with("mouse_") => s[6..].parse().ok().map(Input::Mouse),
+            s if s.starts_with("pad_") => PadButton::from_name(s).map(Input::Pad),
+            s if GestureKind::from_name(s).is_some() => GestureKind::from_name(s).map(Input::Touch),
+            _ => KEYS.iter().find(|k| format!("{:?}", k) == s).map(|k| Input::Key(*k)),
+        }
+    }
+
+    pub fn bindable(&self) -> bool {
+        match self {
+            Input::Key(k) => KEYS.contains(k),
+            // every tap and swipe starts with one, so the rebind screen waits
+            // to see which gesture it was. touch has to go in by hand
+            Input::TouchDown => false,
+            _ => true,
+        }
+    }
+}
+
+// for picking the most eventful of several statuses
+pub fn status_rank(s: KeyStatus) -> u8 {
+    match s {
+        KeyStatus::Released => 0,
+        KeyStatus::JustReleased => 1,
+        KeyStatus::Pressed => 2,
+        KeyStatus::JustPressed => 3,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    map: HashMap<Action, Vec<Input>>,
+}
+
+// controls.conf only exists once someone rebinds something, it gets written
+// next to wherever the game is run from
+pub const DEFAULT_BINDINGS: &str = "
+[bindings]
+flip = Space lmb pad_south touch
+pause = P pad_start
+restart = Space Return pad_south tap
+confirm = Return Space pad_south
+back = Escape pad_east swipe_left mouse_back
+rewind = R pad_west
+next_theme = T pad_north
+rebind = F1 pad_select
+menu_up = Up W pad_up swipe_up wheel_up
+menu_down = Down S pad_down swipe_down wheel_down
+unbind = Delete Back pad_west swipe_right
+";
+
+impl Bindings {
+    pub fn empty() -> Bindings {
+        Bindings { map: HashMap::new() }
+    }
+
+    pub fn builtin() -> Bindings {
+        Bindings::from_conf(&parse_conf(DEFAULT_BINDINGS))
+    }
+
+    // actions missing from the file keep their builtin bindings, unknown names are skipped
+    pub fn from_conf(sections: &[ConfSection]) -> Bindings {
+        let mut b = Bindings::empty();
+        if let Some(s) = sections.iter().find(|s| s.name == "bindings") {
+            for (k, v) in s.entries.iter() {
+                if let Some(a) = Action::from_name(k) {
+                    b.map.insert(a, v.split_whitespace().filter_map(Input::from_name).collect());
+                }
+            }
+        }
+        b
+    }
+
+    // same but missing actions keep the builtin ones
+    pub fn from_conf_or_builtin(sections: &[ConfSection]) -> Bindings {
+        let mut b = Bindings::builtin();
+        b.map.extend(Bindings::from_conf(sections).map);
+        b
+    }
+
+    pub fn load(paths: &[&str]) -> Bindings {
+        Bindings::from_conf_or_builtin(&load_conf(paths, ""))
+    }
+
+    pub fn to_conf(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "# key bindings, rebind in game with F1. keys are winit names, mouse is lmb rmb mmb mouse_back mouse_forward wheel_up wheel_down, pads are pad_south pad_start etc, touch is touch, tap and swipe_up/down/left/right").unwrap();
+        writeln!(s, "[bindings]").unwrap();
+        for a in ACTIONS {
+            let inputs: Vec<String> = self.get(a).iter().map(|i| i.name()).collect();
+            writeln!(s, "{} = {}", a.name(), inputs.join(" ")).unwrap();
+        }
+        s
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_conf())
+    }
+
+    pub fn get(&self, a: Action) -> &[Input] {
+        self.map.get(&a).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn actions_for(&self, input: Input) -> Vec<Action> {
+        ACTIONS.iter().copied().filter(|a| self.get(*a).contains(&input)).collect()
+    }
+
+    // what else would fire if input went on a
+    pub fn conflict(&self, a: Action, input: Input) -> Option<Action> {
+        ACTIONS.iter().copied().find(|b| *b != a && a.overlaps(*b) && self.get(*b).contains(&input))
+    }
+
+    // refuses if its a conflict, says who with
+    pub fn bind(&mut self, a: Action, input: Input) -> Result<(), Action> {
+        if let Some(other) = self.conflict(a, input) {
+            return Err(other);
+        }
+        let inputs = self.map.entry(a).or_default();
+        if !inputs.contains(&input) {
+            inputs.push(input);
+        }
+        Ok(())
+    }
+
+    // stays in the file as an empty line so it doesnt come back as the default
+    pub fn unbind_all(&mut self, a: Action) {
+        self.map.insert(a, Vec::new());
+    }
+
+    // every pair of actions that share an input, for checking files people edited by hand
+    pub fn conflicts(&self) -> Vec<(Action, Action, Input)> {
+        let mut out = Vec::new();
+        for (i, a) in ACTIONS.iter().enumerate() {
+            for b in ACTIONS.iter().skip(i + 1) {
+                if !a.overlaps(*b) {
+                    continue;
+                }
+                for input in self.get(*a) {
+                    if self.get(*b).contains(input) {
+                        out.push((*a, *b, *input));
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // action statuses from input statuses. if several inputs are bound the
+    // most eventful one wins, so pressing a second key still counts as a press
+    pub fn resolve(&self, status: impl Fn(Input) -> KeyStatus) -> HashMap<Action, KeyStatus> {
+        let mut out = HashMap::new();
+        for (a, inputs) in self.map.iter() {
+            let best = inputs.iter().map(|i| status(*i)).max_by_key(|s| status_rank(*s)).unwrap_or(KeyStatus::Released);
+            if best != KeyStatus::Released {
+                out.insert(*a, best);
+            }
+        }
+        out
+    }
+}
+
+#[test]
+pub fn test_bindings() {
+    let b = Bindings::builtin();
+    assert!(b.conflicts().is_empty(), "{:?}", b.conflicts());
+    assert!(b.get(Action::Flip).contains(&Input::Key(Space)));
+    assert!(b.get(Action::Flip).contains(&Input::Lmb));
+    assert!(b.get(Action::Flip).contains(&Input::Pad(PadButton::South)));
+    assert!(b.get(Action::Flip).contains(&Input::TouchDown));
+    assert_eq!(Input::from_name("touch"), Some(Input::TouchDown));
+    // swipes are for menus, one mid run would pause or open rebinding by accident
+    for a in ACTIONS.iter().filter(|a| a.context() == Context::Play) {
+        assert!(!b.get(*a).iter().any(|i| matches!(i, Input::Touch(g) if *g != GestureKind::Tap)), "{:?}", a);
+    }
+
+    // restart sharing space with flip is fine, theyre never read together
+    assert!(b.get(Action::Restart).contains(&Input::Key(Space)));
+    let mut b2 = b.clone();
+    assert_eq!(b2.bind(Action::Pause, Input::Key(Space)), Err(Action::Flip));
+    // next theme is global so it clashes with menu keys too
+    let menu_key = b.get(Action::Confirm)[0];
+    assert!(b2.bind(Action::NextTheme, menu_key).is_err());
+    assert_eq!(b2.bind(Action::Pause, Input::Key(Q)), Ok(()));
+    b2.unbind_all(Action::Flip);
+    assert_eq!(b2.bind(Action::Pause, Input::Key(Space)), Ok(()));
+
+    // round trips through the file, hand edited clashes get found
+    assert_eq!(Bindings::from_conf(&parse_conf(&b2.to_conf())), b2);
+    let clash = Bindings::from_conf(&parse_conf("[bindings]\nflip = Space\npause = Space lmb\nrestart = Space"));
+    assert_eq!(clash.conflicts(), vec![(Action::Flip, Action::Pause, Input::Key(Space))]);
+
+    let statuses = b.resolve(|i| match i {
+        Input::Key(Space) => KeyStatus::Pressed,
+        Input::Lmb => KeyStatus::JustPressed,
+        _ => KeyStatus::Released,
+    });
+    assert_eq!(statuses.get(&Action::Flip), Some(&KeyStatus::JustPressed));
+    assert_eq!(statuses.get(&Action::Restart), Some(&KeyStatus::Pressed));
+    assert_eq!(statuses.get(&Action::Pause), None);
+}
@@ -0,0 +1,203 @@
+use crate::lib::kconf::*;
+
+// Frame animation. An Anim is a named list of atlas region names with a
+// duration each, an Animator plays one at a time and says which region to draw.
+// Defined in kconf:
+//
+// [anim]
+// name = roll
+// mode = loop              # loop, pingpong or once
+// frames = ball_0 ball_1 ball_2
+// durations = 0.08         # one for all of them, or one per frame
+// events = 0:flash 2:puff  # frame:event, fired when that frame comes up
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimMode {
+    Loop,
+    PingPong,
+    Once,   // holds the last frame
+}
+
+impl AnimMode {
+    pub fn from_name(s: &str) -> AnimMode {
+        match s {
+            "pingpong" => AnimMode::PingPong,
+            "once" => AnimMode::Once,
+            _ => AnimMode::Loop,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Anim {
+    pub name: String,
+    pub mode: AnimMode,
+    pub frames: Vec<String>,
+    pub durations: Vec<f32>,
+    pub events: Vec<(usize, String)>,
+}
+
+impl Anim {
+    pub fn from_conf(s: &ConfSection) -> Option<Anim> {
+        let frames: Vec<String> = s.get("frames")?.split_whitespace().map(|f| f.to_owned()).collect();
+        if frames.is_empty() {
+            return None;
+        }
+        let durations = match s.floats("durations").as_deref() {
+            Some([d]) => vec![*d; frames.len()],
+            Some(ds) if ds.len() == frames.len() => ds.to_vec(),
+            _ => vec![0.1; frames.len()],
+        };
+        let events = s.str_or("events", "").split_whitespace()
+            .filter_map(|e| {
+                let (frame, name) = e.split_once(':')?;
+                Some((frame.parse().ok()?, name.to_owned()))
+            })
+            .collect();
+        Some(Anim {
+            name: s.str_or("name", "").to_owned(),
+            mode: AnimMode::from_name(s.str_or("mode", "loop")),
+            frames,
+            durations,
+            events,
+        })
+    }
+
+    // frame index for the nth step since starting, and whether its finished
+    fn step_to_frame(&self, step: usize) -> (usize, bool) {
+        let n = self.frames.len();
+        match self.mode {
+            AnimMode::Loop => (step % n, false),
+            AnimMode::Once => (step.min(n - 1), step >= n - 1),
+            AnimMode::PingPong => {
+                if n < 2 {
+                    return (0, false);
+                }
+                let period = 2 * n - 2;
+                let i = step % period;
+                (if i < n {i} else {period - i}, false)
+            },
+        }
+    }
+}
+
+pub fn parse_anims(sections: &[ConfSection]) -> Vec<Anim> {
+    sections.iter().filter(|s| s.name == "anim").filter_map(Anim::from_conf).collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct Animator {
+    anims: Vec<Anim>,
+    current: usize,
+    step: usize,
+    t: f32,
+    pub speed: f32,
+}
+
+impl Animator {
+    pub fn new(anims: Vec<Anim>) -> Animator {
+        Animator {
+            anims,
+            current: 0,
+            step: 0,
+            t: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    pub fn playing(&self) -> Option<&str> {
+        self.anims.get(self.current).map(|a| a.name.as_str())
+    }
+
+    // restarts if its already playing, returns the first frames events
+    pub fn play(&mut self, name: &str) -> Vec<String> {
+        match self.anims.iter().position(|a| a.name == name) {
+            Some(i) => {
+                self.current = i;
+                self.step = 0;
+                self.t = 0.0;
+                self.events_for(0)
+            },
+            None => Vec::new(),
+        }
+    }
+
+    // region name to draw, None if theres nothing loaded
+    pub fn frame(&self) -> Option<&str> {
+        let a = self.anims.get(self.current)?;
+        Some(a.frames[a.step_to_frame(self.step).0].as_str())
+    }
+
+    pub fn finished(&self) -> bool {
+        self.anims.get(self.current).map(|a| a.step_to_frame(self.step).1).unwrap_or(true)
+    }
+
+    fn events_for(&self, frame: usize) -> Vec<String> {
+        match self.anims.get(self.current) {
+            Some(a) => a.events.iter().filter(|(f, _)| *f == frame).map(|(_, e)| e.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // advances and returns events for every frame that came up, in order
+    pub fn update(&mut self, dt: f32) -> Vec<String> {
+        let mut events = Vec::new();
+        if self.anims.get(self.current).is_none() {
+            return events;
+        }
+        self.t += dt * self.speed;
+        loop {
+            let a = &self.anims[self.current];
+            let (frame, done) = a.step_to_frame(self.step);
+            let d = a.durations[frame];
+            if done || self.t < d || d <= 0.0 {
+                break;
+            }
+            self.t -= d;
+            self.step += 1;
+            let frame = self.anims[self.current].step_to_frame(self.step).0;
+            events.extend(self.events_for(frame));
+        }
+        events
+    }
+}
+
+#[test]
+pub fn test_anim() {
+    let anims = parse_anims(&parse_conf("
+        [anim]
+        name = roll
+        frames = a b c
+        durations = 0.1
+        events = 2:puff
+        [anim]
+        name = bounce
+        mode = pingpong
+        frames = a b c
+        durations = 0.1 0.2 0.1
+        [anim]
+        name = hit
+        mode = once
+        frames = x y
+        events = 0:start 1:end
+    "));
+    assert_eq!(anims.len(), 3);
+    let mut a = Animator::new(anims);
+    assert_eq!(a.frame(), Some("a"));
+    assert_eq!(a.update(0.15), Vec::<String>::new());
+    assert_eq!(a.frame(), Some("b"));
+    // skipping over a whole frame still fires its events
+    assert_eq!(a.update(0.2), vec!["puff".to_owned()]);
+    assert_eq!(a.frame(), Some("a"));
+
+    a.play("bounce");
+    let frames: Vec<String> = (0..6).map(|_| {a.update(0.1); a.frame().unwrap().to_owned()}).collect();
+    assert_eq!(frames, vec!["b", "b", "c", "b", "b", "a"]);
+
+    assert_eq!(a.play("hit"), vec!["start".to_owned()]);
+    assert_eq!(a.update(1.0), vec!["end".to_owned()]);
+    assert!(a.finished());
+    assert_eq!(a.frame(), Some("y"));
+    assert!(a.play("nope").is_empty());
+    assert_eq!(a.playing(), Some("hit"));
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::lib::kmath::*;
+use crate::lib::kimg::*;
+use crate::lib::kconf::*;
+
+// Texture atlas packing. Takes a pile of named images, shelf packs them with
+// padding and gives back one image plus where everything went. The manifest
+// is a kconf file of pixel rects:
+//
+// size = 320 160
+// [region]
+// name = blank
+// rect = 0 0 16 8
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub w: usize,
+    pub h: usize,
+    pub regions: Vec<Region>,
+}
+
+impl Manifest {
+    pub fn uv(&self, r: &Region) -> Rect {
+        Rect::new(
+            r.x as f32 / self.w as f32,
+            r.y as f32 / self.h as f32,
+            r.w as f32 / self.w as f32,
+            r.h as f32 / self.h as f32,
+        )
+    }
+
+    pub fn uv_map(&self) -> HashMap<String, Rect> {
+        self.regions.iter().map(|r| (r.name.clone(), self.uv(r))).collect()
+    }
+
+    pub fn to_conf(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "# generated by gball pack-atlas, rects are x y w h in pixels").unwrap();
+        writeln!(s, "size = {} {}", self.w, self.h).unwrap();
+        for r in self.regions.iter() {
+            writeln!(s, "\n[region]\nname = {}\nrect = {} {} {} {}", r.name, r.x, r.y, r.w, r.h).unwrap();
+        }
+        s
+    }
+
+    pub fn from_conf(sections: &[ConfSection]) -> Option<Manifest> {
+        let size = sections.iter().find(|s| s.name.is_empty())?.floats("size")?;
+        if size.len() != 2 {
+            return None;
+        }
+        let regions = sections.iter()
+            .filter(|s| s.name == "region")
+            .filter_map(|s| {
+                let r = s.floats("rect")?;
+                if r.len() != 4 {
+                    return None;
+                }
+                Some(Region {
+                    name: s.get("name")?.to_owned(),
+                    x: r[0] as usize,
+                    y: r[1] as usize,
+                    w: r[2] as usize,
+                    h: r[3] as usize,
+                })
+            })
+            .collect();
+        Some(Manifest { w: size[0] as usize, h: size[1] as usize, regions })
+    }
+}
+
+fn blit(dst: &mut ImageBufferA, src: &ImageBufferA, x: usize, y: usize) {
+    for j in 0..src.h {
+        for i in 0..src.w {
+            dst.set_px(x + i, y + j, src.get_px(i, j));
+        }
+    }
+}
+
+// tallest first onto shelves. width is the next power of two that would make it
+// roughly square, height is whatever it takes
+pub fn pack(images: &[(String, ImageBufferA)], padding: usize) -> (ImageBufferA, Manifest) {
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(|a, b| images[*b].1.h.cmp(&images[*a].1.h).then(images[*a].0.cmp(&images[*b].0)));
+
+    let area: usize = images.iter().map(|(_, im)| (im.w + padding) * (im.h + padding)).sum();
+    let widest = images.iter().map(|(_, im)| im.w + 2 * padding).max().unwrap_or(1);
+    let w = ((area as f32).sqrt().ceil() as usize).max(widest).next_power_of_two();
+
+    let mut regions = Vec::new();
+    let (mut x, mut y, mut shelf_h) = (padding, padding, 0);
+    for i in order {
+        let (name, im) = &images[i];
+        if x + im.w + padding > w {
+            x = padding;
+            y += shelf_h + padding;
+            shelf_h = 0;
+        }
+        regions.push(Region { name: name.clone(), x, y, w: im.w, h: im.h });
+        x += im.w + padding;
+        shelf_h = shelf_h.max(im.h);
+    }
+    let h = (y + shelf_h + padding).max(1);
+
+    let mut atlas = ImageBufferA::new(w, h);
+    for r in regions.iter() {
+        let (_, im) = images.iter().find(|(n, _)| *n == r.name).unwrap();
+        blit(&mut atlas, im, r.x, r.y);
+    }
+    // keep the manifest in input order, easier to diff
+    regions.sort_by_key(|r| images.iter().position(|(n, _)| *n == r.name));
+    (atlas, Manifest { w, h, regions })
+}
+
+// packs images and sticks them under base, widening if it has to. base_regions
+// are uv in base, everything comes back as uv in the combined image
+pub fn append_sheet(base: &ImageBufferA, base_regions: &HashMap<String, Rect>, images: &[(String, ImageBufferA)], padding: usize) -> (ImageBufferA, HashMap<String, Rect>) {
+    let (sheet, manifest) = pack(images, padding);
+    let w = base.w.max(sheet.w);
+    let h = base.h + sheet.h;
+    let mut atlas = ImageBufferA::new(w, h);
+    blit(&mut atlas, base, 0, 0);
+    blit(&mut atlas, &sheet, 0, base.h);
+
+    let base_uv = Rect::new(0.0, 0.0, base.w as f32 / w as f32, base.h as f32 / h as f32);
+    let mut regions: HashMap<String, Rect> = base_regions.iter()
+        .map(|(k, r)| (k.clone(), r.transform(Rect::new(0.0, 0.0, 1.0, 1.0), base_uv)))
+        .collect();
+    for r in manifest.regions.iter() {
+        let uv = Rect::new(r.x as f32 / w as f32, (base.h + r.y) as f32 / h as f32, r.w as f32 / w as f32, r.h as f32 / h as f32);
+        regions.insert(r.name.clone(), uv);
+    }
+    (atlas, regions)
+}
+
+// every png in the dir, named by file stem
+pub fn load_png_dir(dir: &str) -> Result<Vec<(String, ImageBufferA)>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir, e))?;
+    let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "png").unwrap_or(false))
+        .collect();
+    paths.sort();
+    let mut images = Vec::new();
+    for p in paths {
+        let name = p.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_owned();
+        let im = ImageBufferA::new_from_file(p.to_str().unwrap_or(""))
+            .ok_or_else(|| format!("couldnt load {}", p.display()))?;
+        images.push((name, im));
+    }
+    Ok(images)
+}
+
+// pack-atlas <dir> <out>: writes <out>.png and <out>.manifest
+pub fn pack_dir(dir: &str, out: &str, padding: usize) -> Result<Manifest, String> {
+    let images = load_png_dir(dir)?;
+    if images.is_empty() {
+        return Err(format!("no pngs in {}", dir));
+    }
+    let (atlas, manifest) = pack(&images, padding);
+    atlas.dump_to_file(&format!("{}.png", out));
+    std::fs::write(format!("{}.manifest", out), manifest.to_conf()).map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+#[test]
+pub fn test_pack() {
+    let images = vec![
+        ("a".to_owned(), ImageBufferA::new(10, 4)),
+        ("b".to_owned(), ImageBufferA::new(3, 9)),
+        ("c".to_owned(), ImageBufferA::new(7, 7)),
+    ];
+    let (atlas, m) = pack(&images, 1);
+    assert_eq!((atlas.w, atlas.h), (m.w, m.h));
+    assert_eq!(m.regions.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+    // nothing overlaps, at least a pixel of padding between, all in bounds
+    for (i, a) in m.regions.iter().enumerate() {
+        assert!(a.x >= 1 && a.y >= 1 && a.x + a.w < m.w && a.y + a.h < m.h);
+        for b in m.regions.iter().skip(i + 1) {
+            let apart = a.x + a.w < b.x || b.x + b.w < a.x || a.y + a.h < b.y || b.y + b.h < a.y;
+            assert!(apart, "{:?} {:?}", a, b);
+        }
+    }
+    let round_trip = Manifest::from_conf(&parse_conf(&m.to_conf())).unwrap();
+    assert_eq!(round_trip.regions, m.regions);
+}
@@ -0,0 +1,101 @@
+use crate::lib::kmath::*;
+
+// Tiny ini-ish format so things can live in data files:
+//
+// # comment
+// [section]
+// key = value
+//
+// Sections can repeat, they stay in file order.
+
+#[derive(Clone, Debug)]
+pub struct ConfSection {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl ConfSection {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        // last one wins
+        self.entries.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+    pub fn str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).unwrap_or(default)
+    }
+    pub fn f32_or(&self, key: &str, default: f32) -> f32 {
+        self.get(key).and_then(|v| v.parse().ok()).unwrap_or(default)
+    }
+    pub fn bool_or(&self, key: &str, default: bool) -> bool {
+        match self.get(key) {
+            Some("true") | Some("yes") | Some("1") => true,
+            Some("false") | Some("no") | Some("0") => false,
+            _ => default,
+        }
+    }
+    pub fn floats(&self, key: &str) -> Option<Vec<f32>> {
+        let v = self.get(key)?;
+        v.split_whitespace().map(|x| x.parse().ok()).collect()
+    }
+    pub fn vec2_or(&self, key: &str, default: Vec2) -> Vec2 {
+        match self.floats(key).as_deref() {
+            Some([x, y]) => Vec2::new(*x, *y),
+            _ => default,
+        }
+    }
+    pub fn vec4_or(&self, key: &str, default: Vec4) -> Vec4 {
+        match self.floats(key).as_deref() {
+            Some([x, y, z]) => Vec4::new(*x, *y, *z, 1.0),
+            Some([x, y, z, w]) => Vec4::new(*x, *y, *z, *w),
+            _ => default,
+        }
+    }
+}
+
+pub fn parse_conf(s: &str) -> Vec<ConfSection> {
+    let mut sections = vec![ConfSection { name: String::new(), entries: Vec::new() }];
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push(ConfSection { name: line[1..line.len()-1].trim().to_owned(), entries: Vec::new() });
+        } else if let Some((k, v)) = line.split_once('=') {
+            sections.last_mut().unwrap().entries.push((k.trim().to_owned(), v.trim().to_owned()));
+        } else {
+            println!("conf: ignoring line '{}'", line);
+        }
+    }
+    // drop the implicit top section if nothing went in it
+    if sections[0].entries.is_empty() {
+        sections.remove(0);
+    }
+    sections
+}
+
+// first file that exists, otherwise the built in default
+pub fn load_conf(paths: &[&str], default: &str) -> Vec<ConfSection> {
+    for path in paths {
+        if let Ok(s) = std::fs::read_to_string(path) {
+            return parse_conf(&s);
+        }
+    }
+    parse_conf(default)
+}
+
+#[test]
+pub fn test_parse_conf() {
+    let sections = parse_conf("
+        top = 1
+        [layer] # trailing comment
+        speed = 0.5
+        tint = 0.1 0.2 0.3
+        [layer]
+        speed = nonsense
+    ");
+    assert_eq!(sections.len(), 3);
+    assert_eq!(sections[0].name, "");
+    assert_eq!(sections[1].f32_or("speed", 0.0), 0.5);
+    assert_eq!(sections[1].vec4_or("tint", Vec4::new(0.0, 0.0, 0.0, 0.0)), Vec4::new(0.1, 0.2, 0.3, 1.0));
+    assert_eq!(sections[2].f32_or("speed", 2.0), 2.0);
+}
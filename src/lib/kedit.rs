@@ -0,0 +1,228 @@
+use std::process::Command;
+
+use glutin::event::VirtualKeyCode;
+
+use crate::lib::kinput::*;
+
+// Single line text editing, for typing a name in and that sort of thing.
+// TextField is just the state, it eats FrameInputState::typed in order so fast
+// typing and held keys come out right. KRCanvas::text_field draws it.
+// Positions are in chars not bytes.
+
+pub trait Clipboard {
+    fn paste(&mut self) -> Option<String>;
+}
+
+// theres no clipboard crate so it asks whatever tool the platform has
+pub struct SystemClipboard;
+
+impl Clipboard for SystemClipboard {
+    fn paste(&mut self) -> Option<String> {
+        let tools: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+            &[("pbpaste", &[])]
+        } else if cfg!(windows) {
+            &[("powershell", &["-NoProfile", "-Command", "Get-Clipboard"])]
+        } else {
+            &[("wl-paste", &["--no-newline"]), ("xclip", &["-selection", "clipboard", "-o"]), ("xsel", &["-b", "-o"])]
+        };
+        tools.iter()
+            .filter_map(|(cmd, args)| Command::new(cmd).args(*args).output().ok())
+            .find(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+}
+
+// fixed contents, for tests
+impl Clipboard for String {
+    fn paste(&mut self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextField {
+    text: String,
+    cursor: usize,
+    anchor: Option<usize>,  // other end of the selection
+    pub max_len: usize,
+    pub focused: bool,
+}
+
+fn byte_index(s: &str, chars: usize) -> usize {
+    s.char_indices().nth(chars).map(|(i, _)| i).unwrap_or(s.len())
+}
+
+impl TextField {
+    pub fn new(max_len: usize) -> TextField {
+        TextField { text: String::new(), cursor: 0, anchor: None, max_len, focused: true }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // start and end, None if its empty
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        let a = self.anchor?;
+        if a == self.cursor {
+            return None;
+        }
+        Some((a.min(self.cursor), a.max(self.cursor)))
+    }
+
+    pub fn select_all(&mut self) {
+        self.anchor = Some(0);
+        self.cursor = self.len();
+    }
+
+    fn delete_selection(&mut self) -> bool {
+        match self.selection() {
+            Some((a, b)) => {
+                let (ba, bb) = (byte_index(&self.text, a), byte_index(&self.text, b));
+                self.text.replace_range(ba..bb, "");
+                self.cursor = a;
+                self.anchor = None;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // over the selection if there is one, cut short at max_len
+    pub fn insert(&mut self, s: &str) {
+        self.delete_selection();
+        self.anchor = None;
+        let room = self.max_len.saturating_sub(self.len());
+        let s: String = s.chars().filter(|c| !c.is_control()).take(room).collect();
+        let i = byte_index(&self.text, self.cursor);
+        self.text.insert_str(i, &s);
+        self.cursor += s.chars().count();
+    }
+
+    pub fn backspace(&mut self) {
+        if !self.delete_selection() && self.cursor > 0 {
+            self.cursor -= 1;
+            let i = byte_index(&self.text, self.cursor);
+            self.text.remove(i);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if !self.delete_selection() && self.cursor < self.len() {
+            let i = byte_index(&self.text, self.cursor);
+            self.text.remove(i);
+        }
+    }
+
+    // with shift held the selection goes along
+    pub fn move_to(&mut self, pos: usize, select: bool) {
+        if select {
+            self.anchor.get_or_insert(self.cursor);
+        } else {
+            self.anchor = None;
+        }
+        self.cursor = pos.min(self.len());
+    }
+
+    fn step(&mut self, right: bool, select: bool) {
+        // without shift an arrow just drops the selection at that end
+        if let (Some((a, b)), false) = (self.selection(), select) {
+            self.move_to(if right {b} else {a}, false);
+        } else if right {
+            self.move_to(self.cursor + 1, select);
+        } else {
+            self.move_to(self.cursor.saturating_sub(1), select);
+        }
+    }
+
+    // true when return was pressed
+    pub fn update(&mut self, inputs: &FrameInputState, clipboard: &mut dyn Clipboard) -> bool {
+        if !self.focused {
+            return false;
+        }
+        let mut submitted = false;
+        for t in inputs.typed.iter() {
+            match *t {
+                Typed::Char(c) => self.insert(&c.to_string()),
+                Typed::Key { key, ctrl, shift } => match key {
+                    VirtualKeyCode::Back => self.backspace(),
+                    VirtualKeyCode::Delete => self.delete(),
+                    VirtualKeyCode::Left => self.step(false, shift),
+                    VirtualKeyCode::Right => self.step(true, shift),
+                    VirtualKeyCode::Home => self.move_to(0, shift),
+                    VirtualKeyCode::End => self.move_to(self.len(), shift),
+                    VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => submitted = true,
+                    VirtualKeyCode::A if ctrl => self.select_all(),
+                    VirtualKeyCode::V if ctrl => {
+                        // first line only, its a single line field
+                        if let Some(s) = clipboard.paste() {
+                            self.insert(s.lines().next().unwrap_or(""));
+                        }
+                    },
+                    _ => {},
+                },
+            }
+        }
+        submitted
+    }
+}
+
+#[test]
+pub fn test_text_field() {
+    use glutin::event::{ElementState, Event, WindowEvent};
+    use VirtualKeyCode::*;
+
+    let key = key_event;
+    let ch = |c| window_event(WindowEvent::ReceivedCharacter(c));
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+    let mut clip = "pasted\nsecond line".to_owned();
+    let mut field = TextField::new(10);
+    let mut frame = |events: Vec<Event<()>>, field: &mut TextField, clip: &mut String| {
+        for e in events.iter() {
+            ea.handle_event(e);
+        }
+        let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+        (field.update(&inputs, clip), inputs.text)
+    };
+    use ElementState::{Pressed, Released};
+
+    // typing then fixing a typo, backspace held so it repeats, winit sends it as a char too
+    let (_, text) = frame(vec![
+        key(A, Pressed), ch('a'), key(A, Released), ch('b'), ch('x'), ch('y'),
+        key(Back, Pressed), ch('\u{8}'), key(Back, Pressed), ch('\u{8}'), key(Back, Released),
+        ch('é'), ch('c'),
+    ], &mut field, &mut clip);
+    assert_eq!(text, "abxyéc");
+    assert_eq!(field.text(), "abéc");
+    assert_eq!(field.cursor(), 4);
+
+    // shift left twice selects éc, typing replaces it
+    frame(vec![key(LShift, Pressed), key(Left, Pressed), key(Left, Pressed), key(Left, Released)], &mut field, &mut clip);
+    assert_eq!(field.selection(), Some((2, 4)));
+    frame(vec![key(LShift, Released), ch('d')], &mut field, &mut clip);
+    assert_eq!(field.text(), "abd");
+
+    // home, delete, paste only takes the first line and only what fits
+    frame(vec![key(Home, Pressed), key(Delete, Pressed)], &mut field, &mut clip);
+    assert_eq!(field.text(), "bd");
+    frame(vec![key(LControl, Pressed), key(V, Pressed), ch('\u{16}'), key(V, Released), key(LControl, Released)], &mut field, &mut clip);
+    assert_eq!(field.text(), "pastedbd");
+    field.insert("12345");
+    assert_eq!(field.text(), "pasted12bd");
+    assert_eq!(field.len(), field.max_len);
+
+    // ctrl a then backspace clears it, return submits
+    frame(vec![key(LControl, Pressed), key(A, Pressed), ch('\u{1}'), key(LControl, Released), key(Back, Pressed)], &mut field, &mut clip);
+    assert_eq!(field.text(), "");
+    let (submitted, _) = frame(vec![ch('z'), key(Return, Pressed), ch('\r')], &mut field, &mut clip);
+    assert!(submitted);
+    assert_eq!(field.text(), "z");
+}
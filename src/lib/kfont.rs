@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use crate::lib::kmath::*;
+use crate::lib::kimg::*;
+use crate::lib::ktext::*;
+
+// Bitmap font import. BDF (text) and PSF1/PSF2 (console fonts) get parsed into
+// BitmapFont, then packed into the atlas which gives back a Font with uv rects
+// and metrics in units of line height, which is what ktext lays out with.
+//
+// Kerning isnt in either format so it comes from an optional sidecar file
+// (foo.bdf -> foo.kern), lines of: <left char> <right char> <pixels>
+
+#[derive(Clone, Debug)]
+pub struct GlyphBitmap {
+    pub w: usize,
+    pub h: usize,
+    pub bits: Vec<bool>,    // row major, top row first
+    pub advance: i32,
+    pub x_off: i32,         // left edge relative to pen
+    pub y_off: i32,         // bottom edge relative to baseline, up is positive (bdf convention)
+}
+
+#[derive(Clone, Debug)]
+pub struct BitmapFont {
+    pub name: String,
+    pub ascent: i32,
+    pub descent: i32,
+    pub glyphs: HashMap<char, GlyphBitmap>,
+    pub kerning: HashMap<(char, char), i32>,
+}
+
+impl BitmapFont {
+    pub fn line_height(&self) -> i32 {
+        (self.ascent + self.descent).max(1)
+    }
+}
+
+/***************************************************
+ * BDF
+ ***************************************************/
+
+pub fn parse_bdf(name: &str, s: &str) -> Result<BitmapFont, String> {
+    let mut font = BitmapFont {
+        name: name.to_owned(),
+        ascent: 0,
+        descent: 0,
+        glyphs: HashMap::new(),
+        kerning: HashMap::new(),
+    };
+    let mut bbox_ascent = 0;
+    let mut lines = s.lines().map(|l| l.trim());
+    let ints = |rest: &[&str]| -> Result<Vec<i32>, String> {
+        rest.iter().map(|x| x.parse::<i32>().map_err(|e| format!("bdf: bad number '{}': {}", x, e))).collect()
+    };
+
+    if !lines.next().map(|l| l.starts_with("STARTFONT")).unwrap_or(false) {
+        return Err("bdf: missing STARTFONT".to_owned());
+    }
+
+    while let Some(line) = lines.next() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"FONT_ASCENT") => font.ascent = ints(&words[1..])?.first().copied().unwrap_or(0),
+            Some(&"FONT_DESCENT") => font.descent = ints(&words[1..])?.first().copied().unwrap_or(0),
+            Some(&"FONTBOUNDINGBOX") => {
+                let v = ints(&words[1..])?;
+                if v.len() == 4 {
+                    bbox_ascent = v[1] + v[3];
+                    if font.descent == 0 {
+                        font.descent = -v[3];
+                    }
+                }
+            },
+            Some(&"STARTCHAR") => {
+                let mut encoding: Option<u32> = None;
+                let mut glyph = GlyphBitmap { w: 0, h: 0, bits: Vec::new(), advance: 0, x_off: 0, y_off: 0 };
+                loop {
+                    let line = lines.next().ok_or("bdf: unterminated STARTCHAR")?;
+                    let words: Vec<&str> = line.split_whitespace().collect();
+                    match words.first() {
+                        Some(&"ENCODING") => encoding = ints(&words[1..])?.first().and_then(|e| u32::try_from(*e).ok()),
+                        Some(&"DWIDTH") => glyph.advance = ints(&words[1..])?.first().copied().unwrap_or(0),
+                        Some(&"BBX") => {
+                            let v = ints(&words[1..])?;
+                            if v.len() != 4 {
+                                return Err(format!("bdf: bad BBX '{}'", line));
+                            }
+                            glyph.w = v[0].max(0) as usize;
+                            glyph.h = v[1].max(0) as usize;
+                            glyph.x_off = v[2];
+                            glyph.y_off = v[3];
+                        },
+                        Some(&"BITMAP") => {
+                            for _ in 0..glyph.h {
+                                let row = lines.next().ok_or("bdf: short BITMAP")?;
+                                // bytes not str slices, a stray non ascii char would split a char boundary
+                                let row_bytes: Vec<u8> = row.as_bytes().chunks_exact(2)
+                                    .map(|pair| std::str::from_utf8(pair).ok().and_then(|h| u8::from_str_radix(h, 16).ok()))
+                                    .collect::<Option<_>>()
+                                    .ok_or_else(|| format!("bdf: bad bitmap row '{}'", row))?;
+                                for x in 0..glyph.w {
+                                    let byte = row_bytes.get(x / 8).copied().unwrap_or(0);
+                                    glyph.bits.push(byte & (0x80 >> (x % 8)) != 0);
+                                }
+                            }
+                        },
+                        Some(&"ENDCHAR") => break,
+                        _ => {},
+                    }
+                }
+                if glyph.advance == 0 {
+                    glyph.advance = glyph.w as i32;
+                }
+                // ENCODING -1 is unencoded, just skip those
+                if let Some(c) = encoding.and_then(char::from_u32) {
+                    font.glyphs.insert(c, glyph);
+                }
+            },
+            _ => {},
+        }
+    }
+    if font.ascent == 0 {
+        font.ascent = bbox_ascent;
+    }
+    if font.glyphs.is_empty() {
+        return Err("bdf: no glyphs".to_owned());
+    }
+    Ok(font)
+}
+
+/***************************************************
+ * PSF
+ ***************************************************/
+
+fn read_u32(b: &[u8], at: usize) -> Result<u32, String> {
+    b.get(at..at+4).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]])).ok_or_else(|| "psf: truncated header".to_owned())
+}
+
+fn psf_glyph(data: &[u8], w: usize, h: usize) -> GlyphBitmap {
+    let row_bytes = w.div_ceil(8);
+    let mut bits = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            bits.push(data[y * row_bytes + x / 8] & (0x80 >> (x % 8)) != 0);
+        }
+    }
+    GlyphBitmap { w, h, bits, advance: w as i32, x_off: 0, y_off: 0 }
+}
+
+pub fn parse_psf(name: &str, b: &[u8]) -> Result<BitmapFont, String> {
+    let (w, h, count, glyph_size, header_size, has_table, psf2) = if b.len() >= 4 && b[0..2] == [0x36, 0x04] {
+        let mode = b[2];
+        let count = if mode & 0x01 != 0 {512} else {256};
+        (8, b[3] as usize, count, b[3] as usize, 4, mode & 0x06 != 0, false)
+    } else if b.len() >= 32 && b[0..4] == [0x72, 0xb5, 0x4a, 0x86] {
+        let header_size = read_u32(b, 8)? as usize;
+        let flags = read_u32(b, 12)?;
+        let count = read_u32(b, 16)? as usize;
+        let glyph_size = read_u32(b, 20)? as usize;
+        let h = read_u32(b, 24)? as usize;
+        let w = read_u32(b, 28)? as usize;
+        (w, h, count, glyph_size, header_size, flags & 0x01 != 0, true)
+    } else {
+        return Err("psf: bad magic".to_owned());
+    };
+
+    // psf2 header fields are straight from the file so dont trust them not to overflow
+    let glyph_end = count.checked_mul(glyph_size).and_then(|n| n.checked_add(header_size));
+    let row_bytes = w.checked_add(7).map(|w| w / 8).and_then(|rb| rb.checked_mul(h));
+    let glyph_end = match (glyph_end, row_bytes) {
+        (Some(end), Some(rb)) if b.len() >= end && glyph_size >= rb => end,
+        _ => return Err("psf: truncated glyph data".to_owned()),
+    };
+
+    // which chars each glyph is for
+    let mut mapping: Vec<Vec<char>> = vec![Vec::new(); count];
+    if has_table {
+        let mut i = glyph_end;
+        let mut glyph = 0;
+        while glyph < count && i < b.len() {
+            if psf2 {
+                // utf8 sequences, 0xff ends the glyph, 0xfe starts combining sequences which we skip
+                let end = b[i..].iter().position(|x| *x == 0xff).map(|p| i + p).unwrap_or(b.len());
+                let entry = &b[i..end];
+                let singles = entry.split(|x| *x == 0xfe).next().unwrap_or(&[]);
+                if let Ok(s) = std::str::from_utf8(singles) {
+                    mapping[glyph].extend(s.chars());
+                }
+                i = end + 1;
+            } else {
+                // u16 le, 0xffff ends the glyph, 0xfffe starts sequences
+                let mut in_seq = false;
+                while i + 1 < b.len() {
+                    let v = u16::from_le_bytes([b[i], b[i+1]]);
+                    i += 2;
+                    if v == 0xffff {
+                        break;
+                    }
+                    if v == 0xfffe {
+                        in_seq = true;
+                    } else if !in_seq {
+                        if let Some(c) = char::from_u32(v as u32) {
+                            mapping[glyph].push(c);
+                        }
+                    }
+                }
+            }
+            glyph += 1;
+        }
+    } else {
+        for (i, m) in mapping.iter_mut().enumerate() {
+            if let Some(c) = char::from_u32(i as u32) {
+                m.push(c);
+            }
+        }
+    }
+
+    let mut font = BitmapFont {
+        name: name.to_owned(),
+        ascent: h as i32,
+        descent: 0,
+        glyphs: HashMap::new(),
+        kerning: HashMap::new(),
+    };
+    for (i, chars) in mapping.iter().enumerate() {
+        let start = header_size + i * glyph_size;
+        let glyph = psf_glyph(&b[start..start + glyph_size], w, h);
+        for c in chars {
+            font.glyphs.entry(*c).or_insert_with(|| glyph.clone());
+        }
+    }
+    Ok(font)
+}
+
+pub fn parse_kerning(s: &str) -> HashMap<(char, char), i32> {
+    let mut kerning = HashMap::new();
+    for line in s.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if let [a, b, px] = words[..] {
+            let mut a = a.chars();
+            let mut b = b.chars();
+            if let (Some(a), Some(b), Ok(px)) = (a.next(), b.next(), px.parse()) {
+                kerning.insert((a, b), px);
+            }
+        }
+    }
+    kerning
+}
+
+// picks the parser from the extension, pulls in a .kern file if theres one next to it
+pub fn load_font_file(path: &std::path::Path) -> Result<BitmapFont, String> {
+    let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("font");
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut font = match path.extension().and_then(|e| e.to_str()) {
+        Some("bdf") => parse_bdf(name, &String::from_utf8_lossy(&bytes))?,
+        Some("psf") | Some("psfu") => parse_psf(name, &bytes)?,
+        _ => return Err(format!("{}: not a bdf or psf", path.display())),
+    };
+    if let Ok(k) = std::fs::read_to_string(path.with_extension("kern")) {
+        font.kerning = parse_kerning(&k);
+    }
+    Ok(font)
+}
+
+pub fn load_font_dir(dirs: &[&str]) -> Vec<BitmapFont> {
+    for dir in dirs {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            paths.sort();
+            return paths.iter()
+                .filter(|p| p.extension().map(|e| e == "bdf" || e == "psf" || e == "psfu").unwrap_or(false))
+                .filter_map(|p| load_font_file(p).map_err(|e| println!("font: {}", e)).ok())
+                .collect();
+        }
+    }
+    Vec::new()
+}
+
+/***************************************************
+ * Packed into the atlas
+ ***************************************************/
+
+#[derive(Clone, Copy, Debug)]
+pub struct FontGlyph {
+    pub uv: Rect,
+    pub advance: f32,
+    pub bounds: Rect,   // relative to pen at the top of the line, in line heights
+}
+
+#[derive(Clone, Debug)]
+pub struct Font {
+    pub name: String,
+    pub line_px: f32,
+    pub glyphs: HashMap<char, FontGlyph>,
+    pub kerning: HashMap<(char, char), f32>,
+}
+
+impl GlyphMetrics for Font {
+    fn has_glyph(&self, c: char) -> bool {
+        self.glyphs.contains_key(&c)
+    }
+    fn advance(&self, c: char) -> f32 {
+        self.glyphs.get(&c).map(|g| g.advance).unwrap_or(0.0)
+    }
+    fn kern(&self, a: char, b: char) -> f32 {
+        self.kerning.get(&(a, b)).copied().unwrap_or(0.0)
+    }
+    fn line_px(&self) -> f32 {
+        self.line_px
+    }
+    fn bounds(&self, c: char) -> Rect {
+        self.glyphs.get(&c).map(|g| g.bounds).unwrap_or(Rect::new(0.0, 0.0, 0.0, 0.0))
+    }
+}
+
+// Shelf packs every glyph of every font into new rows under `base`, so the
+// atlas gets taller. Returns the new atlas, where base ended up in uv space, and the fonts.
+pub fn append_fonts(base: &ImageBufferA, fonts: &[BitmapFont]) -> (ImageBufferA, Rect, Vec<Font>) {
+    if fonts.is_empty() {
+        let mut atlas = ImageBufferA::new(base.w, base.h);
+        atlas.pixels.copy_from_slice(&base.pixels);
+        return (atlas, Rect::new(0.0, 0.0, 1.0, 1.0), Vec::new());
+    }
+    let pad = 1;
+    let w = base.w;
+
+    // first pass to work out positions
+    let mut placements: Vec<Vec<(char, usize, usize)>> = Vec::new();
+    let (mut x, mut y, mut shelf_h) = (pad, base.h + pad, 0);
+    for font in fonts {
+        let mut chars: Vec<&char> = font.glyphs.keys().collect();
+        chars.sort();
+        let mut placed = Vec::new();
+        for c in chars {
+            let g = &font.glyphs[c];
+            if x + g.w + pad > w {
+                x = pad;
+                y += shelf_h + pad;
+                shelf_h = 0;
+            }
+            placed.push((*c, x, y));
+            x += g.w + pad;
+            shelf_h = shelf_h.max(g.h);
+        }
+        placements.push(placed);
+    }
+    let h = y + shelf_h + pad;
+
+    let mut atlas = ImageBufferA::new(w, h);
+    for j in 0..base.h {
+        for i in 0..base.w {
+            atlas.set_px(i, j, base.get_px(i, j));
+        }
+    }
+
+    let mut out = Vec::new();
+    for (font, placed) in fonts.iter().zip(placements.iter()) {
+        let lh = font.line_height() as f32;
+        let mut glyphs = HashMap::new();
+        for (c, gx, gy) in placed {
+            let g = &font.glyphs[c];
+            for j in 0..g.h {
+                for i in 0..g.w {
+                    if g.bits[j * g.w + i] {
+                        atlas.set_px(gx + i, gy + j, (255, 255, 255, 255));
+                    }
+                }
+            }
+            let top = font.ascent - (g.y_off + g.h as i32);
+            glyphs.insert(*c, FontGlyph {
+                uv: Rect::new(*gx as f32 / w as f32, *gy as f32 / h as f32, g.w as f32 / w as f32, g.h as f32 / h as f32),
+                advance: g.advance as f32 / lh,
+                bounds: Rect::new(g.x_off as f32 / lh, top as f32 / lh, g.w as f32 / lh, g.h as f32 / lh),
+            });
+        }
+        out.push(Font {
+            name: font.name.clone(),
+            line_px: lh,
+            glyphs,
+            kerning: font.kerning.iter().map(|(k, v)| (*k, *v as f32 / lh)).collect(),
+        });
+    }
+
+    (atlas, Rect::new(0.0, 0.0, 1.0, base.h as f32 / h as f32), out)
+}
+
+#[test]
+pub fn test_font_import() {
+    let bdf = "STARTFONT 2.1
+FONTBOUNDINGBOX 4 6 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 5
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 1
+STARTCHAR A
+ENCODING 65
+DWIDTH 5 0
+BBX 4 5 0 0
+BITMAP
+60
+90
+F0
+90
+90
+ENDCHAR
+ENDFONT
+";
+    let f = parse_bdf("test", bdf).unwrap();
+    let a = &f.glyphs[&'A'];
+    assert_eq!((a.w, a.h, a.advance), (4, 5, 5));
+    assert_eq!(&a.bits[0..4], &[false, true, true, false]);
+    assert_eq!(f.line_height(), 6);
+
+    // psf1, 256 glyphs of 8x2, no unicode table
+    let mut psf = vec![0x36, 0x04, 0x00, 2];
+    for i in 0..256 {
+        psf.push(if i == 65 {0x81} else {0});
+        psf.push(0);
+    }
+    let p = parse_psf("psf", &psf).unwrap();
+    assert_eq!(p.glyphs[&'A'].bits[0..8], [true, false, false, false, false, false, false, true]);
+
+    // junk in a bitmap row or a psf2 header that overflows is an error not a panic
+    assert!(parse_bdf("bad", &bdf.replace("F0", "é")).is_err());
+    let mut psf2 = vec![0x72, 0xb5, 0x4a, 0x86, 0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0];
+    for v in [u32::MAX, u32::MAX, 8, 8] {
+        psf2.extend_from_slice(&v.to_le_bytes());
+    }
+    assert_eq!(parse_psf("bad", &psf2).err().as_deref(), Some("psf: truncated glyph data"));
+
+    let base = ImageBufferA::new(16, 16);
+    let (atlas, base_uv, fonts) = append_fonts(&base, &[f]);
+    assert!(atlas.h > 16);
+    assert_eq!(base_uv.h, 16.0 / atlas.h as f32);
+    let g = fonts[0].glyphs[&'A'];
+    assert_eq!(g.advance, 5.0 / 6.0);
+    assert_eq!(g.bounds.y, 0.0);
+}
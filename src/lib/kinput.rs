@@ -1,6 +1,10 @@
 use crate::lib::kmath::*;
+use crate::lib::kaction::*;
+use crate::lib::kpad::*;
+use crate::lib::ktouch::*;
 
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::{SystemTime, Instant, Duration};
 
 use glutin::event::VirtualKeyCode;
@@ -12,8 +16,17 @@ use glutin::event::WindowEvent::KeyboardInput;
 use glutin::event::WindowEvent::MouseInput;
 use glutin::event::WindowEvent::CursorMoved;
 use glutin::event::WindowEvent::Resized;
+use glutin::event::WindowEvent::ScaleFactorChanged;
+use glutin::event::WindowEvent::Touch as TouchInput;
+use glutin::event::WindowEvent::ReceivedCharacter;
+use glutin::event::WindowEvent::MouseWheel;
+use glutin::event::WindowEvent::CursorEntered;
+use glutin::event::WindowEvent::CursorLeft;
+use glutin::event::WindowEvent::Focused;
+use glutin::event::DeviceEvent;
+use glutin::event::MouseScrollDelta;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum KeyStatus {
     Pressed,
     JustPressed,
@@ -21,16 +34,92 @@ pub enum KeyStatus {
     Released,
 }
 
+// a press or release and exactly when, on the same clock as FrameInputState::t.
+// keys and buttons get the time their event arrived, pads the time they were polled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEdge {
+    pub input: Input,
+    pub pressed: bool,
+    pub t: f64,
+}
+
+// the same through the bindings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActionEdge {
+    pub action: Action,
+    pub pressed: bool,
+    pub t: f64,
+}
+
+// text typing in the order it happened. chars are whatever the os made of
+// the keys (so layouts, dead keys and ime commits are right), keys are every
+// press including os repeats, for editing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Typed {
+    Char(char),
+    Key { key: VirtualKeyCode, ctrl: bool, shift: bool },   // modifiers as they were when it went down
+}
+
+// what the game wants the pointer doing, the application applies it to the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CursorMode {
+    pub visible: bool,
+    pub grab: bool,     // kept in the window, for things that only read raw_mouse_delta
+}
+
+impl Default for CursorMode {
+    fn default() -> CursorMode {
+        CursorMode { visible: true, grab: false }
+    }
+}
+
+// back and forward come through as Other with a different number on every
+// platform: 1 2 on windows, 3 4 on mac, 8 9 on x11, BTN_SIDE BTN_EXTRA on wayland
+pub fn mouse_button_input(b: MouseButton) -> Input {
+    match b {
+        MouseButton::Left => Input::Lmb,
+        MouseButton::Right => Input::Rmb,
+        MouseButton::Middle => Input::Mmb,
+        MouseButton::Other(n) => match n {
+            1 | 3 | 8 | 275 => Input::MouseBack,
+            2 | 4 | 9 | 276 => Input::MouseForward,
+            n => Input::Mouse(n),
+        },
+    }
+}
+
 #[derive(Clone)]
 pub struct FrameInputState {
-    pub screen_rect: Rect,
+    pub screen_rect: Rect,  // the playfield, same size whatever the window is
+    pub window_rect: Rect,  // the whole window in playfield coordinates, bigger on one axis
+    pub pixels: Vec2,       // physical size of the window
+    pub scale_factor: f64,  // physical pixels per logical pixel, 2.0 on a retina screen
     pub mouse_pos: Vec2,
     pub mouse_delta: Vec2,
 
     pub keys: HashMap<VirtualKeyCode, KeyStatus>,
+    pub actions: HashMap<Action, KeyStatus>,   // from keys and buttons through the bindings
+    pub bindings: Rc<Bindings>,
     pub lmb: KeyStatus,
     pub rmb: KeyStatus,
     pub mmb: KeyStatus,
+    pub mouse_buttons: HashMap<Input, KeyStatus>,   // back, forward and the rest past the first three
+    pub scroll_lines: Vec2,     // wheel notches this frame, positive y is away from you like winit has it
+    pub scroll_pixels: Vec2,    // touchpads and smooth wheels, physical pixels
+    pub raw_mouse_delta: Vec2,  // straight off the device, no acceleration, keeps going at the window edge or grabbed
+    pub cursor_inside: bool,
+    pub cursor_entered: bool,   // this frame
+    pub cursor_left: bool,
+    pub focus: Option<bool>,    // the window lost or got back focus this frame
+    pub pads: Vec<PadState>,    // everything seen since startup in the order they turned up, unplugged ones stay
+    pub pads_connected: Vec<PadId>,     // this frame
+    pub pads_disconnected: Vec<PadId>,
+    pub text: String,           // printable chars typed this frame
+    pub typed: Vec<Typed>,
+    pub touches: Vec<Touch>,            // fingers down, plus ones that lifted this frame
+    pub gestures: Vec<Gesture>,
+    pub edges: Vec<InputEdge>,          // everything that went up or down since last frame, in order
+    pub action_edges: Vec<ActionEdge>,
     pub t: f64,
     pub dt: f64,
     pub frame: u32,
@@ -38,53 +127,247 @@ pub struct FrameInputState {
 }
 
 impl FrameInputState {
+    // a tap that goes down and up inside one frame still counts
     pub fn just_pressed(&self, keycode: VirtualKeyCode) -> bool {
+        if self.press_count(Input::Key(keycode)) > 0 {
+            return true;
+        }
         if let Some(result) = self.keys.get(&keycode) {
             return *result == KeyStatus::JustPressed
         }
         return false;
     }
+    pub fn just_released(&self, keycode: VirtualKeyCode) -> bool {
+        self.release_count(Input::Key(keycode)) > 0 || self.keys.get(&keycode) == Some(&KeyStatus::JustReleased)
+    }
+    // how many times it went down or up since last frame, none are lost however quick
+    pub fn press_count(&self, input: Input) -> u32 {
+        self.edges.iter().filter(|e| e.input == input && e.pressed).count() as u32
+    }
+    pub fn release_count(&self, input: Input) -> u32 {
+        self.edges.iter().filter(|e| e.input == input && !e.pressed).count() as u32
+    }
     pub fn pressed(&self, keycode: VirtualKeyCode) -> bool {
         if let Some(result) = self.keys.get(&keycode) {
             return *result == KeyStatus::JustPressed || *result == KeyStatus::Pressed
         }
         return false;
     }
+    pub fn input_status(&self, input: Input) -> KeyStatus {
+        match input {
+            Input::Key(k) => self.keys.get(&k).copied().unwrap_or(KeyStatus::Released),
+            Input::Lmb => self.lmb,
+            Input::Rmb => self.rmb,
+            Input::Mmb => self.mmb,
+            Input::MouseBack | Input::MouseForward | Input::Mouse(_) => self.mouse_buttons.get(&input).copied().unwrap_or(KeyStatus::Released),
+            // any pad will do
+            Input::Pad(b) => self.pads.iter().map(|p| p.button(b)).max_by_key(|s| status_rank(*s)).unwrap_or(KeyStatus::Released),
+            // gestures are over as soon as theyre recognised, theres no holding one
+            Input::Touch(_) | Input::WheelUp | Input::WheelDown => if self.press_count(input) > 0 {KeyStatus::JustPressed} else {KeyStatus::Released},
+            Input::TouchDown => if self.press_count(input) > 0 {
+                KeyStatus::JustPressed
+            } else if self.touches.iter().any(|t| matches!(t.phase, TouchPhase::Moved | TouchPhase::Held)) {
+                KeyStatus::Pressed
+            } else if self.release_count(input) > 0 {
+                KeyStatus::JustReleased
+            } else {
+                KeyStatus::Released
+            },
+        }
+    }
+    pub fn pad(&self, id: PadId) -> Option<&PadState> {
+        self.pads.iter().find(|p| p.id == id)
+    }
+    pub fn action(&self, a: Action) -> KeyStatus {
+        self.actions.get(&a).copied().unwrap_or(KeyStatus::Released)
+    }
+    pub fn action_press_count(&self, a: Action) -> u32 {
+        let n = self.action_edges.iter().filter(|e| e.action == a && e.pressed).count() as u32;
+        if n == 0 && self.action(a) == KeyStatus::JustPressed {1} else {n}
+    }
+    pub fn action_just_pressed(&self, a: Action) -> bool {
+        self.action_press_count(a) > 0
+    }
+    pub fn action_pressed(&self, a: Action) -> bool {
+        matches!(self.action(a), KeyStatus::JustPressed | KeyStatus::Pressed)
+    }
+    // when a was pressed this frame. inputs made without timings (old
+    // recordings, tests) say it was at the start of the frame
+    pub fn presses(&self, a: Action) -> Vec<f64> {
+        let ts: Vec<f64> = self.action_edges.iter().filter(|e| e.action == a && e.pressed).map(|e| e.t).collect();
+        if ts.is_empty() && self.action_just_pressed(a) {
+            return vec![self.t - self.dt];
+        }
+        ts
+    }
+    // the first bindable thing that went down this frame, for rebinding
+    pub fn any_just_pressed(&self) -> Option<Input> {
+        self.edges.iter().find(|e| e.pressed && e.input.bindable()).map(|e| e.input)
+    }
+}
+
+// The playfield is 0..aspect wide and 0..1 tall, centred in the window with
+// bars on whichever side is left over. Returns the window in those coordinates.
+pub fn letterbox(xres: f32, yres: f32, aspect: f32) -> Rect {
+    let window_aspect = xres / yres.max(1.0);
+    if window_aspect > aspect {
+        // pillarbox
+        Rect::new((aspect - window_aspect) / 2.0, 0.0, window_aspect, 1.0)
+    } else {
+        // letterbox
+        let h = aspect / window_aspect.max(0.0001);
+        Rect::new(0.0, (1.0 - h) / 2.0, aspect, h)
+    }
 }
 
-// Its basically just a state machine to go from events to polling behaviour
+// Its basically just a state machine to go from events to polling behaviour.
+// Everything in here is physical pixels, winit gives us cursor positions and
+// sizes in physical already, so the scale factor only matters to whoever wants it.
 pub struct EventAggregator {
     xres: f32,
     yres: f32,
+    aspect: f32,
     t_last: Instant,
     instant_mouse_pos: Vec2,
+    touch: TouchTracker,
+    pad_backend: Option<Box<dyn PadBackend>>,
+    stick_deadzone: Deadzone,
+    trigger_deadzone: Deadzone,
     current: FrameInputState,
 }
 
 impl EventAggregator {
-    pub fn new(xres: f32, yres: f32) -> EventAggregator {
+    pub fn new(xres: f32, yres: f32, scale_factor: f64, aspect: f32) -> EventAggregator {
         EventAggregator { 
             xres, 
             yres, 
+            aspect,
             t_last: Instant::now(),
             instant_mouse_pos: Vec2::new(0.0, 0.0),
+            touch: TouchTracker::new(),
+            pad_backend: None,
+            stick_deadzone: STICK_DEADZONE,
+            trigger_deadzone: TRIGGER_DEADZONE,
             current: FrameInputState { 
-                screen_rect: Rect::new(0.0, 0.0, xres/yres, 1.0, ), 
+                screen_rect: Rect::new(0.0, 0.0, aspect, 1.0, ), 
+                window_rect: letterbox(xres, yres, aspect),
+                pixels: Vec2::new(xres, yres),
+                scale_factor,
                 mouse_pos: Vec2::new(0.0, 0.0), 
                 mouse_delta: Vec2::new(0.0, 0.0), 
                 keys: HashMap::new(),
+                actions: HashMap::new(),
+                bindings: Rc::new(Bindings::builtin()),
                 lmb: KeyStatus::Released, 
                 rmb: KeyStatus::Released, 
                 mmb: KeyStatus::Released, 
+                mouse_buttons: HashMap::new(),
+                scroll_lines: Vec2::new(0.0, 0.0),
+                scroll_pixels: Vec2::new(0.0, 0.0),
+                raw_mouse_delta: Vec2::new(0.0, 0.0),
+                cursor_inside: true,
+                cursor_entered: false,
+                cursor_left: false,
+                focus: None,
+                pads: Vec::new(),
+                pads_connected: Vec::new(),
+                pads_disconnected: Vec::new(),
+                text: String::new(),
+                typed: Vec::new(),
+                touches: Vec::new(),
+                gestures: Vec::new(),
+                edges: Vec::new(),
+                action_edges: Vec::new(),
                 t: 0.0,
                 dt: 0.0,
                 frame: 0,
                 seed: SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::from_nanos(34123123)).subsec_nanos(),
+            },
+        }
+    }
+
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.current.bindings = Rc::new(bindings);
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.current.bindings
+    }
+
+    pub fn set_pad_backend(&mut self, backend: Box<dyn PadBackend>) {
+        self.pad_backend = Some(backend);
+    }
+
+    // pads only get polled once a frame so a press could have been any time
+    // in it. say it was at the start, same as untimed inputs, so it lands in
+    // this frame rather than waiting for the next
+    fn poll_pads(&mut self) {
+        let t = self.current.t - self.current.dt;
+        let events = match self.pad_backend.as_mut() {
+            Some(b) => b.poll(),
+            None => return,
+        };
+        for e in events.iter() {
+            let id = match e {
+                PadEvent::Connected { id, .. } | PadEvent::Button { id, .. } | PadEvent::Axis { id, .. } => *id,
+                PadEvent::Disconnected(id) => *id,
+            };
+            match e {
+                PadEvent::Connected { .. } => self.current.pads_connected.push(id),
+                PadEvent::Disconnected(_) => self.current.pads_disconnected.push(id),
+                _ => {},
+            }
+            let i = match self.current.pads.iter().position(|p| p.id == id) {
+                Some(i) => i,
+                None => {
+                    self.current.pads.push(PadState::new(id, ""));
+                    self.current.pads.len() - 1
+                },
+            };
+            let pad = &mut self.current.pads[i];
+            let before: Vec<(PadButton, KeyStatus)> = pad.buttons.iter().map(|(b, s)| (*b, *s)).collect();
+            pad.apply(e);
+            for (b, s) in pad.buttons.iter() {
+                let was = before.iter().find(|(bb, _)| bb == b).map(|(_, s)| *s).unwrap_or(KeyStatus::Released);
+                if *s != was {
+                    let pressed = *s == KeyStatus::JustPressed;
+                    self.current.edges.push(InputEdge { input: Input::Pad(*b), pressed, t });
+                }
             }
         }
+        for p in self.current.pads.iter_mut() {
+            p.apply_deadzones(self.stick_deadzone, self.trigger_deadzone);
+        }
+    }
+
+    fn resize(&mut self, xres: f32, yres: f32) {
+        self.xres = xres;
+        self.yres = yres;
+        self.current.pixels = Vec2::new(xres, yres);
+        self.current.window_rect = letterbox(xres, yres, self.aspect);
     }
 
     pub fn handle_event(&mut self, event: &Event<()>) -> Option<FrameInputState> {
+        self.handle_event_at(event, Instant::now())
+    }
+
+    fn event_t(&self, now: Instant) -> f64 {
+        self.current.t + now.saturating_duration_since(self.t_last).as_secs_f64()
+    }
+
+    fn edge(&mut self, input: Input, pressed: bool, now: Instant) {
+        let t = self.event_t(now);
+        self.current.edges.push(InputEdge { input, pressed, t });
+    }
+
+    // physical pixels to playfield coordinates
+    fn to_playfield(&self, x: f64, y: f64) -> Vec2 {
+        let w = self.current.window_rect;
+        Vec2::new(w.x + x as f32 / self.xres * w.w, w.y + y as f32 / self.yres * w.h)
+    }
+
+    // now is when the event happened, so tests can make up their own timings
+    pub fn handle_event_at(&mut self, event: &Event<()>, now: Instant) -> Option<FrameInputState> {
         match event {
             Event::WindowEvent {event, ..} => match event {
                 KeyboardInput { 
@@ -94,11 +377,18 @@ impl EventAggregator {
                     ..},
                 ..} => {
                     if *state == ElementState::Pressed {
-                        if !self.current.keys.contains_key(virtual_code) { // should filter out repeats
+                        let held = |k| matches!(self.current.keys.get(&k), Some(KeyStatus::Pressed | KeyStatus::JustPressed));
+                        let ctrl = held(VirtualKeyCode::LControl) || held(VirtualKeyCode::RControl) || held(VirtualKeyCode::LWin) || held(VirtualKeyCode::RWin);
+                        let shift = held(VirtualKeyCode::LShift) || held(VirtualKeyCode::RShift);
+                        self.current.typed.push(Typed::Key { key: *virtual_code, ctrl, shift });
+                        // should filter out repeats. released this frame isnt held, that ones a second tap
+                        if !matches!(self.current.keys.get(virtual_code), Some(KeyStatus::Pressed | KeyStatus::JustPressed)) {
                             self.current.keys.insert(*virtual_code, KeyStatus::JustPressed);
+                            self.edge(Input::Key(*virtual_code), true, now);
                         }
                     } else {
                         self.current.keys.insert(*virtual_code, KeyStatus::JustReleased);
+                        self.edge(Input::Key(*virtual_code), false, now);
                     }
                 },
 
@@ -109,6 +399,7 @@ impl EventAggregator {
                     } else {
                         self.current.lmb = KeyStatus::JustReleased;
                     }
+                    self.edge(Input::Lmb, *state == ElementState::Pressed, now);
                 },
                 MouseInput { button: glutin::event::MouseButton::Middle, state, ..} => {
                     if *state == ElementState::Pressed {
@@ -116,6 +407,7 @@ impl EventAggregator {
                     } else {
                         self.current.mmb = KeyStatus::JustReleased;
                     }
+                    self.edge(Input::Mmb, *state == ElementState::Pressed, now);
                 },
                 MouseInput { button: glutin::event::MouseButton::Right, state, ..} => {
                     if *state == ElementState::Pressed {
@@ -123,6 +415,47 @@ impl EventAggregator {
                     } else {
                         self.current.rmb = KeyStatus::JustReleased;
                     }
+                    self.edge(Input::Rmb, *state == ElementState::Pressed, now);
+                },
+                MouseInput { button, state, ..} => {
+                    let input = mouse_button_input(*button);
+                    if *state == ElementState::Pressed {
+                        self.current.mouse_buttons.insert(input, KeyStatus::JustPressed);
+                    } else {
+                        self.current.mouse_buttons.insert(input, KeyStatus::JustReleased);
+                    }
+                    self.edge(input, *state == ElementState::Pressed, now);
+                },
+
+                // one wheel_up or wheel_down per event whatever the size, for menus
+                MouseWheel { delta, .. } => {
+                    let y = match delta {
+                        MouseScrollDelta::LineDelta(x, y) => {
+                            self.current.scroll_lines = self.current.scroll_lines + Vec2::new(*x, *y);
+                            *y as f64
+                        },
+                        MouseScrollDelta::PixelDelta(p) => {
+                            self.current.scroll_pixels = self.current.scroll_pixels + Vec2::new(p.x as f32, p.y as f32);
+                            p.y
+                        },
+                    };
+                    if y != 0.0 {
+                        let input = if y > 0.0 {Input::WheelUp} else {Input::WheelDown};
+                        self.edge(input, true, now);
+                        self.edge(input, false, now);
+                    }
+                },
+
+                CursorEntered { .. } => {
+                    self.current.cursor_inside = true;
+                    self.current.cursor_entered = true;
+                },
+                CursorLeft { .. } => {
+                    self.current.cursor_inside = false;
+                    self.current.cursor_left = true;
+                },
+                Focused(f) => {
+                    self.current.focus = Some(*f);
                 },
 
 
@@ -131,14 +464,54 @@ impl EventAggregator {
                     position: pos,
                     ..
                 } => {
-                    self.instant_mouse_pos = Vec2::new(pos.x as f32 / self.yres, pos.y as f32 / self.yres);
+                    self.instant_mouse_pos = self.to_playfield(pos.x, pos.y);
+                },
+
+                // ime commits come through here too, winit 0.26 has no preedit events
+                ReceivedCharacter(c) if !c.is_control() => {
+                    self.current.text.push(*c);
+                    self.current.typed.push(Typed::Char(*c));
+                },
+
+                TouchInput(touch) => {
+                    let pos = self.to_playfield(touch.location.x, touch.location.y);
+                    let t = self.event_t(now);
+                    match touch.phase {
+                        glutin::event::TouchPhase::Started => {
+                            if self.touch.down(touch.id) {
+                                self.edge(Input::TouchDown, false, now);
+                            }
+                            self.touch.began(touch.id, pos, t);
+                            self.edge(Input::TouchDown, true, now);
+                        },
+                        glutin::event::TouchPhase::Moved => self.touch.moved(touch.id, pos),
+                        glutin::event::TouchPhase::Ended => {
+                            if self.touch.down(touch.id) {
+                                self.edge(Input::TouchDown, false, now);
+                            }
+                            if let Some(g) = self.touch.ended(touch.id, pos, t) {
+                                self.current.gestures.push(g);
+                                self.edge(Input::Touch(g.kind), true, now);
+                                self.edge(Input::Touch(g.kind), false, now);
+                            }
+                        },
+                        glutin::event::TouchPhase::Cancelled => {
+                            if self.touch.down(touch.id) {
+                                self.edge(Input::TouchDown, false, now);
+                            }
+                            self.touch.cancelled(touch.id);
+                        },
+                    }
                 },
 
                 // Resize
                 Resized(physical_size) => {
-                    self.xres = physical_size.width as f32;
-                    self.yres = physical_size.height as f32;
-                    self.current.screen_rect = Rect::new(0.0, 0.0, self.xres / self.yres, 1.0);
+                    self.resize(physical_size.width as f32, physical_size.height as f32);
+                },
+                // moved to a monitor with a different dpi, the window gets resized to match
+                ScaleFactorChanged { scale_factor, new_inner_size } => {
+                    self.current.scale_factor = *scale_factor;
+                    self.resize(new_inner_size.width as f32, new_inner_size.height as f32);
                 },
 
 
@@ -146,17 +519,44 @@ impl EventAggregator {
                 _ => {},
                 
             },
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta: (x, y) }, .. } => {
+                self.current.raw_mouse_delta = self.current.raw_mouse_delta + Vec2::new(*x as f32, *y as f32);
+            },
             Event::MainEventsCleared => {
-                let t_now = Instant::now();
-                let dt = t_now.duration_since(self.t_last).as_secs_f64();
+                let t_now = now;
+                let dt = t_now.saturating_duration_since(self.t_last).as_secs_f64();
                 self.current.dt = dt;
                 self.current.t += dt;
                 self.t_last = t_now;
                 self.current.frame += 1;
                 self.current.mouse_delta = self.instant_mouse_pos - self.current.mouse_pos;
                 self.current.mouse_pos = self.instant_mouse_pos;
+                self.poll_pads();
+                self.current.touches = self.touch.touches().to_vec();
+                self.current.actions = self.current.bindings.resolve(|i| self.current.input_status(i));
+                let bindings = self.current.bindings.clone();
+                self.current.action_edges = self.current.edges.iter()
+                    .flat_map(|e| bindings.actions_for(e.input).into_iter().map(move |action| ActionEdge { action, pressed: e.pressed, t: e.t }))
+                    .collect();
                 let state = self.current.clone();
-                self.current.seed = khash(self.current.seed * 196513497);
+                self.current.edges.clear();
+                self.current.gestures.clear();
+                self.current.text.clear();
+                self.current.typed.clear();
+                self.current.scroll_lines = Vec2::new(0.0, 0.0);
+                self.current.scroll_pixels = Vec2::new(0.0, 0.0);
+                self.current.raw_mouse_delta = Vec2::new(0.0, 0.0);
+                self.current.cursor_entered = false;
+                self.current.cursor_left = false;
+                self.current.focus = None;
+                self.current.mouse_buttons.retain(|_, v| *v != KeyStatus::JustReleased);
+                for v in self.current.mouse_buttons.values_mut() {
+                    if *v == KeyStatus::JustPressed {
+                        *v = KeyStatus::Pressed;
+                    }
+                }
+                self.touch.advance();
+                self.current.seed = khash(self.current.seed.wrapping_mul(196513497));
                 self.current.keys.retain(|k, v| match v {KeyStatus::JustReleased => false, _ => true});
                 for (k, v) in self.current.keys.iter_mut() {
                     match v {
@@ -169,6 +569,11 @@ impl EventAggregator {
                 self.current.lmb = match self.current.lmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
                 self.current.mmb = match self.current.mmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
                 self.current.rmb = match self.current.rmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
+                for p in self.current.pads.iter_mut() {
+                    p.advance();
+                }
+                self.current.pads_connected.clear();
+                self.current.pads_disconnected.clear();
 
                 return Some(state);
             },
@@ -177,4 +582,186 @@ impl EventAggregator {
 
         None
     }
-}
\ No newline at end of file
+}
+
+// for tests, events as if winit sent them from a window and keyboard that dont exist
+#[cfg(test)]
+pub fn test_device() -> glutin::event::DeviceId {
+    unsafe { glutin::event::DeviceId::dummy() }
+}
+
+#[cfg(test)]
+pub fn window_event(event: glutin::event::WindowEvent) -> Event<()> {
+    Event::WindowEvent { window_id: unsafe { glutin::window::WindowId::dummy() }, event }
+}
+
+#[cfg(test)]
+#[allow(deprecated)]
+pub fn key_event(k: VirtualKeyCode, state: ElementState) -> Event<'static, ()> {
+    window_event(KeyboardInput {
+        device_id: test_device(),
+        input: glutin::event::KeyboardInput { scancode: 0, state, virtual_keycode: Some(k), modifiers: glutin::event::ModifiersState::empty() },
+        is_synthetic: false,
+    })
+}
+
+#[test]
+pub fn test_letterbox() {
+    // exact fit, ultrawide and portrait all keep the playfield whole and centred
+    assert_eq!(letterbox(1600.0, 900.0, 16.0/9.0), Rect::new(0.0, 0.0, 16.0/9.0, 1.0));
+    let wide = letterbox(3440.0, 1440.0, 16.0/9.0);
+    assert_eq!(wide.h, 1.0);
+    assert!((wide.centroid().x - 8.0/9.0).abs() < 0.0001);
+    let tall = letterbox(900.0, 1600.0, 16.0/9.0);
+    assert_eq!(tall.w, 16.0/9.0);
+    assert!((tall.centroid().y - 0.5).abs() < 0.0001);
+    assert!(tall.h > 3.0);
+}
+
+#[test]
+#[allow(deprecated)]
+pub fn test_scale_factor_change() {
+    use glutin::dpi::{PhysicalPosition, PhysicalSize};
+    use glutin::event::ModifiersState;
+
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+
+    // dragged onto a 2x monitor
+    let mut size = PhysicalSize::new(3200, 1800);
+    ea.handle_event(&window_event(ScaleFactorChanged { scale_factor: 2.0, new_inner_size: &mut size }));
+    let centre = PhysicalPosition::new(1600.0, 900.0);
+    ea.handle_event(&window_event(CursorMoved { device_id: test_device(), position: centre, modifiers: ModifiersState::empty() }));
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert_eq!(inputs.scale_factor, 2.0);
+    assert_eq!(inputs.pixels, Vec2::new(3200.0, 1800.0));
+    assert_eq!(inputs.screen_rect, Rect::new(0.0, 0.0, 16.0/9.0, 1.0));
+    assert!((inputs.mouse_pos - inputs.screen_rect.centroid()).magnitude() < 0.0001);
+}
+
+#[test]
+pub fn test_event_timestamps() {
+    let key = key_event;
+    let ms = |n: u64| Duration::from_millis(n);
+
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+    let t0 = Instant::now();
+    ea.handle_event_at(&Event::MainEventsCleared, t0);
+    ea.handle_event_at(&key(VirtualKeyCode::Space, ElementState::Pressed), t0 + ms(3));
+    ea.handle_event_at(&key(VirtualKeyCode::Space, ElementState::Pressed), t0 + ms(5));   // repeat
+    ea.handle_event_at(&key(VirtualKeyCode::P, ElementState::Pressed), t0 + ms(11));
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + ms(16)).unwrap();
+    let start = inputs.t - inputs.dt;
+    assert!((inputs.dt - 0.016).abs() < 1e-9);
+    assert_eq!(inputs.edges.len(), 2);
+    assert!((inputs.edges[0].t - start - 0.003).abs() < 1e-9);
+    // space is flip (and restart and confirm), p is pause
+    let flips = inputs.presses(Action::Flip);
+    assert_eq!(flips.len(), 1);
+    assert!((flips[0] - start - 0.003).abs() < 1e-9);
+    assert!((inputs.presses(Action::Pause)[0] - start - 0.011).abs() < 1e-9);
+    assert_eq!(inputs.action_edges.iter().filter(|e| e.action == Action::Restart).count(), 1);
+
+    // held over the frame boundary, released partway through the next
+    ea.handle_event_at(&key(VirtualKeyCode::Space, ElementState::Released), t0 + ms(20));
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + ms(33)).unwrap();
+    assert!(inputs.presses(Action::Flip).is_empty());
+    let up = inputs.action_edges.iter().find(|e| e.action == Action::Flip).unwrap();
+    assert!(!up.pressed);
+    assert!((up.t - (inputs.t - inputs.dt) - 0.004).abs() < 1e-9);
+}
+
+#[test]
+pub fn test_taps_within_a_frame() {
+    use glutin::event::ModifiersState;
+
+    let key = key_event;
+    #[allow(deprecated)]
+    let mouse = |button, state| window_event(MouseInput { device_id: test_device(), state, button, modifiers: ModifiersState::empty() });
+    use ElementState::{Pressed, Released};
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+
+    // two taps on space and one on lmb before the frame ends
+    for e in [
+        key(VirtualKeyCode::Space, Pressed), key(VirtualKeyCode::Space, Pressed), key(VirtualKeyCode::Space, Released),
+        key(VirtualKeyCode::Space, Pressed), key(VirtualKeyCode::Space, Released),
+        mouse(MouseButton::Left, Pressed), mouse(MouseButton::Left, Released),
+    ] {
+        assert!(ea.handle_event(&e).is_none());
+    }
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert!(inputs.just_pressed(VirtualKeyCode::Space));
+    assert!(inputs.just_released(VirtualKeyCode::Space));
+    assert!(!inputs.pressed(VirtualKeyCode::Space));
+    assert_eq!(inputs.press_count(Input::Key(VirtualKeyCode::Space)), 2);
+    assert_eq!(inputs.release_count(Input::Key(VirtualKeyCode::Space)), 2);
+    assert_eq!(inputs.press_count(Input::Lmb), 1);
+    assert_eq!(inputs.release_count(Input::Lmb), 1);
+    // flip is space and lmb
+    assert_eq!(inputs.action_press_count(Action::Flip), 3);
+    assert_eq!(inputs.presses(Action::Flip).len(), 3);
+    assert_eq!(inputs.any_just_pressed(), Some(Input::Key(VirtualKeyCode::Space)));
+
+    // nothing carries over
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert!(!inputs.just_pressed(VirtualKeyCode::Space));
+    assert!(!inputs.action_just_pressed(Action::Flip));
+    assert_eq!(inputs.lmb, KeyStatus::Released);
+
+    // let go and pressed again inside a frame is still held after it
+    ea.handle_event(&key(VirtualKeyCode::P, Pressed));
+    ea.handle_event(&Event::MainEventsCleared);
+    ea.handle_event(&key(VirtualKeyCode::P, Released));
+    ea.handle_event(&key(VirtualKeyCode::P, Pressed));
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert_eq!(inputs.release_count(Input::Key(VirtualKeyCode::P)), 1);
+    assert!(inputs.action_just_pressed(Action::Pause));
+    assert!(inputs.pressed(VirtualKeyCode::P));
+}
+
+#[test]
+#[allow(deprecated)]
+pub fn test_mouse_extras() {
+    use glutin::dpi::PhysicalPosition;
+    use glutin::event::{ModifiersState, TouchPhase, WindowEvent};
+
+    let device_id = test_device();
+    let window = window_event;
+    let button = |button, state| window(WindowEvent::MouseInput { device_id, state, button, modifiers: ModifiersState::empty() });
+    let wheel = |delta| window(WindowEvent::MouseWheel { device_id, delta, phase: TouchPhase::Moved, modifiers: ModifiersState::empty() });
+    let motion = |x, y| Event::DeviceEvent { device_id, event: DeviceEvent::MouseMotion { delta: (x, y) } };
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+
+    // x11 numbering, back tapped, button 12 held
+    for e in [
+        button(MouseButton::Other(8), ElementState::Pressed), button(MouseButton::Other(8), ElementState::Released),
+        button(MouseButton::Other(12), ElementState::Pressed),
+        wheel(MouseScrollDelta::LineDelta(0.0, 1.0)), wheel(MouseScrollDelta::LineDelta(0.0, 2.0)),
+        wheel(MouseScrollDelta::PixelDelta(PhysicalPosition::new(3.0, -40.0))),
+        motion(5.0, -2.0), motion(1.0, 1.0),
+        window(WindowEvent::CursorLeft { device_id }),
+    ] {
+        ea.handle_event(&e);
+    }
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert_eq!(inputs.press_count(Input::MouseBack), 1);
+    assert_eq!(inputs.input_status(Input::MouseBack), KeyStatus::JustReleased);
+    assert_eq!(inputs.input_status(Input::Mouse(12)), KeyStatus::JustPressed);
+    assert_eq!(inputs.scroll_lines, Vec2::new(0.0, 3.0));
+    assert_eq!(inputs.scroll_pixels, Vec2::new(3.0, -40.0));
+    assert_eq!(inputs.press_count(Input::WheelUp), 2);
+    assert_eq!(inputs.press_count(Input::WheelDown), 1);
+    assert_eq!(inputs.raw_mouse_delta, Vec2::new(6.0, -1.0));
+    assert!(inputs.cursor_left && !inputs.cursor_inside);
+    // back button and wheel are bound in menus
+    assert!(inputs.action_just_pressed(Action::Back));
+    assert_eq!(inputs.action_press_count(Action::MenuUp), 2);
+
+    ea.handle_event(&window(WindowEvent::CursorEntered { device_id }));
+    let inputs = ea.handle_event(&Event::MainEventsCleared).unwrap();
+    assert_eq!(inputs.input_status(Input::MouseBack), KeyStatus::Released);
+    assert_eq!(inputs.input_status(Input::Mouse(12)), KeyStatus::Pressed);
+    assert_eq!(inputs.scroll_lines, Vec2::new(0.0, 0.0));
+    assert_eq!(inputs.raw_mouse_delta, Vec2::new(0.0, 0.0));
+    assert!(inputs.cursor_entered && inputs.cursor_inside && !inputs.cursor_left);
+    assert_eq!(Input::from_name("mouse_12"), Some(Input::Mouse(12)));
+}
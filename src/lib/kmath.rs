@@ -15,6 +15,11 @@ pub fn remap(x: f32, from_low: f32, from_high: f32, to_low: f32, to_high: f32) -
     lerp(to_low, to_high, unlerp(x, from_low, from_high))
 }
 
+pub fn smoothstep(t: f32) -> f32 {
+    let t = t.max(0.0).min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
 pub fn gradient(t: f32, colours: Vec<(Vec3, f32)>) -> Vec3 {
     // find nearest 2 neighbours in colours vec and interp between them
     for ((c1, t1), (c2, t2)) in colours.iter().tuple_windows() {
@@ -26,8 +31,12 @@ pub fn gradient(t: f32, colours: Vec<(Vec3, f32)>) -> Vec3 {
     Vec3::new(1.0, 1.0, 1.0)
 }
 
+// de casteljau
 pub fn cubic_bezier(start: Vec2, c1: Vec2, c2: Vec2, end: Vec2, t: f32) -> Vec2 {
-    start.lerp(c1.lerp(c2.lerp(end, t), t), t)
+    let a = start.lerp(c1, t);
+    let b = c1.lerp(c2, t);
+    let c = c2.lerp(end, t);
+    a.lerp(b, t).lerp(b.lerp(c, t), t)
 }
 
 /***************************************************
@@ -35,9 +44,9 @@ pub fn cubic_bezier(start: Vec2, c1: Vec2, c2: Vec2, end: Vec2, t: f32) -> Vec2
  ***************************************************/
 
 pub fn khash(mut state: u32) -> u32 {
-    state = (state ^ 2747636419) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
+    state = (state ^ 2747636419).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
     state
 }
 
@@ -71,6 +80,9 @@ impl Vec2 {
     pub fn dist(&self, other: Vec2) -> f32 { (*self - other).magnitude() }
     pub fn normalize(&self) -> Vec2 { let m = self.magnitude(); if m == 0.0 { *self } else { self.div_scalar(self.magnitude()) }}
     pub fn lerp(&self, other: Vec2, t: f32) -> Vec2 { Vec2::new(self.x*(1.0-t) + other.x*(t), self.y*(1.0-t) + other.y*(t)) }
+    pub fn dot(&self, other: Vec2) -> f32 { self.x*other.x + self.y*other.y }
+    pub fn cross(&self, other: Vec2) -> f32 { self.x*other.y - self.y*other.x }
+    pub fn perp(&self) -> Vec2 { Vec2::new(-self.y, self.x) }
     pub fn rotate(&self, radians: f32) -> Vec2 { 
         Vec2::new(
             self.x * radians.cos() - self.y * radians.sin(), 
@@ -254,6 +266,7 @@ impl Vec4 {
     pub fn square_distance(&self) -> f32 { self.x*self.x + self.y*self.y + self.z*self.z + self.w*self.w }
     pub fn normalize(&self) -> Vec4 { self.div_scalar(self.magnitude()) }
     pub fn lerp(&self, other: Vec4, t: f32) -> Vec4 { Vec4::new(self.x*(1.0-t) + other.x*(t), self.y*(1.0-t) + other.y*(t), self.z*(1.0-t) + other.z*(t), self.w*(1.0-t) + other.w*t) }
+    pub fn mul_elem(&self, other: Vec4) -> Vec4 { Vec4::new(self.x*other.x, self.y*other.y, self.z*other.z, self.w*other.w) }
     pub fn dist(&self, other: Vec4) -> f32 {(*self - other).magnitude().sqrt()}
     pub fn dot(&self, other: Vec4) -> f32 {self.x*other.x + self.y*other.y + self.z*other.z} // is squ dist lol
 }
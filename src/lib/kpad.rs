@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+#[cfg(test)]
+use std::cell::RefCell;
+#[cfg(test)]
+use std::rc::Rc;
+
+use crate::lib::kmath::*;
+use crate::lib::kinput::KeyStatus;
+
+// Gamepads. Whatever talks to the hardware implements PadBackend and hands
+// over events when polled, EventAggregator folds them into FrameInputState
+// once a frame. Theres the linux joystick api (/dev/input/js*) and a mock you
+// push events into for tests.
+//
+// Ids are per controller and stick around: unplug a pad and plug it back in
+// and its the same id, so player 2 stays player 2.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PadId(pub u32);
+
+// named by position, south is A on an xbox pad and cross on a playstation one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    Guide,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Other(u8),
+}
+
+pub const PAD_BUTTONS: [(PadButton, &str); 15] = [
+    (PadButton::South, "pad_south"),
+    (PadButton::East, "pad_east"),
+    (PadButton::West, "pad_west"),
+    (PadButton::North, "pad_north"),
+    (PadButton::LeftShoulder, "pad_lb"),
+    (PadButton::RightShoulder, "pad_rb"),
+    (PadButton::Select, "pad_select"),
+    (PadButton::Start, "pad_start"),
+    (PadButton::Guide, "pad_guide"),
+    (PadButton::LeftStick, "pad_ls"),
+    (PadButton::RightStick, "pad_rs"),
+    (PadButton::DPadUp, "pad_up"),
+    (PadButton::DPadDown, "pad_down"),
+    (PadButton::DPadLeft, "pad_left"),
+    (PadButton::DPadRight, "pad_right"),
+];
+
+impl PadButton {
+    pub fn name(&self) -> String {
+        match self {
+            PadButton::Other(n) => format!("pad_{}", n),
+            b => PAD_BUTTONS.iter().find(|(pb, _)| pb == b).map(|(_, n)| n.to_string()).unwrap_or_default(),
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<PadButton> {
+        if let Some((b, _)) = PAD_BUTTONS.iter().find(|(_, n)| *n == s) {
+            return Some(*b);
+        }
+        s.strip_prefix("pad_")?.parse().ok().map(PadButton::Other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+    Other(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PadEvent {
+    Connected { id: PadId, name: String },
+    Disconnected(PadId),
+    Button { id: PadId, button: PadButton, pressed: bool },
+    Axis { id: PadId, axis: PadAxis, value: f32 },   // sticks -1..1 with down positive, triggers 0..1
+}
+
+pub trait PadBackend {
+    fn poll(&mut self) -> Vec<PadEvent>;
+}
+
+// below inner its zero, above outer its full, rescaled in between so theres no jump
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deadzone {
+    pub inner: f32,
+    pub outer: f32,
+}
+
+impl Deadzone {
+    pub fn apply(&self, x: f32) -> f32 {
+        x.signum() * ((x.abs() - self.inner) / (self.outer - self.inner)).clamp(0.0, 1.0)
+    }
+
+    // radial so diagonals dont get cut down more than straight
+    pub fn apply_stick(&self, v: Vec2) -> Vec2 {
+        let m = v.magnitude();
+        if m <= self.inner {
+            return Vec2::new(0.0, 0.0);
+        }
+        v.normalize() * self.apply(m)
+    }
+}
+
+pub const STICK_DEADZONE: Deadzone = Deadzone { inner: 0.2, outer: 0.95 };
+pub const TRIGGER_DEADZONE: Deadzone = Deadzone { inner: 0.05, outer: 0.95 };
+
+// a test double, keep the handle and push events whenever
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockPads {
+    queue: Rc<RefCell<Vec<PadEvent>>>,
+}
+
+#[cfg(test)]
+impl MockPads {
+    pub fn new() -> MockPads {
+        MockPads::default()
+    }
+    pub fn push(&self, e: PadEvent) {
+        self.queue.borrow_mut().push(e);
+    }
+    pub fn press(&self, id: PadId, button: PadButton, pressed: bool) {
+        self.push(PadEvent::Button { id, button, pressed });
+    }
+    pub fn axis(&self, id: PadId, axis: PadAxis, value: f32) {
+        self.push(PadEvent::Axis { id, axis, value });
+    }
+}
+
+#[cfg(test)]
+impl PadBackend for MockPads {
+    fn poll(&mut self) -> Vec<PadEvent> {
+        std::mem::take(&mut *self.queue.borrow_mut())
+    }
+}
+
+// xbox style layout, which is what the kernel drivers for most pads give
+fn js_button(n: u8) -> PadButton {
+    match n {
+        0 => PadButton::South,
+        1 => PadButton::East,
+        2 => PadButton::West,
+        3 => PadButton::North,
+        4 => PadButton::LeftShoulder,
+        5 => PadButton::RightShoulder,
+        6 => PadButton::Select,
+        7 => PadButton::Start,
+        8 => PadButton::Guide,
+        9 => PadButton::LeftStick,
+        10 => PadButton::RightStick,
+        n => PadButton::Other(n),
+    }
+}
+
+// turns one js_event into pad events, the dpad comes through as axes 6 and 7
+// on most pads so it gets split into buttons here
+fn js_events(id: PadId, kind: u8, number: u8, value: i16) -> Vec<PadEvent> {
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    let kind = kind & !0x80;    // init flag, the state dump on open. treated like anything else
+    if kind == JS_EVENT_BUTTON {
+        return vec![PadEvent::Button { id, button: js_button(number), pressed: value != 0 }];
+    }
+    if kind != JS_EVENT_AXIS {
+        return Vec::new();
+    }
+    let v = value as f32 / 32767.0;
+    let dpad = |neg, pos| vec![
+        PadEvent::Button { id, button: neg, pressed: v < -0.5 },
+        PadEvent::Button { id, button: pos, pressed: v > 0.5 },
+    ];
+    match number {
+        0 => vec![PadEvent::Axis { id, axis: PadAxis::LeftX, value: v }],
+        1 => vec![PadEvent::Axis { id, axis: PadAxis::LeftY, value: v }],
+        2 => vec![PadEvent::Axis { id, axis: PadAxis::LeftTrigger, value: (v + 1.0) / 2.0 }],
+        3 => vec![PadEvent::Axis { id, axis: PadAxis::RightX, value: v }],
+        4 => vec![PadEvent::Axis { id, axis: PadAxis::RightY, value: v }],
+        5 => vec![PadEvent::Axis { id, axis: PadAxis::RightTrigger, value: (v + 1.0) / 2.0 }],
+        6 => dpad(PadButton::DPadLeft, PadButton::DPadRight),
+        7 => dpad(PadButton::DPadUp, PadButton::DPadDown),
+        n => vec![PadEvent::Axis { id, axis: PadAxis::Other(n), value: v }],
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct JsPads {
+    open: Vec<(String, PadId, std::fs::File)>,
+    known: HashMap<(String, String), PadId>,   // (path, name), so reconnects keep their id
+    next_id: u32,
+    since_scan: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl JsPads {
+    pub fn new() -> JsPads {
+        JsPads { open: Vec::new(), known: HashMap::new(), next_id: 0, since_scan: u32::MAX }
+    }
+
+    fn name(path: &str) -> String {
+        let dev = path.rsplit('/').next().unwrap_or("");
+        std::fs::read_to_string(format!("/sys/class/input/{}/device/name", dev))
+            .map(|s| s.trim().to_owned())
+            .unwrap_or_else(|_| dev.to_owned())
+    }
+
+    fn scan(&mut self, out: &mut Vec<PadEvent>) {
+        use std::os::unix::fs::OpenOptionsExt;
+        const O_NONBLOCK: i32 = 0o4000;
+        let entries = match std::fs::read_dir("/dev/input") {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+        let mut paths: Vec<String> = entries.filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with("js"))
+            .map(|e| e.path().to_string_lossy().into_owned())
+            .collect();
+        paths.sort();
+        for path in paths {
+            if self.open.iter().any(|(p, _, _)| *p == path) {
+                continue;
+            }
+            let file = match std::fs::OpenOptions::new().read(true).custom_flags(O_NONBLOCK).open(&path) {
+                Ok(f) => f,
+                Err(_) => continue,
+            };
+            let name = JsPads::name(&path);
+            let next_id = &mut self.next_id;
+            let id = *self.known.entry((path.clone(), name.clone())).or_insert_with(|| {
+                *next_id += 1;
+                PadId(*next_id - 1)
+            });
+            out.push(PadEvent::Connected { id, name });
+            self.open.push((path, id, file));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl PadBackend for JsPads {
+    fn poll(&mut self) -> Vec<PadEvent> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        // once a second or so, opening things is slow
+        self.since_scan = self.since_scan.saturating_add(1);
+        if self.since_scan > 60 {
+            self.since_scan = 0;
+            self.scan(&mut out);
+        }
+        let mut gone = Vec::new();
+        for (i, (_, id, file)) in self.open.iter_mut().enumerate() {
+            let mut buf = [0u8; 8];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(8) => {
+                        // struct js_event { u32 time; s16 value; u8 type; u8 number; }
+                        let value = i16::from_le_bytes([buf[4], buf[5]]);
+                        out.extend(js_events(*id, buf[6], buf[7], value));
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    // unplugged
+                    _ => {
+                        gone.push(i);
+                        break;
+                    },
+                }
+            }
+        }
+        for i in gone.into_iter().rev() {
+            let (_, id, _) = self.open.remove(i);
+            out.push(PadEvent::Disconnected(id));
+        }
+        out
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PadState {
+    pub id: PadId,
+    pub name: String,
+    pub connected: bool,
+    pub buttons: HashMap<PadButton, KeyStatus>,
+    pub axes: HashMap<PadAxis, f32>,   // raw
+    pub left_stick: Vec2,       // deadzoned
+    pub right_stick: Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+impl PadState {
+    pub fn new(id: PadId, name: &str) -> PadState {
+        PadState {
+            id,
+            name: name.to_owned(),
+            connected: true,
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            left_stick: Vec2::new(0.0, 0.0),
+            right_stick: Vec2::new(0.0, 0.0),
+            left_trigger: 0.0,
+            right_trigger: 0.0,
+        }
+    }
+
+    pub fn button(&self, b: PadButton) -> KeyStatus {
+        self.buttons.get(&b).copied().unwrap_or(KeyStatus::Released)
+    }
+
+    pub fn axis(&self, a: PadAxis) -> f32 {
+        self.axes.get(&a).copied().unwrap_or(0.0)
+    }
+
+    pub fn apply(&mut self, e: &PadEvent) {
+        match e {
+            PadEvent::Button { button, pressed: true, .. } => {
+                // the dpad axes send their released half every time they move
+                if !matches!(self.button(*button), KeyStatus::Pressed | KeyStatus::JustPressed) {
+                    self.buttons.insert(*button, KeyStatus::JustPressed);
+                }
+            },
+            PadEvent::Button { button, pressed: false, .. } => {
+                if self.buttons.contains_key(button) {
+                    self.buttons.insert(*button, KeyStatus::JustReleased);
+                }
+            },
+            PadEvent::Axis { axis, value, .. } => {
+                self.axes.insert(*axis, *value);
+            },
+            PadEvent::Connected { name, .. } => {
+                self.name = name.clone();
+                self.connected = true;
+            },
+            // let go of everything so nothing stays held
+            PadEvent::Disconnected(_) => {
+                self.connected = false;
+                self.axes.clear();
+                for v in self.buttons.values_mut() {
+                    *v = KeyStatus::JustReleased;
+                }
+            },
+        }
+    }
+
+    // end of frame, same as keys
+    pub fn advance(&mut self) {
+        self.buttons.retain(|_, v| *v != KeyStatus::JustReleased);
+        for v in self.buttons.values_mut() {
+            if *v == KeyStatus::JustPressed {
+                *v = KeyStatus::Pressed;
+            }
+        }
+    }
+
+    pub fn apply_deadzones(&mut self, stick: Deadzone, trigger: Deadzone) {
+        self.left_stick = stick.apply_stick(Vec2::new(self.axis(PadAxis::LeftX), self.axis(PadAxis::LeftY)));
+        self.right_stick = stick.apply_stick(Vec2::new(self.axis(PadAxis::RightX), self.axis(PadAxis::RightY)));
+        self.left_trigger = trigger.apply(self.axis(PadAxis::LeftTrigger));
+        self.right_trigger = trigger.apply(self.axis(PadAxis::RightTrigger));
+    }
+}
+
+#[test]
+pub fn test_pad_parsing() {
+    let dz = Deadzone { inner: 0.2, outer: 0.9 };
+    assert_eq!(dz.apply(0.1), 0.0);
+    assert_eq!(dz.apply(-1.0), -1.0);
+    assert!((dz.apply(0.55) - 0.5).abs() < 0.0001);
+    // diagonal at 0.3 each is past the inner radius even though neither axis is much
+    let d = dz.apply_stick(Vec2::new(0.3, 0.3));
+    assert!(d.x > 0.0 && (d.x - d.y).abs() < 0.0001);
+
+    let id = PadId(3);
+    assert_eq!(js_events(id, 0x81, 0, 1), vec![PadEvent::Button { id, button: PadButton::South, pressed: true }]);
+    assert_eq!(js_events(id, 0x02, 5, -32767), vec![PadEvent::Axis { id, axis: PadAxis::RightTrigger, value: 0.0 }]);
+    assert_eq!(js_events(id, 0x02, 7, -32767)[0], PadEvent::Button { id, button: PadButton::DPadUp, pressed: true });
+    for (b, n) in PAD_BUTTONS {
+        assert_eq!(PadButton::from_name(n), Some(b));
+    }
+    assert_eq!(PadButton::from_name("pad_14"), Some(PadButton::Other(14)));
+}
+
+#[test]
+pub fn test_mock_pad() {
+    use crate::lib::kinput::*;
+    use crate::lib::kaction::*;
+    use glutin::event::Event;
+    use std::time::{Duration, Instant};
+
+    let mock = MockPads::new();
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+    ea.set_pad_backend(Box::new(mock.clone()));
+    let frame = |ea: &mut EventAggregator| ea.handle_event(&Event::MainEventsCleared).unwrap();
+
+    let (a, b) = (PadId(0), PadId(1));
+    mock.push(PadEvent::Connected { id: a, name: "first".to_owned() });
+    mock.push(PadEvent::Connected { id: b, name: "second".to_owned() });
+    mock.press(b, PadButton::South, true);
+    mock.axis(a, PadAxis::LeftX, 0.1);
+    mock.axis(a, PadAxis::RightTrigger, 0.5);
+    let inputs = frame(&mut ea);
+    assert_eq!(inputs.pads_connected, vec![a, b]);
+    assert_eq!(inputs.pad(b).unwrap().button(PadButton::South), KeyStatus::JustPressed);
+    assert!(inputs.action_just_pressed(Action::Flip));
+    assert_eq!(inputs.pad(a).unwrap().left_stick, Vec2::new(0.0, 0.0));
+    assert!((inputs.pad(a).unwrap().right_trigger - 0.5).abs() < 0.0001);
+
+    let inputs = frame(&mut ea);
+    assert_eq!(inputs.action(Action::Flip), KeyStatus::Pressed);
+    assert!(inputs.pads_connected.is_empty());
+
+    // pulled out while held lets go, comes back as the same pad
+    mock.push(PadEvent::Disconnected(b));
+    let inputs = frame(&mut ea);
+    assert_eq!(inputs.pads_disconnected, vec![b]);
+    assert_eq!(inputs.action(Action::Flip), KeyStatus::JustReleased);
+    assert!(!inputs.pad(b).unwrap().connected);
+    mock.push(PadEvent::Connected { id: b, name: "second".to_owned() });
+    let inputs = frame(&mut ea);
+    assert_eq!(inputs.pads.len(), 2);
+    assert!(inputs.pad(b).unwrap().connected);
+    assert_eq!(inputs.action(Action::Flip), KeyStatus::Released);
+
+    // a press polled at the end of a frame counts from its start, so Game
+    // applies the flip in the steps for this frame and not the next
+    let t0 = Instant::now();
+    ea.handle_event_at(&Event::MainEventsCleared, t0);
+    mock.press(b, PadButton::South, true);
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(16)).unwrap();
+    assert_eq!(inputs.presses(Action::Flip), vec![inputs.t - inputs.dt]);
+    assert!(inputs.dt > 0.0);
+    mock.press(b, PadButton::South, false);
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(32)).unwrap();
+    assert_eq!(inputs.edges, vec![InputEdge { input: Input::Pad(PadButton::South), pressed: false, t: inputs.t - inputs.dt }]);
+}
@@ -0,0 +1,285 @@
+use std::f32::consts::PI;
+
+use crate::lib::kmath::*;
+
+// Vector paths, turned into triangles here so KRCanvas just has to draw them.
+// Angles are radians like offset_r_theta, curves get enough segments to look
+// round at the size theyre drawn (same rule as KRCanvas::circle).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineCap {
+    Butt,
+    Square,
+    Round,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StrokeStyle {
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    pub closed: bool,
+    pub miter_limit: f32,   // in multiples of half the width, past this it bevels
+}
+
+impl StrokeStyle {
+    pub fn new(width: f32) -> StrokeStyle {
+        StrokeStyle {
+            width,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            closed: false,
+            miter_limit: 4.0,
+        }
+    }
+    pub fn join(mut self, join: LineJoin) -> StrokeStyle {
+        self.join = join;
+        self
+    }
+    pub fn cap(mut self, cap: LineCap) -> StrokeStyle {
+        self.cap = cap;
+        self
+    }
+    pub fn closed(mut self) -> StrokeStyle {
+        self.closed = true;
+        self
+    }
+}
+
+pub fn segments_for(radius: f32, sweep: f32) -> usize {
+    let full = (radius.abs().sqrt() * 200.0).max(6.0);
+    ((full * sweep.abs() / (2.0 * PI)).ceil() as usize).max(1)
+}
+
+// includes both ends
+pub fn arc_points(c: Vec2, r: f32, a0: f32, a1: f32) -> Vec<Vec2> {
+    let n = segments_for(r, a1 - a0);
+    (0..=n).map(|i| c.offset_r_theta(r, lerp(a0, a1, i as f32 / n as f32))).collect()
+}
+
+pub fn bezier_points(p0: Vec2, c1: Vec2, c2: Vec2, p3: Vec2) -> Vec<Vec2> {
+    // control polygon length is an upper bound on the curve length
+    let len = p0.dist(c1) + c1.dist(c2) + c2.dist(p3);
+    let n = ((len * 100.0).ceil() as usize).clamp(4, 64);
+    (0..=n).map(|i| cubic_bezier(p0, c1, c2, p3, i as f32 / n as f32)).collect()
+}
+
+// clockwise on screen, starting at the end of the top edge
+pub fn rounded_rect_points(r: Rect, radius: f32) -> Vec<Vec2> {
+    let rad = radius.min(r.w / 2.0).min(r.h / 2.0).max(0.0);
+    if rad == 0.0 {
+        return vec![r.tl(), r.tr(), r.br(), r.bl()];
+    }
+    let corners = [
+        (Vec2::new(r.right() - rad, r.y + rad), -PI/2.0),
+        (Vec2::new(r.right() - rad, r.bot() - rad), 0.0),
+        (Vec2::new(r.x + rad, r.bot() - rad), PI/2.0),
+        (Vec2::new(r.x + rad, r.y + rad), PI),
+    ];
+    let mut points = Vec::new();
+    for (c, a) in corners {
+        points.extend(arc_points(c, rad, a, a + PI/2.0));
+    }
+    points
+}
+
+pub fn fan(c: Vec2, points: &[Vec2]) -> Vec<Triangle> {
+    points.windows(2).map(|w| Triangle::new(c, w[0], w[1])).collect()
+}
+
+pub fn pie(c: Vec2, r: f32, a0: f32, a1: f32) -> Vec<Triangle> {
+    fan(c, &arc_points(c, r, a0, a1))
+}
+
+fn quad(out: &mut Vec<Triangle>, a: Vec2, b: Vec2, c: Vec2, d: Vec2) {
+    out.push(Triangle::new(a, b, c));
+    out.push(Triangle::new(a, c, d));
+}
+
+fn dedup(points: &[Vec2]) -> Vec<Vec2> {
+    let mut out: Vec<Vec2> = Vec::with_capacity(points.len());
+    for p in points {
+        if out.last().map(|q| q.dist(*p) > 1e-6).unwrap_or(true) {
+            out.push(*p);
+        }
+    }
+    out
+}
+
+// one quad per segment, joins fill the gap on the outside of each bend. the
+// quads overlap a bit on the inside which only shows with translucent colours
+pub fn stroke(points: &[Vec2], style: &StrokeStyle) -> Vec<Triangle> {
+    let mut pts = dedup(points);
+    if style.closed && pts.len() > 2 && pts[0].dist(pts[pts.len() - 1]) < 1e-6 {
+        pts.pop();
+    }
+    let mut out = Vec::new();
+    if pts.len() < 2 {
+        return out;
+    }
+    let hw = style.width / 2.0;
+    let n = pts.len();
+    let seg_count = if style.closed {n} else {n - 1};
+    let dir = |i: usize| (pts[(i + 1) % n] - pts[i]).normalize();
+
+    for i in 0..seg_count {
+        let (a, b) = (pts[i], pts[(i + 1) % n]);
+        let off = dir(i).perp() * hw;
+        quad(&mut out, a + off, b + off, b - off, a - off);
+    }
+
+    // joins at every vertex that has a segment both sides
+    let joins: Vec<usize> = if style.closed {(0..n).collect()} else {(1..n-1).collect()};
+    for i in joins {
+        let d0 = dir((i + n - 1) % n);
+        let d1 = dir(i);
+        let turn = d0.cross(d1);
+        if turn.abs() < 1e-6 && d0.dot(d1) > 0.0 {
+            continue;   // straight through
+        }
+        let p = pts[i];
+        let s = if turn > 0.0 {-hw} else {hw};
+        let o0 = d0.perp() * s;
+        let o1 = d1.perp() * s;
+        match style.join {
+            LineJoin::Bevel => out.push(Triangle::new(p, p + o0, p + o1)),
+            LineJoin::Round => {
+                let a0 = o0.y.atan2(o0.x);
+                let mut a1 = o1.y.atan2(o1.x);
+                // the short way round
+                while a1 - a0 > PI { a1 -= 2.0 * PI; }
+                while a1 - a0 < -PI { a1 += 2.0 * PI; }
+                out.extend(pie(p, hw, a0, a1));
+            },
+            LineJoin::Miter => {
+                out.push(Triangle::new(p, p + o0, p + o1));
+                let mid = (o0 + o1).normalize();
+                let cos_half = mid.dot(o0) / hw;
+                if cos_half > 1.0 / style.miter_limit {
+                    let tip = p + mid * (hw / cos_half);
+                    out.push(Triangle::new(p + o0, tip, p + o1));
+                }
+            },
+        }
+    }
+
+    if !style.closed {
+        let ends = [(pts[0], -dir(0)), (pts[n - 1], dir(n - 2))];
+        for (p, out_dir) in ends {
+            let off = out_dir.perp() * hw;
+            match style.cap {
+                LineCap::Butt => {},
+                LineCap::Square => {
+                    let e = out_dir * hw;
+                    quad(&mut out, p + off, p + off + e, p - off + e, p - off);
+                },
+                LineCap::Round => {
+                    let a = off.y.atan2(off.x);
+                    out.extend(pie(p, hw, a, a - PI));
+                },
+            }
+        }
+    }
+    out
+}
+
+pub fn signed_area(points: &[Vec2]) -> f32 {
+    let n = points.len();
+    (0..n).map(|i| points[i].cross(points[(i + 1) % n])).sum::<f32>() / 2.0
+}
+
+// ear clipping, any simple polygon either winding. O(n^3) worst case, fine
+// for the handful of points HUD shapes have
+pub fn triangulate(points: &[Vec2]) -> Vec<Triangle> {
+    let mut pts = dedup(points);
+    if pts.len() > 2 && pts[0].dist(pts[pts.len() - 1]) < 1e-6 {
+        pts.pop();
+    }
+    let mut out = Vec::new();
+    if pts.len() < 3 {
+        return out;
+    }
+    let winding = signed_area(&pts).signum();
+    let mut idx: Vec<usize> = (0..pts.len()).collect();
+    while idx.len() > 3 {
+        let n = idx.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (pts[idx[(i + n - 1) % n]], pts[idx[i]], pts[idx[(i + 1) % n]]);
+            if (b - a).cross(c - b) * winding <= 0.0 {
+                return false;   // reflex or flat
+            }
+            let t = Triangle::new(a, b, c);
+            !idx.iter().any(|&j| {
+                let p = pts[j];
+                p != a && p != b && p != c && t.contains(p)
+            })
+        });
+        match ear {
+            Some(i) => {
+                out.push(Triangle::new(pts[idx[(i + n - 1) % n]], pts[idx[i]], pts[idx[(i + 1) % n]]));
+                idx.remove(i);
+            },
+            // not simple or numerically awful, drop a point and carry on rather than loop forever
+            None => {
+                idx.remove(0);
+            },
+        }
+    }
+    out.push(Triangle::new(pts[idx[0]], pts[idx[1]], pts[idx[2]]));
+    out
+}
+
+#[test]
+pub fn test_paths() {
+    let area = |tris: &[Triangle]| tris.iter().map(|t| signed_area(&[t.a, t.b, t.c]).abs()).sum::<f32>();
+
+    // L shape, one reflex corner, both windings
+    let l = vec![
+        Vec2::new(0.0, 0.0), Vec2::new(2.0, 0.0), Vec2::new(2.0, 1.0),
+        Vec2::new(1.0, 1.0), Vec2::new(1.0, 2.0), Vec2::new(0.0, 2.0),
+    ];
+    let tris = triangulate(&l);
+    assert_eq!(tris.len(), 4);
+    assert!((area(&tris) - 3.0).abs() < 0.0001);
+    let rev: Vec<Vec2> = l.iter().rev().copied().collect();
+    assert!((area(&triangulate(&rev)) - 3.0).abs() < 0.0001);
+
+    // straight line, butt caps is just the rectangle, square caps add half a width each end, round caps a half circle
+    let line = [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0)];
+    assert!((area(&stroke(&line, &StrokeStyle::new(0.1))) - 0.1).abs() < 0.0001);
+    assert!((area(&stroke(&line, &StrokeStyle::new(0.1).cap(LineCap::Square))) - 0.11).abs() < 0.0001);
+    assert!((area(&stroke(&line, &StrokeStyle::new(0.1).cap(LineCap::Round))) - (0.1 + PI * 0.0025)).abs() < 0.0001);
+
+    // right angle with a miter fills the corner square exactly
+    let bend = [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0)];
+    let tris = stroke(&bend, &StrokeStyle::new(0.2));
+    let corner = Vec2::new(1.1, -0.1);
+    assert!(tris.iter().any(|t| [t.a, t.b, t.c].iter().any(|p| p.dist(corner) < 0.0001)));
+    // bevel cuts it off instead, half the square missing
+    let miter = area(&tris);
+    assert!((miter - area(&stroke(&bend, &StrokeStyle::new(0.2).join(LineJoin::Bevel))) - 0.005).abs() < 0.0001);
+
+    let rr = rounded_rect_points(Rect::new(0.0, 0.0, 1.0, 1.0), 0.2);
+    let expected = 1.0 - 0.04 * (4.0 - PI);
+    assert!((signed_area(&rr).abs() - expected).abs() < 0.001);
+}
+
+#[test]
+pub fn test_bezier() {
+    // flattened curve starts and ends on the end points and goes through the middle of the curve
+    let (p0, c1, c2, p3) = (Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 0.0));
+    let pts = bezier_points(p0, c1, c2, p3);
+    assert_eq!(pts.len() % 2, 1);
+    assert_eq!(pts[0], p0);
+    assert!(pts[pts.len() - 1].dist(p3) < 0.0001);
+    assert!(pts[pts.len() / 2].dist(Vec2::new(0.5, 0.75)) < 0.0001);
+    // none of it strays outside the control points box
+    assert!(pts.iter().all(|p| p.x >= 0.0 && p.x <= 1.0 && p.y >= 0.0 && p.y <= 0.75 + 0.0001));
+}
@@ -0,0 +1,363 @@
+use crate::lib::kmath::*;
+
+// Text layout, independent of the renderer. Produces a rect per glyph and the
+// char thats in it, KRCanvas does the drawing and looks the char up in the font.
+// Sizes from GlyphMetrics are in units of line height so char_h scales everything.
+//
+// Inline colour: "normal {#f80}orange{/} normal", {{ for a literal {
+
+pub const GLYPH_COUNT: u32 = 96;            // printable ascii, ' ' to DEL
+pub const REPLACEMENT_CHAR: char = '?';
+pub const REPLACEMENT_GLYPH: u32 = REPLACEMENT_CHAR as u32 - b' ' as u32;
+pub const TAB_WIDTH: usize = 4;
+
+pub fn glyph_index(c: char) -> u32 {
+    let c = c as u32;
+    if c >= b' ' as u32 && c < b' ' as u32 + GLYPH_COUNT {
+        c - b' ' as u32
+    } else {
+        REPLACEMENT_GLYPH
+    }
+}
+
+pub trait GlyphMetrics {
+    fn has_glyph(&self, c: char) -> bool;
+    fn advance(&self, c: char) -> f32;
+    fn kern(&self, _a: char, _b: char) -> f32 {
+        0.0
+    }
+    // glyph box relative to the pen position at the top of the line
+    fn bounds(&self, c: char) -> Rect;
+    // texels per line for bitmap fonts, 0 if it scales freely
+    fn line_px(&self) -> f32 {
+        0.0
+    }
+}
+
+// the grid font baked into atlas.png
+pub struct Monospace {
+    pub aspect: f32,
+    pub px: f32,
+}
+
+impl GlyphMetrics for Monospace {
+    fn has_glyph(&self, c: char) -> bool {
+        glyph_index(c) != REPLACEMENT_GLYPH || c == '?'
+    }
+    fn advance(&self, _c: char) -> f32 {
+        self.aspect
+    }
+    fn bounds(&self, _c: char) -> Rect {
+        Rect::new(0.0, 0.0, self.aspect, 1.0)
+    }
+    fn line_px(&self) -> f32 {
+        self.px
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HAlign {
+    Left,
+    Centre,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextStyle {
+    pub char_h: f32,
+    pub line_spacing: f32,  // multiple of char_h
+    pub halign: HAlign,
+    pub valign: VAlign,
+    pub wrap: bool,
+}
+
+impl TextStyle {
+    pub fn new(char_h: f32) -> TextStyle {
+        TextStyle {
+            char_h,
+            line_spacing: 1.2,
+            halign: HAlign::Left,
+            valign: VAlign::Top,
+            wrap: true,
+        }
+    }
+    pub fn align(mut self, halign: HAlign, valign: VAlign) -> TextStyle {
+        self.halign = halign;
+        self.valign = valign;
+        self
+    }
+    pub fn no_wrap(mut self) -> TextStyle {
+        self.wrap = false;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlacedGlyph {
+    pub rect: Rect,
+    pub c: char,
+    pub colour: Option<Vec4>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    pub glyphs: Vec<PlacedGlyph>,
+    pub bounds: Rect,
+    pub lines: usize,
+}
+
+fn parse_hex_colour(s: &str) -> Option<Vec4> {
+    let digits: Option<Vec<u32>> = s.chars().map(|c| c.to_digit(16)).collect();
+    let d = digits?;
+    let f = |hi: u32, lo: u32| (hi * 16 + lo) as f32 / 255.0;
+    match d.len() {
+        3 => Some(Vec4::new(f(d[0], d[0]), f(d[1], d[1]), f(d[2], d[2]), 1.0)),
+        6 => Some(Vec4::new(f(d[0], d[1]), f(d[2], d[3]), f(d[4], d[5]), 1.0)),
+        8 => Some(Vec4::new(f(d[0], d[1]), f(d[2], d[3]), f(d[4], d[5]), f(d[6], d[7]))),
+        _ => None,
+    }
+}
+
+// strips markup, returns each char with the colour its in. unrecognised
+// markup is left in as text so its obvious something is wrong
+pub fn parse_spans(s: &str) -> Vec<(char, Option<Vec4>)> {
+    let mut out = Vec::new();
+    let mut colour = None;
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            if rest.starts_with("{{") {
+                out.push(('{', colour));
+                rest = &rest[2..];
+                continue;
+            }
+            if let Some(end) = rest.find('}') {
+                let tag = &rest[1..end];
+                if tag == "/" {
+                    colour = None;
+                    rest = &rest[end+1..];
+                    continue;
+                }
+                if let Some(c) = tag.strip_prefix('#').and_then(parse_hex_colour) {
+                    colour = Some(c);
+                    rest = &rest[end+1..];
+                    continue;
+                }
+            }
+        }
+        out.push((c, colour));
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+type Span = (char, Option<Vec4>);
+
+// in line heights, including kerning
+fn line_width(line: &[Span], m: &dyn GlyphMetrics) -> f32 {
+    let mut w = 0.0;
+    for (i, (c, _)) in line.iter().enumerate() {
+        if i > 0 {
+            w += m.kern(line[i-1].0, *c);
+        }
+        w += m.advance(*c);
+    }
+    w
+}
+
+fn is_space(s: Option<&Span>) -> bool {
+    s.map(|(c, _)| *c == ' ').unwrap_or(false)
+}
+
+// split on newlines and optionally wrap at word boundaries to fit max_w.
+// unknown glyphs get swapped for the replacement char here
+fn break_lines(chars: &[Span], max_w: Option<f32>, m: &dyn GlyphMetrics) -> Vec<Vec<Span>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<Span> = Vec::new();
+    let mut last_break: Option<usize> = None;   // index in line after a space
+    let tab_w = m.advance(' ') * TAB_WIDTH as f32;
+
+    for &(c, col) in chars {
+        if c == '\n' {
+            lines.push(std::mem::take(&mut line));
+            last_break = None;
+            continue;
+        }
+        if c == '\t' {
+            // spaces up to the next stop, at least one
+            let w = line_width(&line, m);
+            let stop = if tab_w > 0.0 {((w / tab_w).floor() + 1.0) * tab_w} else {w};
+            line.push((' ', col));
+            while line_width(&line, m) + m.advance(' ') <= stop + 0.0001 {
+                line.push((' ', col));
+            }
+        } else if m.has_glyph(c) {
+            line.push((c, col));
+        } else {
+            line.push((REPLACEMENT_CHAR, col));
+        }
+        if is_space(line.last()) {
+            last_break = Some(line.len());
+        }
+        if let Some(max) = max_w {
+            if line.len() > 1 && line_width(&line, m) > max + 0.0001 {
+                let split = match last_break {
+                    Some(i) if i < line.len() => i,
+                    _ => line.len() - 1,    // no space to break on, chop the word
+                };
+                let rest = line.split_off(split);
+                // trailing spaces dont count
+                while is_space(line.last()) {
+                    line.pop();
+                }
+                lines.push(std::mem::replace(&mut line, rest));
+                while is_space(line.first()) {
+                    line.remove(0);
+                }
+                last_break = None;
+            }
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+// plain chars, no markup parsing
+pub fn layout_spans(chars: &[Span], r: Rect, style: &TextStyle, m: &dyn GlyphMetrics) -> TextLayout {
+    let h = style.char_h;
+    let line_h = h * style.line_spacing;
+    let max_w = if style.wrap && h > 0.0 {Some(r.w / h)} else {None};
+    let lines = break_lines(chars, max_w, m);
+
+    let block_h = line_h * (lines.len() as f32 - 1.0) + h;
+    let y0 = match style.valign {
+        VAlign::Top => r.y,
+        VAlign::Middle => r.y + (r.h - block_h) / 2.0,
+        VAlign::Bottom => r.bot() - block_h,
+    };
+
+    let mut glyphs = Vec::new();
+    let mut min_x = f32::INFINITY;
+    let mut max_x = f32::NEG_INFINITY;
+    for (i, line) in lines.iter().enumerate() {
+        let line_w = line_width(line, m) * h;
+        let x0 = match style.halign {
+            HAlign::Left => r.x,
+            HAlign::Centre => r.x + (r.w - line_w) / 2.0,
+            HAlign::Right => r.right() - line_w,
+        };
+        min_x = min_x.min(x0);
+        max_x = max_x.max(x0 + line_w);
+        let y = y0 + i as f32 * line_h;
+        let mut pen = x0;
+        for (j, (c, colour)) in line.iter().enumerate() {
+            if j > 0 {
+                pen += m.kern(line[j-1].0, *c) * h;
+            }
+            if *c != ' ' {
+                let b = m.bounds(*c);
+                glyphs.push(PlacedGlyph {
+                    rect: Rect::new(pen + b.x * h, y + b.y * h, b.w * h, b.h * h),
+                    c: *c,
+                    colour: *colour,
+                });
+            }
+            pen += m.advance(*c) * h;
+        }
+    }
+
+    TextLayout {
+        glyphs,
+        bounds: Rect::new(min_x, y0, (max_x - min_x).max(0.0), block_h),
+        lines: lines.len(),
+    }
+}
+
+pub fn layout(s: &str, r: Rect, style: &TextStyle, m: &dyn GlyphMetrics) -> TextLayout {
+    layout_spans(&parse_spans(s), r, style, m)
+}
+
+// biggest whole multiple of the fonts pixel size that fits in char_h, so
+// nearest filtering doesnt make some texel rows fatter than others. px_size is
+// world units per screen pixel. Below 1x its going to be mush anyway, left alone
+pub fn crisp_char_h(char_h: f32, px_size: f32, m: &dyn GlyphMetrics) -> f32 {
+    let native = m.line_px();
+    if native <= 0.0 || px_size <= 0.0 {
+        return char_h;
+    }
+    let scale = (char_h / px_size / native).floor();
+    if scale < 1.0 {
+        char_h
+    } else {
+        scale * native * px_size
+    }
+}
+
+pub fn measure(s: &str, max_w: Option<f32>, style: &TextStyle, m: &dyn GlyphMetrics) -> Vec2 {
+    let mut style = *style;
+    style.wrap = max_w.is_some();
+    let l = layout(s, Rect::new(0.0, 0.0, max_w.unwrap_or(0.0), 0.0), &style.align(HAlign::Left, VAlign::Top), m);
+    Vec2::new(l.bounds.w, l.bounds.h)
+}
+
+#[test]
+pub fn test_text_layout() {
+    assert_eq!(glyph_index('A'), 33);
+    assert_eq!(glyph_index('\u{7}'), REPLACEMENT_GLYPH);
+    assert_eq!(glyph_index('é'), REPLACEMENT_GLYPH);
+
+    let style = TextStyle::new(1.0);
+    let m = Monospace { aspect: 1.0, px: 0.0 };
+    // wraps on the space, doesnt draw spaces
+    let l = layout("abc def", Rect::new(0.0, 0.0, 4.0, 10.0), &style, &m);
+    assert_eq!(l.lines, 2);
+    assert_eq!(l.glyphs.len(), 6);
+    assert_eq!(l.glyphs[3].rect.x, 0.0);
+    assert_eq!(l.bounds.w, 3.0);
+
+    // long words get chopped
+    assert_eq!(layout("abcdefgh", Rect::new(0.0, 0.0, 3.0, 10.0), &style, &m).lines, 3);
+
+    // newlines and tabs
+    let l = layout("a\n\tb", Rect::new(0.0, 0.0, 100.0, 10.0), &style, &m);
+    assert_eq!(l.lines, 2);
+    assert_eq!(l.glyphs[1].rect.x, TAB_WIDTH as f32);
+
+    // right and bottom, each line against the right edge on its own and the last one on the bottom
+    let l = layout("ab", Rect::new(0.0, 0.0, 10.0, 10.0), &style.align(HAlign::Right, VAlign::Bottom), &m);
+    assert_eq!(l.glyphs[1].rect.right(), 10.0);
+    assert_eq!(l.glyphs[1].rect.bot(), 10.0);
+    let l = layout("abc\nd", Rect::new(0.0, 0.0, 10.0, 10.0), &style.align(HAlign::Right, VAlign::Bottom), &m);
+    assert_eq!(l.lines, 2);
+    assert_eq!(l.glyphs[0].rect.x, 7.0);
+    assert_eq!(l.glyphs[2].rect.right(), 10.0);
+    assert_eq!(l.glyphs[3].rect.x, 9.0);
+    assert_eq!(l.glyphs[3].rect.bot(), 10.0);
+    assert!(l.glyphs[0].rect.bot() < l.glyphs[3].rect.y);
+    assert_eq!(l.bounds.right(), 10.0);
+
+    // colour spans
+    let l = layout("a{#f00}b{/}c{{", Rect::new(0.0, 0.0, 100.0, 10.0), &style, &m);
+    assert_eq!(l.glyphs.len(), 4);
+    assert_eq!(l.glyphs[0].colour, None);
+    assert_eq!(l.glyphs[1].colour, Some(Vec4::new(1.0, 0.0, 0.0, 1.0)));
+    assert_eq!(l.glyphs[2].colour, None);
+    assert_eq!(l.glyphs[3].c, '{');
+
+    // 0.1 tall at 1/400 units a pixel is 40px, an 8px font gets 5x
+    let bitmap = Monospace { aspect: 1.0, px: 8.0 };
+    assert!((crisp_char_h(0.1, 1.0/400.0, &bitmap) - 0.1).abs() < 0.0001);
+    assert!((crisp_char_h(0.1, 1.0/300.0, &bitmap) - 24.0/300.0).abs() < 0.0001);
+    assert_eq!(crisp_char_h(0.01, 1.0/300.0, &bitmap), 0.01);
+
+    let size = measure("ab\na\u{7}cd", None, &style, &m);
+    assert_eq!(size.x, 4.0);
+    assert!((size.y - 2.2).abs() < 0.0001);
+}
@@ -0,0 +1,208 @@
+use crate::lib::kmath::*;
+
+// Touchscreens. EventAggregator feeds winit touches in here in playfield
+// coordinates, same as the mouse, and this keeps track of every finger and
+// spots taps and swipes when one lifts. Gestures are inputs like anything
+// else (tap, swipe_up...) so they go through the bindings. A finger going
+// down is an input too (touch), for when waiting to see if its a tap is too slow.
+
+// a finger that barely moved and wasnt down long
+pub const TAP_SLOP: f32 = 0.03;
+pub const TAP_TIME: f64 = 0.35;
+// a finger that moved a fair way quickly
+pub const SWIPE_MIN: f32 = 0.1;
+pub const SWIPE_TIME: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchPhase {
+    Began,
+    Moved,
+    Held,       // down but hasnt moved since last frame
+    Ended,
+    Cancelled,  // the os took it, no gesture
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Touch {
+    pub id: u64,
+    pub pos: Vec2,
+    pub start: Vec2,
+    pub start_t: f64,
+    pub phase: TouchPhase,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GestureKind {
+    Tap,
+    SwipeUp,
+    SwipeDown,
+    SwipeLeft,
+    SwipeRight,
+}
+
+pub const GESTURES: [(GestureKind, &str); 5] = [
+    (GestureKind::Tap, "tap"),
+    (GestureKind::SwipeUp, "swipe_up"),
+    (GestureKind::SwipeDown, "swipe_down"),
+    (GestureKind::SwipeLeft, "swipe_left"),
+    (GestureKind::SwipeRight, "swipe_right"),
+];
+
+impl GestureKind {
+    pub fn name(&self) -> &'static str {
+        GESTURES.iter().find(|(g, _)| g == self).map(|(_, n)| *n).unwrap_or("")
+    }
+
+    pub fn from_name(s: &str) -> Option<GestureKind> {
+        GESTURES.iter().find(|(_, n)| *n == s).map(|(g, _)| *g)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gesture {
+    pub kind: GestureKind,
+    pub from: Vec2,
+    pub to: Vec2,
+    pub t: f64,     // when the finger came up
+}
+
+pub fn recognise(touch: &Touch, t: f64) -> Option<Gesture> {
+    let d = touch.pos - touch.start;
+    let held = t - touch.start_t;
+    let kind = if d.magnitude() < TAP_SLOP && held < TAP_TIME {
+        GestureKind::Tap
+    } else if d.magnitude() >= SWIPE_MIN && held < SWIPE_TIME {
+        // y is down
+        if d.x.abs() > d.y.abs() {
+            if d.x > 0.0 {GestureKind::SwipeRight} else {GestureKind::SwipeLeft}
+        } else {
+            if d.y > 0.0 {GestureKind::SwipeDown} else {GestureKind::SwipeUp}
+        }
+    } else {
+        return None;
+    };
+    Some(Gesture { kind, from: touch.start, to: touch.pos, t })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TouchTracker {
+    touches: Vec<Touch>,
+}
+
+impl TouchTracker {
+    pub fn new() -> TouchTracker {
+        TouchTracker::default()
+    }
+
+    // everything down, and anything that came up since the last frame
+    pub fn touches(&self) -> &[Touch] {
+        &self.touches
+    }
+
+    // ids get reused once a finger lifts, so only look at ones still down
+    fn live(&mut self, id: u64) -> Option<&mut Touch> {
+        self.touches.iter_mut().find(|touch| touch.id == id && !matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+    }
+
+    pub fn down(&self, id: u64) -> bool {
+        self.touches.iter().any(|touch| touch.id == id && !matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+    }
+
+    pub fn began(&mut self, id: u64, pos: Vec2, t: f64) {
+        self.cancelled(id);
+        self.touches.push(Touch { id, pos, start: pos, start_t: t, phase: TouchPhase::Began });
+    }
+
+    pub fn moved(&mut self, id: u64, pos: Vec2) {
+        if let Some(touch) = self.live(id) {
+            touch.pos = pos;
+            if touch.phase != TouchPhase::Began {
+                touch.phase = TouchPhase::Moved;
+            }
+        }
+    }
+
+    pub fn ended(&mut self, id: u64, pos: Vec2, t: f64) -> Option<Gesture> {
+        let touch = self.live(id)?;
+        touch.pos = pos;
+        touch.phase = TouchPhase::Ended;
+        recognise(touch, t)
+    }
+
+    pub fn cancelled(&mut self, id: u64) {
+        if let Some(touch) = self.live(id) {
+            touch.phase = TouchPhase::Cancelled;
+        }
+    }
+
+    pub fn advance(&mut self) {
+        self.touches.retain(|touch| !matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled));
+        for touch in self.touches.iter_mut() {
+            touch.phase = TouchPhase::Held;
+        }
+    }
+}
+
+#[test]
+pub fn test_touch() {
+    use crate::lib::kinput::*;
+    use crate::lib::kaction::*;
+    use glutin::dpi::PhysicalPosition;
+    use glutin::event::{Event, WindowEvent};
+    use std::time::{Duration, Instant};
+
+    // two fingers at once, one taps and one swipes left
+    let mut tt = TouchTracker::new();
+    tt.began(1, Vec2::new(0.5, 0.5), 0.0);
+    tt.began(2, Vec2::new(1.0, 0.5), 0.0);
+    tt.moved(1, Vec2::new(0.51, 0.5));
+    tt.moved(2, Vec2::new(0.8, 0.52));
+    assert_eq!(tt.touches().len(), 2);
+    assert_eq!(tt.ended(1, Vec2::new(0.51, 0.5), 0.1).map(|g| g.kind), Some(GestureKind::Tap));
+    assert_eq!(tt.ended(2, Vec2::new(0.7, 0.55), 0.2).map(|g| g.kind), Some(GestureKind::SwipeLeft));
+    assert_eq!(tt.touches()[0].phase, TouchPhase::Ended);
+    tt.advance();
+    assert!(tt.touches().is_empty());
+
+    // held too long or dragged too slowly is nothing
+    tt.began(3, Vec2::new(0.5, 0.5), 0.0);
+    assert_eq!(tt.ended(3, Vec2::new(0.5, 0.5), 1.0), None);
+    tt.began(4, Vec2::new(0.5, 0.5), 0.0);
+    assert_eq!(tt.ended(4, Vec2::new(0.5, 0.1), 2.0), None);
+    tt.began(5, Vec2::new(0.5, 0.5), 0.0);
+    assert_eq!(tt.ended(5, Vec2::new(0.5, 0.3), 0.2).map(|g| g.kind), Some(GestureKind::SwipeUp));
+
+    // through the aggregator, a finger going down in the middle of the window flips
+    // straight away, lifting it is a tap at the playfields centre
+    let touch = |id, phase, x, y| window_event(WindowEvent::Touch(glutin::event::Touch {
+        device_id: test_device(), phase, location: PhysicalPosition::new(x, y), force: None, id,
+    }));
+    use glutin::event::TouchPhase::{Started, Moved, Ended};
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, 16.0/9.0);
+    let t0 = Instant::now();
+    ea.handle_event_at(&Event::MainEventsCleared, t0);
+    ea.handle_event_at(&touch(7, Started, 800.0, 450.0), t0);
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(16)).unwrap();
+    assert_eq!(inputs.touches.len(), 1);
+    assert_eq!(inputs.touches[0].phase, TouchPhase::Began);
+    assert!((inputs.touches[0].pos - inputs.screen_rect.centroid()).magnitude() < 0.0001);
+    assert!(inputs.action_just_pressed(Action::Flip));
+    assert!((inputs.presses(Action::Flip)[0] - (inputs.t - inputs.dt)).abs() < 1e-9);
+    assert!(inputs.gestures.is_empty());
+
+    ea.handle_event_at(&touch(7, Moved, 801.0, 451.0), t0 + Duration::from_millis(20));
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(24)).unwrap();
+    assert_eq!(inputs.action(Action::Flip), KeyStatus::Pressed);
+    ea.handle_event_at(&touch(7, Ended, 801.0, 451.0), t0 + Duration::from_millis(40));
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(48)).unwrap();
+    assert_eq!(inputs.touches[0].phase, TouchPhase::Ended);
+    assert_eq!(inputs.gestures.len(), 1);
+    assert_eq!(inputs.gestures[0].kind, GestureKind::Tap);
+    assert_eq!(inputs.action(Action::Flip), KeyStatus::JustReleased);
+    assert!(!inputs.action_just_pressed(Action::Flip));
+    // menus and the death screen still go on the tap
+    assert!(inputs.action_just_pressed(Action::Restart));
+    assert_eq!(inputs.any_just_pressed(), Some(Input::Touch(GestureKind::Tap)));
+    let inputs = ea.handle_event_at(&Event::MainEventsCleared, t0 + Duration::from_millis(64)).unwrap();
+    assert!(inputs.touches.is_empty() && inputs.gestures.is_empty());
+}
@@ -1,3 +1,13 @@
 pub mod kmath;
 pub mod kinput;
-pub mod kimg;
\ No newline at end of file
+pub mod kimg;
+pub mod kconf;
+pub mod ktext;
+pub mod kfont;
+pub mod katlas;
+pub mod kanim;
+pub mod kpath;
+pub mod kaction;
+pub mod kpad;
+pub mod ktouch;
+pub mod kedit;
\ No newline at end of file
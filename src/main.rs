@@ -2,7 +2,18 @@ mod lib;
 mod application;
 mod krenderer;
 mod kaudio;
+mod ksynth;
 mod game;
+mod particles;
+mod parallax;
+mod sky;
+mod theme;
+mod camera;
+mod music;
+mod events;
+mod rebind;
+mod replay;
+mod scores;
 
 use application::*;
 use glutin::event::{Event, WindowEvent};
@@ -12,8 +23,42 @@ use std::env;
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
 
+    // gball pack-atlas <dir> <out> [padding]
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 4 && args[1] == "pack-atlas" {
+        let padding = args.get(4).and_then(|p| p.parse().ok()).unwrap_or(1);
+        match lib::katlas::pack_dir(&args[2], &args[3], padding) {
+            Ok(m) => println!("packed {} regions into {}x{} {}.png", m.regions.len(), m.w, m.h, args[3]),
+            Err(e) => {
+                eprintln!("pack-atlas: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
+    // gball render-audio <recording> <out.wav>
+    if args.len() >= 4 && args[1] == "render-audio" {
+        match replay::render_audio_file(&args[2], &args[3]) {
+            Ok(seconds) => println!("rendered {:.1}s of play to {}", seconds, args[3]),
+            Err(e) => {
+                eprintln!("render-audio: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
     let event_loop = glutin::event_loop::EventLoop::new();
     let mut application = Application::new(&event_loop);
+
+    // gball record <recording>
+    if args.len() >= 3 && args[1] == "record" {
+        if let Err(e) = application.record_to(&args[2]) {
+            eprintln!("record: {}: {}", args[2], e);
+            std::process::exit(1);
+        }
+    }
     
     event_loop.run(move |event, _, control_flow| {
         application.handle_event(&event);
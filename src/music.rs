@@ -0,0 +1,394 @@
+use std::f32::consts::TAU;
+use std::sync::{Arc, Mutex};
+
+use crate::lib::kmath::*;
+use crate::kaudio::*;
+use crate::ksynth::*;
+use crate::game::*;
+use crate::events::*;
+
+// Ambient music, made up as it goes. The run seed picks a key, a scale, a four
+// chord progression and an arp pattern, then it plays forever as one mixer
+// voice. The game writes a Mood each frame and the music follows it:
+//
+// - tempo goes up the further into the run and with combo
+// - layers come in as things get intense: pad, bass, arp, hats
+// - the low pass opens up with danger and combo
+// - paused is underwater, filter shut right down and a slow wobble
+// - dying plays a stinger and fades the rest out until the next run
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mood {
+    pub seed: u32,
+    pub speed: f32,     // 1 at the start of a run up to 2 a long way in
+    pub combo: u32,
+    pub danger: f32,    // 0..1, how close to a wall
+    pub paused: bool,
+    pub dead: bool,
+}
+
+impl Default for Mood {
+    fn default() -> Mood {
+        Mood { seed: 0, speed: 1.0, combo: 0, danger: 0.0, paused: false, dead: false }
+    }
+}
+
+impl Mood {
+    pub fn tempo(&self) -> f32 {
+        80.0 * self.speed.clamp(0.5, 2.0) + 3.0 * self.combo.min(8) as f32
+    }
+
+    // pad on its own up to everything
+    pub fn layer_count(&self) -> usize {
+        let energy = self.speed + self.combo as f32 * 0.25 + self.danger;
+        1 + (energy >= 1.0) as usize + (energy >= 1.75) as usize + (energy >= 2.5) as usize
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        if self.paused {
+            return 350.0;
+        }
+        (1200.0 + 2500.0 * self.danger.clamp(0.0, 1.0) + 300.0 * self.combo.min(8) as f32).min(8000.0)
+    }
+}
+
+const SCALES: [&[i32]; 3] = [
+    &[0, 2, 3, 5, 7, 8, 10],    // aeolian
+    &[0, 2, 3, 5, 7, 9, 10],    // dorian
+    &[0, 2, 4, 6, 7, 9, 11],    // lydian
+];
+const STEPS_PER_BAR: usize = 16;
+const LAYERS: usize = 4;
+
+fn midi_to_hz(n: f32) -> f32 {
+    440.0 * 2.0f32.powf((n - 69.0) / 12.0)
+}
+
+struct Song {
+    root: i32,  // midi
+    scale: &'static [i32],
+    progression: [usize; 4],    // scale degrees
+    arp: [Option<usize>; STEPS_PER_BAR],    // chord tone per step
+    bass: [bool; STEPS_PER_BAR],
+}
+
+impl Song {
+    fn new(seed: u32) -> Song {
+        let r = |i: u32| khash(seed.wrapping_mul(2654435761).wrapping_add(i));
+        let degrees = [3, 4, 5, 2, 6];
+        let mut progression = [0; 4];
+        for (i, p) in progression.iter_mut().enumerate().skip(1) {
+            *p = degrees[r(2 + i as u32) as usize % degrees.len()];
+        }
+        let mut arp = [None; STEPS_PER_BAR];
+        let mut bass = [false; STEPS_PER_BAR];
+        for i in 0..STEPS_PER_BAR {
+            if r(10 + i as u32) % 5 != 0 {
+                arp[i] = Some(r(30 + i as u32) as usize % 5);
+            }
+            bass[i] = i % 8 == 0 || r(50 + i as u32) % 6 == 0;
+        }
+        Song {
+            root: 45 + (r(0) % 7) as i32,
+            scale: SCALES[r(1) as usize % SCALES.len()],
+            progression,
+            arp,
+            bass,
+        }
+    }
+
+    // stacked thirds up from the chords degree
+    fn chord_note(&self, chord: usize, tone: usize) -> f32 {
+        let n = self.scale.len();
+        let degree = self.progression[chord % 4] + tone * 2;
+        (self.root + 12 * (degree / n) as i32 + self.scale[degree % n]) as f32
+    }
+}
+
+// one shot with an exponential decay
+#[derive(Clone, Copy)]
+struct Pluck {
+    phase: f32,
+    freq: f32,
+    level: f32,
+    decay: f32,     // per sample
+}
+
+impl Pluck {
+    fn new() -> Pluck {
+        Pluck { phase: 0.0, freq: 0.0, level: 0.0, decay: 1.0 }
+    }
+
+    fn trigger(&mut self, freq: f32, seconds: f32) {
+        self.freq = freq;
+        self.level = 1.0;
+        self.decay = (-1.0 / (seconds * SAMPLE_RATE as f32)).exp();
+    }
+
+    fn next(&mut self, wave: Wave, noise: f32) -> f32 {
+        let s = wave.sample(self.phase, noise) * self.level;
+        self.phase = (self.phase + self.freq / SAMPLE_RATE as f32).fract();
+        self.level *= self.decay;
+        s
+    }
+}
+
+fn stinger(song: &Song) -> Vec<f32> {
+    let mut out: Vec<f32> = Vec::new();
+    for tone in 0..3 {
+        let f = midi_to_hz(song.chord_note(0, tone) - 12.0);
+        let mut p = Patch::new("stinger", 1.8, f);
+        p.freq_end = f * 0.7;
+        p.env = Env { attack: 0.01, decay: 0.6, sustain: 0.4, release: 1.0 };
+        p.wobble_rate = 5.0;
+        p.wobble_depth = 0.1;
+        p.gain = 0.3;
+        p.ops.push(Operator { wave: Wave::Sine, ratio: 2.0, detune: 1.5, level: 1.0, env: Env::flat() });
+        p.ops[0].wave = Wave::Saw;
+        p.matrix[0][1] = 1.2;
+        let s = p.render();
+        out.resize(out.len().max(s.len()), 0.0);
+        for (o, x) in out.iter_mut().zip(s) {
+            *o += x;
+        }
+    }
+    out
+}
+
+pub struct Music {
+    control: Arc<Mutex<Mood>>,
+    mood: Mood,
+    song: Song,
+
+    t: f32,
+    step_pos: f64,
+    step: usize,
+
+    pad_phases: [f32; 6],
+    bass: Pluck,
+    arp: Pluck,
+    hat: Pluck,
+    layer_gain: [f32; LAYERS],
+
+    cutoff: f32,
+    lp: [f32; 2],
+    volume: f32,
+
+    stinger: Vec<f32>,
+    stinger_pos: Option<usize>,
+    noise_i: u32,
+}
+
+impl Music {
+    pub fn new(mood: Mood) -> Music {
+        let song = Song::new(mood.seed);
+        Music {
+            control: Arc::new(Mutex::new(mood)),
+            mood,
+            stinger: stinger(&song),
+            song,
+            t: 0.0,
+            step_pos: 0.0,
+            step: usize::MAX,
+            pad_phases: [0.0; 6],
+            bass: Pluck::new(),
+            arp: Pluck::new(),
+            hat: Pluck::new(),
+            layer_gain: [0.0; LAYERS],
+            cutoff: mood.cutoff(),
+            lp: [0.0; 2],
+            volume: 1.0,
+            stinger_pos: None,
+            noise_i: 0,
+        }
+    }
+
+    // write to this to steer it once its playing
+    pub fn control(&self) -> Arc<Mutex<Mood>> {
+        self.control.clone()
+    }
+
+    fn set_mood(&mut self, mood: Mood) {
+        if mood.seed != self.mood.seed {
+            self.song = Song::new(mood.seed);
+            self.stinger = stinger(&self.song);
+        }
+        if mood.dead && !self.mood.dead {
+            self.stinger_pos = Some(0);
+        }
+        self.mood = mood;
+    }
+
+    fn trigger_step(&mut self, n: usize) {
+        let chord = n / STEPS_PER_BAR;
+        let s = n % STEPS_PER_BAR;
+        if self.song.bass[s] {
+            self.bass.trigger(midi_to_hz(self.song.chord_note(chord, 0) - 12.0), 0.35);
+        }
+        if let Some(tone) = self.song.arp[s] {
+            self.arp.trigger(midi_to_hz(self.song.chord_note(chord, tone) + 12.0), 0.12);
+        }
+        if s % 2 == 1 {
+            self.hat.trigger(0.0, 0.025);
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let sr = SAMPLE_RATE as f32;
+        let m = self.mood;
+
+        // slowed right down underwater
+        let tempo = if m.paused {m.tempo() * 0.5} else {m.tempo()};
+        self.step_pos += (tempo * 4.0 / 60.0 / sr) as f64;
+        let step = self.step_pos as usize;
+        if step != self.step {
+            self.step = step;
+            self.trigger_step(step);
+        }
+
+        let layers = if m.dead {0} else {m.layer_count()};
+        for (i, g) in self.layer_gain.iter_mut().enumerate() {
+            let target = if i < layers {1.0} else {0.0};
+            *g += (target - *g) * 0.00005;
+        }
+
+        // pad swells in at the start of each bar
+        let chord = step / STEPS_PER_BAR;
+        let bar_u = (self.step_pos as f32 % STEPS_PER_BAR as f32) / STEPS_PER_BAR as f32;
+        let pad_env = (bar_u * 4.0).min(1.0) * (0.7 + 0.3 * (1.0 - bar_u));
+        let wobble = if m.paused {1.0 + 0.004 * (TAU * 0.3 * self.t).sin()} else {1.0};
+        let mut pad = 0.0;
+        for (i, phase) in self.pad_phases.iter_mut().enumerate() {
+            let detune = if i % 2 == 0 {0.998} else {1.002};
+            let f = midi_to_hz(self.song.chord_note(chord, i / 2)) * detune * wobble;
+            pad += Wave::Saw.sample(*phase, 0.0);
+            *phase = (*phase + f / sr).fract();
+        }
+        pad *= pad_env / 6.0;
+
+        self.noise_i = self.noise_i.wrapping_add(1);
+        let noise = krand(self.noise_i.wrapping_mul(747796405)) * 2.0 - 1.0;
+        let bass = self.bass.next(Wave::Triangle, noise);
+        let arp = self.arp.next(Wave::Square, noise) * 0.5;
+        let hat = self.hat.next(Wave::Noise, noise) * 0.3;
+        let mix = pad * self.layer_gain[0] + bass * self.layer_gain[1] + arp * self.layer_gain[2] + hat * self.layer_gain[3];
+
+        // two one pole low passes
+        self.cutoff += (m.cutoff() - self.cutoff) * 0.0003;
+        let a = 1.0 - (-TAU * self.cutoff / sr).exp();
+        self.lp[0] += (mix - self.lp[0]) * a;
+        self.lp[1] += (self.lp[0] - self.lp[1]) * a;
+
+        let target_volume = if m.paused {0.6} else {1.0};
+        self.volume += (target_volume - self.volume) * 0.0002;
+
+        let mut out = self.lp[1] * self.volume * 0.4;
+        if let Some(pos) = self.stinger_pos {
+            out += self.stinger[pos];
+            self.stinger_pos = if pos + 1 < self.stinger.len() {Some(pos + 1)} else {None};
+        }
+        self.t += 1.0 / sr;
+        out
+    }
+}
+
+impl Source for Music {
+    fn fill(&mut self, out: &mut [f32]) -> bool {
+        let mood = *self.control.lock().unwrap();
+        self.set_mood(mood);
+        for o in out.iter_mut() {
+            *o = self.next_sample();
+        }
+        true
+    }
+}
+
+// patch names in sfx.conf
+pub fn sfx_for(e: &GameEvent) -> Option<&'static str> {
+    match e {
+        GameEvent::Flipped { .. } => Some("flip"),
+        GameEvent::PickupCollected { .. } => Some("pickup"),
+        GameEvent::Died { .. } => Some("death"),
+        GameEvent::NearMiss => Some("near_miss"),
+        GameEvent::ScoreMilestone(_) => Some("milestone"),
+        GameEvent::ThemeChanged => Some("theme"),
+        GameEvent::WallPassed => None,
+    }
+}
+
+// everything the game sounds like hooked up to a mixer. the live audio thread
+// and offline renders both go through this so they sound the same
+pub struct GameAudio {
+    sfx: SfxBank,
+    music: Arc<Mutex<Mood>>,
+}
+
+impl GameAudio {
+    pub fn new(mixer: &mut Mixer, mood: Mood, patches: &[Patch]) -> GameAudio {
+        // a mixer thats had one before keeps its buses
+        let sfx_bus = mixer.bus("sfx").unwrap_or_else(|| mixer.add_bus("sfx", 0.8));
+        let music_bus = mixer.bus("music").unwrap_or_else(|| mixer.add_bus("music", 0.5));
+        let music = Music::new(mood);
+        let control = music.control();
+        let voice = mixer.play(Box::new(music), VoiceParams { bus: music_bus, keep: true, ..Default::default() });
+        if !mixer.is_playing(voice) {
+            println!("audio: no room left for the music");
+        }
+        GameAudio {
+            sfx: SfxBank::new(patches, sfx_bus),
+            music: control,
+        }
+    }
+
+    // once a frame after Game::frame
+    pub fn update(&self, mixer: &mut Mixer, game: &Game) {
+        *self.music.lock().unwrap() = game.mood();
+        for e in game.events().iter() {
+            if let Some(name) = sfx_for(e) {
+                self.sfx.play(mixer, name);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_music() {
+    let calm = Mood::default();
+    let hot = Mood { speed: 1.3, combo: 4, danger: 0.8, ..calm };
+    assert!(hot.tempo() > calm.tempo());
+    assert!(hot.cutoff() > calm.cutoff());
+    assert_eq!(calm.layer_count(), 2);
+    assert_eq!(hot.layer_count(), 4);
+    assert!(Mood { paused: true, ..hot }.cutoff() < calm.cutoff());
+
+    let render = |mood: Mood, n: usize| {
+        let mut m = Music::new(mood);
+        let mut out = vec![0.0; n];
+        m.fill(&mut out);
+        out
+    };
+    // same seed same music, different seed something else
+    let a = render(hot, 44100);
+    assert_eq!(a, render(hot, 44100));
+    assert_ne!(a, render(Mood { seed: 7, ..hot }, 44100));
+    assert!(a.iter().any(|x| x.abs() > 0.01));
+
+    // underwater has less top end, roughness is how much it moves sample to sample.
+    // mostly fundamentals either way so the difference isnt huge
+    let rough = |s: &[f32]| {
+        let diff: f32 = s.windows(2).map(|w| (w[1] - w[0]).abs()).sum();
+        diff / s.iter().map(|x| x.abs()).sum::<f32>()
+    };
+    let under = render(Mood { paused: true, ..hot }, 44100);
+    assert!(rough(&under[22050..]) < rough(&a[22050..]) * 0.8);
+
+    // stinger comes in when dead goes true
+    let mut m = Music::new(calm);
+    let mut out = vec![0.0; 4410];
+    m.fill(&mut out);
+    *m.control().lock().unwrap() = Mood { dead: true, ..calm };
+    let mut stung = vec![0.0; 4410];
+    m.fill(&mut stung);
+    let peak = |s: &[f32]| s.iter().fold(0.0f32, |a, x| a.max(x.abs()));
+    assert!(peak(&stung) > peak(&out) * 2.0);
+}
@@ -0,0 +1,240 @@
+use crate::lib::kmath::*;
+use crate::lib::kconf::*;
+use crate::krenderer::*;
+use crate::game::RepeatTimer;
+
+// Background layers, all declared in layers.conf. Each layer spawns things off the
+// right edge of the screen, scrolls them left at its own speed and drops them
+// once they are off the left edge.
+
+pub const DEFAULT_LAYERS: &str = include_str!("../layers.conf");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloudStyle {
+    Puffy,
+    Flat,
+    Blocky,
+}
+
+impl CloudStyle {
+    pub fn from_name(s: &str) -> CloudStyle {
+        match s {
+            "flat" => CloudStyle::Flat,
+            "blocky" => CloudStyle::Blocky,
+            _ => CloudStyle::Puffy,
+        }
+    }
+
+    pub fn draw(&self, kc: &mut KRCanvas, r: Rect, seed: u32) {
+        match self {
+            CloudStyle::Puffy => kc.cloud(r, seed),
+            CloudStyle::Flat => {
+                // long rounded lozenge
+                let h = r.h * 0.6;
+                let body = Rect::new(r.x, r.bot() - h, r.w, h);
+                kc.rect(body);
+                kc.circle(Vec2::new(r.x, r.bot() - h/2.0), h/2.0);
+                kc.circle(Vec2::new(r.right(), r.bot() - h/2.0), h/2.0);
+            },
+            CloudStyle::Blocky => {
+                let mut row = r;
+                for i in 0..3 {
+                    kc.rect(row);
+                    let s = khash(seed.wrapping_add(i * 912347));
+                    let w = row.w * kuniform(s, 0.5, 0.8);
+                    let x = row.x + (row.w - w) * krand(s.wrapping_mul(3));
+                    row = Rect::new(x, row.y - r.h, w, r.h);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Generator {
+    Clouds,
+    Hills,
+    Stars,
+    City,
+}
+
+impl Generator {
+    pub fn from_name(s: &str) -> Option<Generator> {
+        match s {
+            "clouds" => Some(Generator::Clouds),
+            "hills" => Some(Generator::Hills),
+            "stars" => Some(Generator::Stars),
+            "city" => Some(Generator::City),
+            _ => None,
+        }
+    }
+
+    pub fn draw(&self, kc: &mut KRCanvas, r: Rect, seed: u32, cloud_style: CloudStyle) {
+        match self {
+            Generator::Clouds => cloud_style.draw(kc, r, seed),
+            Generator::Hills => {
+                // couple of overlapping lumps sitting on the bottom edge
+                for i in 0..3 {
+                    let s = khash(seed.wrapping_add(i * 1923871));
+                    let cx = r.x + r.w * kuniform(s, 0.2, 0.8);
+                    let hw = r.w * kuniform(s.wrapping_mul(3), 0.3, 0.5);
+                    let h = r.h * kuniform(s.wrapping_mul(7), 0.4, 1.0);
+                    kc.triangle(Vec2::new(cx - hw, r.bot()), Vec2::new(cx, r.bot() - h), Vec2::new(cx + hw, r.bot()));
+                }
+            },
+            Generator::Stars => {
+                for i in 0..12 {
+                    let s = khash(seed.wrapping_add(i * 3498712));
+                    let p = Vec2::new(r.x + r.w * krand(s), r.y + r.h * krand(s.wrapping_mul(5)));
+                    let size = kuniform(s.wrapping_mul(11), 0.002, 0.005);
+                    kc.rect(Rect::centered(p, size, size));
+                }
+            },
+            Generator::City => {
+                let n = 5;
+                let bw = r.w / n as f32;
+                for i in 0..n {
+                    let s = khash(seed.wrapping_add(i * 9871233));
+                    let h = r.h * kuniform(s, 0.3, 1.0);
+                    kc.rect(Rect::new(r.x + i as f32 * bw, r.bot() - h, bw * 0.9, h));
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LayerDef {
+    pub generator: Generator,
+    pub speed: f32,
+    pub layer: RenderLayer,
+    pub tint: Vec4,
+    pub y: f32,             // top of the spawned rect
+    pub size: Vec2,
+    pub spawn_period: f32,
+    pub spawn_chance: f32,
+    pub prewarm: bool,      // start with the screen already populated
+    pub cull_margin: f32,   // how far past the left edge before things get dropped
+    pub salt: u32,
+}
+
+impl LayerDef {
+    pub fn from_conf(section: &ConfSection) -> Option<LayerDef> {
+        let generator = Generator::from_name(section.str_or("generator", ""));
+        if generator.is_none() {
+            println!("layers: unknown generator in layer '{}'", section.str_or("name", ""));
+        }
+        // prewarm steps back spawn_period at a time, 0 or less never gets anywhere
+        let spawn_period = section.f32_or("spawn_period", 1.0);
+        if spawn_period <= 0.0 || spawn_period.is_nan() {
+            println!("layers: spawn_period has to be more than 0 in layer '{}'", section.str_or("name", ""));
+            return None;
+        }
+        Some(LayerDef {
+            generator: generator?,
+            speed: section.f32_or("speed", 0.05),
+            layer: RenderLayer::from_name(section.str_or("layer", "")).unwrap_or(RenderLayer::CloudsMid),
+            tint: section.vec4_or("tint", Vec4::new(1.0, 1.0, 1.0, 1.0)),
+            y: section.f32_or("y", 0.5),
+            size: section.vec2_or("size", Vec2::new(0.1, 0.05)),
+            spawn_period,
+            spawn_chance: section.f32_or("spawn_chance", 1.0),
+            prewarm: section.bool_or("prewarm", false),
+            cull_margin: section.f32_or("cull_margin", 0.5),
+            salt: section.f32_or("salt", 0.0) as u32,
+        })
+    }
+}
+
+pub fn parse_layers(sections: &[ConfSection]) -> Vec<LayerDef> {
+    sections.iter()
+        .filter(|s| s.name == "layer")
+        .filter_map(LayerDef::from_conf)
+        .collect()
+}
+
+pub fn load_layers() -> Vec<LayerDef> {
+    parse_layers(&load_conf(&["layers.conf", "../../layers.conf"], DEFAULT_LAYERS))
+}
+
+pub struct ParallaxLayer {
+    pub def: LayerDef,
+    items: Vec<(u32, f32)>,   // seed, x
+    spawn_timer: RepeatTimer,
+    seed: u32,
+}
+
+impl ParallaxLayer {
+    pub fn new(def: LayerDef, seed: u32) -> ParallaxLayer {
+        ParallaxLayer {
+            spawn_timer: RepeatTimer::new(def.spawn_period as f64),
+            seed: khash(seed ^ def.salt.wrapping_mul(2654435761)),
+            def,
+            items: Vec::new(),
+        }
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        self.seed = khash(self.seed.wrapping_add(394712377));
+        self.seed
+    }
+
+    fn try_spawn(&mut self, x: f32) {
+        let roll = self.next_seed();
+        if chance(roll, self.def.spawn_chance) {
+            let s = self.next_seed();
+            self.items.push((s, x));
+        }
+    }
+
+    pub fn prewarm(&mut self, screen_rect: Rect) {
+        if !self.def.prewarm || self.def.speed <= 0.0 {
+            return;
+        }
+        let step = self.def.spawn_period * self.def.speed;
+        let mut x = screen_rect.right() + 0.2;
+        while x > screen_rect.left() - self.def.size.x {
+            x -= step;
+            self.try_spawn(x);
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, screen_rect: Rect) {
+        if self.spawn_timer.tick(dt as f64) {
+            self.try_spawn(screen_rect.right() + 0.2);
+        }
+        for (_, x) in self.items.iter_mut() {
+            *x -= self.def.speed * dt;
+        }
+        let cull_x = screen_rect.left() - self.def.size.x - self.def.cull_margin;
+        self.items.retain(|(_, x)| *x > cull_x);
+    }
+
+    pub fn draw(&self, kc: &mut KRCanvas, tint: Vec4, cloud_style: CloudStyle) {
+        kc.set_layer(self.def.layer);
+        kc.set_colour(self.def.tint.mul_elem(tint));
+        for (seed, x) in self.items.iter() {
+            self.def.generator.draw(kc, Rect::new(*x, self.def.y, self.def.size.x, self.def.size.y), *seed, cloud_style);
+        }
+    }
+}
+
+#[test]
+pub fn test_layers_cull() {
+    let def = LayerDef::from_conf(&parse_conf("[layer]\ngenerator = clouds\nspeed = 1.0\nspawn_period = 0.1\ncull_margin = 0.0\nsize = 0.1 0.1")[0]).unwrap();
+    let screen = Rect::new(0.0, 0.0, 1.0, 1.0);
+    let mut layer = ParallaxLayer::new(def, 1);
+    for _ in 0..1000 {
+        layer.update(0.05, screen);
+    }
+    // everything older than a screen width and a bit should be gone
+    assert!(layer.items.len() <= 15);
+    assert!(!layer.items.is_empty());
+
+    // a period that would hang prewarm doesnt make it in
+    for period in ["0", "-0.5"] {
+        let conf = format!("[layer]\ngenerator = clouds\nprewarm = true\nspawn_period = {}", period);
+        assert!(LayerDef::from_conf(&parse_conf(&conf)[0]).is_none());
+    }
+}
+
@@ -0,0 +1,260 @@
+use std::f32::consts::PI;
+
+use crate::lib::kmath::*;
+use crate::krenderer::*;
+
+// Fixed size pool, when its full the oldest particle gets stomped
+
+#[derive(Clone, Copy)]
+pub struct ParticleStyle {
+    pub lifetime: f32,
+    pub speed_min: f32,
+    pub speed_max: f32,
+    pub spread: f32,        // radians either side of the emit direction
+    pub gravity: Vec2,
+    pub drag: f32,
+    pub colour_start: Vec4,
+    pub colour_end: Vec4,
+    pub size_start: f32,
+    pub size_end: f32,
+    pub inherit: f32,       // how much of the emitters velocity gets added
+}
+
+#[derive(Clone, Copy)]
+struct Particle {
+    pos: Vec2,
+    vel: Vec2,
+    age: f32,
+    style: ParticleStyle,
+    alive: bool,
+}
+
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    next: usize,
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(capacity: usize, seed: u32) -> ParticleSystem {
+        ParticleSystem {
+            particles: vec![Particle {
+                pos: Vec2::new(0.0, 0.0),
+                vel: Vec2::new(0.0, 0.0),
+                age: 0.0,
+                style: DEATH,
+                alive: false,
+            }; capacity],
+            next: 0,
+            seed,
+        }
+    }
+
+    fn rand(&mut self) -> f32 {
+        self.seed = khash(self.seed.wrapping_add(1234577));
+        krand(self.seed)
+    }
+
+    pub fn spawn(&mut self, style: &ParticleStyle, pos: Vec2, dir: f32, base_vel: Vec2) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let theta = dir + (self.rand() * 2.0 - 1.0) * style.spread;
+        let speed = lerp(style.speed_min, style.speed_max, self.rand());
+        let vel = Vec2::new(speed, 0.0).rotate(theta) + base_vel * style.inherit;
+        self.particles[self.next] = Particle {
+            pos,
+            vel,
+            age: 0.0,
+            style: *style,
+            alive: true,
+        };
+        self.next = (self.next + 1) % self.particles.len();
+    }
+
+    pub fn burst(&mut self, style: &ParticleStyle, pos: Vec2, dir: f32, count: usize) {
+        for _ in 0..count {
+            self.spawn(style, pos, dir, Vec2::new(0.0, 0.0));
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for p in self.particles.iter_mut().filter(|p| p.alive) {
+            p.age += dt;
+            if p.age >= p.style.lifetime {
+                p.alive = false;
+                continue;
+            }
+            p.vel = p.vel + p.style.gravity * dt;
+            p.vel = p.vel * (1.0 - p.style.drag * dt).max(0.0);
+            p.pos = p.pos + p.vel * dt;
+        }
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.particles.iter().filter(|p| p.alive).count()
+    }
+
+    pub fn draw(&self, kc: &mut KRCanvas) {
+        for p in self.particles.iter().filter(|p| p.alive) {
+            let t = p.age / p.style.lifetime;
+            kc.set_colour(p.style.colour_start.lerp(p.style.colour_end, t));
+            kc.poly(p.pos, lerp(p.style.size_start, p.style.size_end, t), 6);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum EmitterKind {
+    Burst(usize),       // everything at once when fired
+    Continuous(f32),    // particles per second
+    Trail(f32),         // particles per second, smeared along the path since last tick
+}
+
+pub struct Emitter {
+    pub kind: EmitterKind,
+    pub style: ParticleStyle,
+    pub dir: f32,
+    pub active: bool,
+    acc: f32,
+    last_pos: Option<Vec2>,
+}
+
+impl Emitter {
+    pub fn new(kind: EmitterKind, style: ParticleStyle, dir: f32) -> Emitter {
+        Emitter {
+            kind,
+            style,
+            dir,
+            active: true,
+            acc: 0.0,
+            last_pos: None,
+        }
+    }
+
+    pub fn fire(&mut self, ps: &mut ParticleSystem, pos: Vec2) {
+        match self.kind {
+            EmitterKind::Burst(n) => ps.burst(&self.style, pos, self.dir, n),
+            EmitterKind::Continuous(_) | EmitterKind::Trail(_) => ps.spawn(&self.style, pos, self.dir, Vec2::new(0.0, 0.0)),
+        }
+    }
+
+    pub fn tick(&mut self, ps: &mut ParticleSystem, pos: Vec2, dt: f32) {
+        let last_pos = self.last_pos.unwrap_or(pos);
+        self.last_pos = Some(pos);
+        if !self.active || dt <= 0.0 {
+            return;
+        }
+        let vel = (pos - last_pos) / dt;
+        match self.kind {
+            EmitterKind::Burst(_) => {},
+            EmitterKind::Continuous(rate) => {
+                self.acc += rate * dt;
+                while self.acc >= 1.0 {
+                    self.acc -= 1.0;
+                    ps.spawn(&self.style, pos, self.dir, vel);
+                }
+            },
+            EmitterKind::Trail(rate) => {
+                self.acc += rate * dt;
+                let n = self.acc.floor();
+                let mut i = 0.0;
+                while self.acc >= 1.0 {
+                    self.acc -= 1.0;
+                    i += 1.0;
+                    ps.spawn(&self.style, last_pos.lerp(pos, i / n), self.dir, vel);
+                }
+            },
+        }
+    }
+}
+
+/***************************************************
+ * Presets
+ ***************************************************/
+
+pub const FLIP: ParticleStyle = ParticleStyle {
+    lifetime: 0.35,
+    speed_min: 0.2,
+    speed_max: 0.5,
+    spread: PI/3.0,
+    gravity: Vec2::new(0.0, 0.0),
+    drag: 4.0,
+    colour_start: Vec4::new(0.6, 1.0, 1.0, 1.0),
+    colour_end: Vec4::new(0.0, 0.9, 0.9, 0.0),
+    size_start: 0.006,
+    size_end: 0.001,
+    inherit: 0.0,
+};
+
+pub const PICKUP: ParticleStyle = ParticleStyle {
+    lifetime: 0.6,
+    speed_min: 0.1,
+    speed_max: 0.6,
+    spread: PI,
+    gravity: Vec2::new(0.0, 0.8),
+    drag: 2.0,
+    colour_start: Vec4::new(1.0, 0.9, 0.3, 1.0),
+    colour_end: Vec4::new(0.8, 0.0, 0.0, 0.0),
+    size_start: 0.008,
+    size_end: 0.002,
+    inherit: 0.0,
+};
+
+pub const DEATH: ParticleStyle = ParticleStyle {
+    lifetime: 1.2,
+    speed_min: 0.1,
+    speed_max: 0.9,
+    spread: PI,
+    gravity: Vec2::new(0.0, 1.0),
+    drag: 1.5,
+    colour_start: Vec4::new(1.0, 1.0, 1.0, 1.0),
+    colour_end: Vec4::new(0.0, 0.9, 0.9, 0.0),
+    size_start: 0.012,
+    size_end: 0.0,
+    inherit: 0.0,
+};
+
+pub const TRAIL: ParticleStyle = ParticleStyle {
+    lifetime: 0.5,
+    speed_min: 0.0,
+    speed_max: 0.0,
+    spread: 0.0,
+    gravity: Vec2::new(0.0, 0.0),
+    drag: 0.0,
+    colour_start: Vec4::new(0.0, 0.0, 1.0, 0.6),
+    colour_end: Vec4::new(0.0, 0.0, 1.0, 0.0),
+    size_start: 0.015,
+    size_end: 0.002,
+    inherit: 0.0,
+};
+
+#[test]
+pub fn test_particle_pool() {
+    let mut ps = ParticleSystem::new(8, 1);
+    ps.burst(&FLIP, Vec2::new(0.0, 0.0), 0.0, 20);
+    assert_eq!(ps.live_count(), 8);
+    ps.update(FLIP.lifetime * 0.5);
+    assert_eq!(ps.live_count(), 8);
+    ps.update(FLIP.lifetime);
+    assert_eq!(ps.live_count(), 0);
+
+    // bursts go all at once when fired and never on a tick
+    let mut ps = ParticleSystem::new(64, 1);
+    let mut burst = Emitter::new(EmitterKind::Burst(5), FLIP, 0.0);
+    burst.tick(&mut ps, Vec2::new(0.0, 0.0), 0.1);
+    assert_eq!(ps.live_count(), 0);
+    burst.fire(&mut ps, Vec2::new(0.0, 0.0));
+    assert_eq!(ps.live_count(), 5);
+
+    // continuous keeps the rate across ticks that dont land on a whole particle, nothing while inactive
+    let mut ps = ParticleSystem::new(64, 1);
+    let mut steady = Emitter::new(EmitterKind::Continuous(10.0), DEATH, 0.0);
+    for _ in 0..10 {
+        steady.tick(&mut ps, Vec2::new(0.0, 0.0), 0.05);
+    }
+    assert_eq!(ps.live_count(), 5);
+    steady.active = false;
+    steady.tick(&mut ps, Vec2::new(0.0, 0.0), 1.0);
+    assert_eq!(ps.live_count(), 5);
+}
@@ -0,0 +1,130 @@
+use crate::lib::kinput::*;
+use crate::lib::kmath::*;
+use crate::lib::ktext::*;
+use crate::lib::kaction::*;
+use crate::lib::ktouch::*;
+use crate::krenderer::*;
+
+// Controls screen. Works on its own copy of the bindings and hands them back
+// when it closes. Up and down pick an action, confirm then whatever gets
+// pressed next is added to it, unless something it would be read alongside
+// already has it. On a touchscreen tapping a row picks it and tapping it
+// again is confirm.
+
+pub struct RebindScreen {
+    bindings: Bindings,
+    selected: usize,
+    capturing: bool,
+    message: String,
+}
+
+pub fn binding_names(b: &Bindings, a: Action) -> String {
+    let names: Vec<String> = b.get(a).iter().map(|i| i.name()).collect();
+    if names.is_empty() {"-".to_owned()} else {names.join(" ")}
+}
+
+// the screen inset a bit and a row height
+fn layout(r: Rect) -> (Rect, f32) {
+    let inner = r.dilate_pc(-0.05);
+    (inner, inner.h / (ACTIONS.len() + 4) as f32)
+}
+
+fn row_rect(r: Rect, i: usize) -> Rect {
+    let (inner, row_h) = layout(r);
+    Rect::new(inner.x, inner.y + row_h * (i + 2) as f32, inner.w, row_h)
+}
+
+impl RebindScreen {
+    pub fn new(bindings: Bindings) -> RebindScreen {
+        RebindScreen { bindings, selected: 0, capturing: false, message: String::new() }
+    }
+
+    pub fn selected(&self) -> Action {
+        ACTIONS[self.selected]
+    }
+
+    // Some(bindings) when its closed. r is where its drawn, for touches
+    pub fn update(&mut self, inputs: &FrameInputState, r: Rect) -> Option<Bindings> {
+        let a = self.selected();
+        if self.capturing {
+            if inputs.action_just_pressed(Action::Back) {
+                self.capturing = false;
+                self.message.clear();
+            } else if let Some(input) = inputs.any_just_pressed() {
+                self.capturing = false;
+                self.message = match self.bindings.bind(a, input) {
+                    Ok(()) => String::new(),
+                    Err(other) => format!("{} is already {}", input.name(), other.name()),
+                };
+            }
+            return None;
+        }
+
+        if inputs.action_just_pressed(Action::Back) {
+            return Some(self.bindings.clone());
+        }
+        if inputs.action_just_pressed(Action::MenuUp) {
+            self.selected = (self.selected + ACTIONS.len() - 1) % ACTIONS.len();
+            self.message.clear();
+        }
+        if inputs.action_just_pressed(Action::MenuDown) {
+            self.selected = (self.selected + 1) % ACTIONS.len();
+            self.message.clear();
+        }
+        let mut confirm = inputs.action_just_pressed(Action::Confirm);
+        for g in inputs.gestures.iter().filter(|g| g.kind == GestureKind::Tap) {
+            if let Some(i) = (0..ACTIONS.len()).find(|i| row_rect(r, *i).contains(g.to)) {
+                confirm |= i == self.selected;
+                self.selected = i;
+                self.message.clear();
+            }
+        }
+        let a = self.selected();
+        if confirm {
+            self.capturing = true;
+            self.message = format!("press something for {}", a.name());
+        }
+        if inputs.action_just_pressed(Action::Unbind) {
+            // losing these would lock you in here
+            if matches!(a, Action::Confirm | Action::Back | Action::MenuUp | Action::MenuDown) {
+                self.message = format!("{} cant be left unbound", a.name());
+            } else {
+                self.bindings.unbind_all(a);
+            }
+        }
+        None
+    }
+
+    pub fn draw(&self, kc: &mut KRCanvas, r: Rect, text: Vec4) {
+        kc.set_layer(RenderLayer::Overlay);
+        kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 0.8));
+        kc.rect(r);
+
+        let (inner, row_h) = layout(r);
+        let style = TextStyle::new(row_h * 0.6).align(HAlign::Left, VAlign::Middle).no_wrap();
+        kc.set_colour(text);
+        kc.text("controls", Rect::new(inner.x, inner.y, inner.w, row_h), &style);
+        for (i, a) in ACTIONS.iter().enumerate() {
+            let row = row_rect(r, i);
+            if i == self.selected {
+                kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 0.15));
+                kc.rect(row);
+            }
+            let conflicted = self.bindings.get(*a).iter().any(|input| self.bindings.conflict(*a, *input).is_some());
+            kc.set_colour(if conflicted {Vec4::new(1.0, 0.3, 0.3, 1.0)} else {text});
+            kc.text(a.name(), row.child(0.02, 0.0, 0.4, 1.0), &style);
+            let bound = if i == self.selected && self.capturing {"...".to_owned()} else {binding_names(&self.bindings, *a)};
+            kc.text(&bound, row.child(0.45, 0.0, 0.55, 1.0), &style);
+        }
+        kc.set_colour(text);
+        let help = if self.message.is_empty() {
+            format!("{} add, {} clear, {} done",
+                binding_names(&self.bindings, Action::Confirm),
+                binding_names(&self.bindings, Action::Unbind),
+                binding_names(&self.bindings, Action::Back))
+        } else {
+            self.message.clone()
+        };
+        kc.text(&help, Rect::new(inner.x, inner.bot() - row_h, inner.w, row_h), &style);
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write, LineWriter};
+
+use std::rc::Rc;
+
+use crate::lib::kinput::*;
+use crate::lib::kmath::*;
+use crate::lib::kconf::*;
+use crate::lib::kaction::*;
+use crate::lib::ktouch::*;
+use crate::krenderer::*;
+use crate::kaudio::*;
+use crate::ksynth::*;
+use crate::music::*;
+use crate::game::*;
+use crate::scores::*;
+
+// Recorded runs. Everything the game reads off FrameInputState that matters to
+// the simulation goes in, one line per frame, so playing it back through Game
+// gives the same run. Actions get recorded as well as the raw inputs, the
+// bindings and high score table it started with go at the top since the name
+// box and the controls screen go off those. The file is kconf:
+//
+// seed = 0
+// [bindings]
+// ...
+// [score]
+// ...
+// [frames]
+// frame = 0.016667 3405691582 +flip >flip@0.00412 ^Space@0.00412    # dt, frame seed, then the rest
+//
+// actions are + just pressed, = held, - just released, anything not there is up.
+// > and < are presses and releases with how far into the frame they happened,
+// so flips land at the same physics step they did live. ^ and ~ are the same
+// for raw inputs, '6b is a typed char in hex, *V+ctrl a typed key, %tap@t@x,y@x,y
+// a gesture from and to, !blur and !focus the window losing and getting focus.
+// Offline audio renders play a recording through Game and GameAudio into a
+// WavSink, no window or audio device involved.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordedFrame {
+    pub dt: f64,
+    pub seed: u32,
+    pub actions: Vec<(Action, KeyStatus)>,
+    pub edges: Vec<ActionEdge>,     // t is from the start of the frame
+    pub inputs: Vec<InputEdge>,     // t too, only bindable ones
+    pub typed: Vec<Typed>,
+    pub gestures: Vec<Gesture>,     // t too
+    pub focus: Option<bool>,
+}
+
+fn parse_vec2(s: &str) -> Option<Vec2> {
+    let (x, y) = s.split_once(',')?;
+    Some(Vec2::new(x.parse().ok()?, y.parse().ok()?))
+}
+
+fn status_prefix(s: KeyStatus) -> Option<char> {
+    match s {
+        KeyStatus::JustPressed => Some('+'),
+        KeyStatus::Pressed => Some('='),
+        KeyStatus::JustReleased => Some('-'),
+        KeyStatus::Released => None,
+    }
+}
+
+impl RecordedFrame {
+    pub fn from_inputs(inputs: &FrameInputState) -> RecordedFrame {
+        let actions = ACTIONS.iter()
+            .map(|a| (*a, inputs.action(*a)))
+            .filter(|(_, s)| status_prefix(*s).is_some())
+            .collect();
+        let start = inputs.t - inputs.dt;
+        let edges = inputs.action_edges.iter().map(|e| ActionEdge { t: e.t - start, ..*e }).collect();
+        // anything that couldnt be bound cant be told apart by the game either
+        let raw = inputs.edges.iter().filter(|e| e.input.bindable()).map(|e| InputEdge { t: e.t - start, ..*e }).collect();
+        let typed = inputs.typed.iter().filter(|t| match t {
+            Typed::Key { key, .. } => Input::Key(*key).bindable(),
+            Typed::Char(_) => true,
+        }).copied().collect();
+        let gestures = inputs.gestures.iter().map(|g| Gesture { t: g.t - start, ..*g }).collect();
+        RecordedFrame { dt: inputs.dt, seed: inputs.seed, actions, edges, inputs: raw, typed, gestures, focus: inputs.focus }
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut s = format!("{} {}", self.dt, self.seed);
+        for (a, status) in self.actions.iter() {
+            if let Some(c) = status_prefix(*status) {
+                s.push(' ');
+                s.push(c);
+                s.push_str(a.name());
+            }
+        }
+        for e in self.edges.iter() {
+            s.push_str(&format!(" {}{}@{}", if e.pressed {'>'} else {'<'}, e.action.name(), e.t));
+        }
+        for e in self.inputs.iter() {
+            s.push_str(&format!(" {}{}@{}", if e.pressed {'^'} else {'~'}, e.input.name(), e.t));
+        }
+        // chars in hex so spaces and #s dont upset the file
+        for t in self.typed.iter() {
+            match *t {
+                Typed::Char(c) => s.push_str(&format!(" '{:x}", c as u32)),
+                Typed::Key { key, ctrl, shift } => {
+                    s.push_str(&format!(" *{}", Input::Key(key).name()));
+                    if ctrl {
+                        s.push_str("+ctrl");
+                    }
+                    if shift {
+                        s.push_str("+shift");
+                    }
+                },
+            }
+        }
+        for g in self.gestures.iter() {
+            s.push_str(&format!(" %{}@{}@{},{}@{},{}", g.kind.name(), g.t, g.from.x, g.from.y, g.to.x, g.to.y));
+        }
+        match self.focus {
+            Some(true) => s.push_str(" !focus"),
+            Some(false) => s.push_str(" !blur"),
+            None => {},
+        }
+        s
+    }
+
+    pub fn from_line(line: &str) -> Option<RecordedFrame> {
+        let mut words = line.split_whitespace();
+        let dt = words.next()?.parse().ok()?;
+        let seed = words.next()?.parse().ok()?;
+        let mut f = RecordedFrame { dt, seed, ..Default::default() };
+        for w in words {
+            let status = match w.chars().next() {
+                Some('+') => KeyStatus::JustPressed,
+                Some('=') => KeyStatus::Pressed,
+                Some('-') => KeyStatus::JustReleased,
+                Some(c @ ('>' | '<')) => {
+                    if let Some((name, t)) = w[1..].split_once('@') {
+                        if let (Some(action), Ok(t)) = (Action::from_name(name), t.parse()) {
+                            f.edges.push(ActionEdge { action, pressed: c == '>', t });
+                        }
+                    }
+                    continue;
+                },
+                Some(c @ ('^' | '~')) => {
+                    if let Some((name, t)) = w[1..].split_once('@') {
+                        if let (Some(input), Ok(t)) = (Input::from_name(name), t.parse()) {
+                            f.inputs.push(InputEdge { input, pressed: c == '^', t });
+                        }
+                    }
+                    continue;
+                },
+                Some('\'') => {
+                    if let Some(c) = u32::from_str_radix(&w[1..], 16).ok().and_then(char::from_u32) {
+                        f.typed.push(Typed::Char(c));
+                    }
+                    continue;
+                },
+                Some('*') => {
+                    let mut parts = w[1..].split('+');
+                    if let Some(Input::Key(key)) = parts.next().and_then(Input::from_name) {
+                        let mods: Vec<&str> = parts.collect();
+                        f.typed.push(Typed::Key { key, ctrl: mods.contains(&"ctrl"), shift: mods.contains(&"shift") });
+                    }
+                    continue;
+                },
+                Some('%') => {
+                    let parts: Vec<&str> = w[1..].split('@').collect();
+                    if let [kind, t, from, to] = parts[..] {
+                        if let (Some(kind), Ok(t), Some(from), Some(to)) = (GestureKind::from_name(kind), t.parse(), parse_vec2(from), parse_vec2(to)) {
+                            f.gestures.push(Gesture { kind, from, to, t });
+                        }
+                    }
+                    continue;
+                },
+                Some('!') => {
+                    match &w[1..] {
+                        "focus" => f.focus = Some(true),
+                        "blur" => f.focus = Some(false),
+                        _ => {},
+                    }
+                    continue;
+                },
+                _ => continue,
+            };
+            if let Some(a) = Action::from_name(&w[1..]) {
+                f.actions.push((a, status));
+            }
+        }
+        Some(f)
+    }
+
+    // t and frame are the running totals, what EventAggregator would have had.
+    // bindings are the builtin ones, Playback swaps in the recordings
+    pub fn to_inputs(&self, frame: u32, t: f64) -> FrameInputState {
+        let screen_rect = Rect::new(0.0, 0.0, PLAYFIELD_ASPECT, 1.0);
+        FrameInputState {
+            screen_rect,
+            window_rect: screen_rect,
+            pixels: Vec2::new(1600.0, 900.0),
+            scale_factor: 1.0,
+            mouse_pos: Vec2::new(0.0, 0.0),
+            mouse_delta: Vec2::new(0.0, 0.0),
+            keys: HashMap::new(),
+            actions: self.actions.iter().copied().collect(),
+            bindings: Rc::new(Bindings::builtin()),
+            lmb: KeyStatus::Released,
+            rmb: KeyStatus::Released,
+            mmb: KeyStatus::Released,
+            mouse_buttons: HashMap::new(),
+            scroll_lines: Vec2::new(0.0, 0.0),
+            scroll_pixels: Vec2::new(0.0, 0.0),
+            raw_mouse_delta: Vec2::new(0.0, 0.0),
+            cursor_inside: true,
+            cursor_entered: false,
+            cursor_left: false,
+            focus: self.focus,
+            pads: Vec::new(),
+            pads_connected: Vec::new(),
+            pads_disconnected: Vec::new(),
+            text: self.typed.iter().filter_map(|t| match t {Typed::Char(c) => Some(*c), _ => None}).collect(),
+            typed: self.typed.clone(),
+            touches: Vec::new(),
+            gestures: self.gestures.iter().map(|g| Gesture { t: g.t + t - self.dt, ..*g }).collect(),
+            edges: self.inputs.iter().map(|e| InputEdge { t: e.t + t - self.dt, ..*e }).collect(),
+            action_edges: self.edges.iter().map(|e| ActionEdge { t: e.t + t - self.dt, ..*e }).collect(),
+            t,
+            dt: self.dt,
+            frame,
+            seed: self.seed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub seed: u32,  // what Game::new got
+    pub bindings: Bindings,     // at the start, rebinds after that come out of the game
+    pub scores: HighScores,     // at the start, theyre what decide if it asks for a name
+    pub frames: Vec<RecordedFrame>,
+}
+
+fn header(seed: u32, bindings: &Bindings, scores: &HighScores) -> String {
+    format!("# gball recording, frame = dt seed actions\nseed = {}\n{}{}[frames]\n", seed, bindings.to_conf(), scores.to_conf())
+}
+
+impl Recording {
+    // older recordings have the frames up top and no bindings or scores
+    pub fn from_conf(sections: &[ConfSection]) -> Option<Recording> {
+        let top = sections.iter().find(|s| s.name.is_empty())?;
+        let seed = top.get("seed")?.parse().ok()?;
+        let frames = sections.iter()
+            .filter(|s| s.name.is_empty() || s.name == "frames")
+            .flat_map(|s| s.entries.iter())
+            .filter(|(k, _)| k == "frame")
+            .map(|(_, v)| RecordedFrame::from_line(v))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Recording { seed, bindings: Bindings::from_conf_or_builtin(sections), scores: HighScores::from_conf(sections), frames })
+    }
+
+    // the whole thing at once, for tests. Recorder writes it as it goes
+    #[cfg(test)]
+    pub fn to_conf(&self) -> String {
+        let mut s = header(self.seed, &self.bindings, &self.scores);
+        for f in self.frames.iter() {
+            s.push_str(&format!("frame = {}\n", f.to_line()));
+        }
+        s
+    }
+
+    pub fn load(path: &str) -> Result<Recording, String> {
+        let s = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Recording::from_conf(&parse_conf(&s)).ok_or_else(|| format!("{}: not a recording", path))
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|f| f.dt).sum()
+    }
+
+    // the game as it was when recording started
+    pub fn start(&self) -> Game {
+        let mut game = Game::new(self.seed);
+        game.set_high_scores(self.scores.clone());
+        game
+    }
+
+    pub fn playback(&self) -> Playback {
+        Playback { rec: self, bindings: Rc::new(self.bindings.clone()), i: 0, t: 0.0 }
+    }
+}
+
+// hands the frames out as inputs one at a time, picking up rebinds from the
+// game in between the way the application does
+pub struct Playback<'a> {
+    rec: &'a Recording,
+    bindings: Rc<Bindings>,
+    i: usize,
+    t: f64,
+}
+
+impl<'a> Playback<'a> {
+    pub fn next_inputs(&mut self, game: &mut Game) -> Option<FrameInputState> {
+        if let Some(b) = game.take_bindings() {
+            self.bindings = Rc::new(b);
+        }
+        let f = self.rec.frames.get(self.i)?;
+        self.t += f.dt;
+        let mut inputs = f.to_inputs(self.i as u32, self.t);
+        inputs.bindings = self.bindings.clone();
+        self.i += 1;
+        Some(inputs)
+    }
+}
+
+// writes as it goes so a crash or a kill still leaves a usable file
+pub struct Recorder {
+    w: LineWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str, game: &Game, bindings: &Bindings) -> io::Result<Recorder> {
+        let mut w = LineWriter::new(File::create(path)?);
+        write!(w, "{}", header(game.seed(), bindings, game.high_scores()))?;
+        Ok(Recorder { w })
+    }
+
+    pub fn frame(&mut self, inputs: &FrameInputState) -> io::Result<()> {
+        writeln!(self.w, "frame = {}", RecordedFrame::from_inputs(inputs).to_line())
+    }
+}
+
+fn render_until(mixer: &mut Mixer, sink: &mut dyn Sink, frame: u64, block: &mut Vec<f32>) -> io::Result<()> {
+    while mixer.now() < frame {
+        let n = ((frame - mixer.now()) as usize).min(BLOCK_FRAMES);
+        block.resize(n * 2, 0.0);
+        mixer.render(block);
+        sink.write(block)?;
+    }
+    Ok(())
+}
+
+// the whole audio track of a recording plus tail seconds to let things ring
+// out. sounds from a frame start at the end of that frame, same as live where
+// they get played after Game::frame. patches are passed in so whatever sfx.conf
+// is lying around doesnt change the render
+pub fn render_audio(rec: &Recording, patches: &[Patch], sink: &mut dyn Sink, tail: f64) -> io::Result<()> {
+    let mut game = rec.start();
+    let mut mixer = Mixer::new();
+    let game_audio = GameAudio::new(&mut mixer, game.mood(), patches);
+    let mut block = Vec::new();
+    let mut playback = rec.playback();
+    let mut t = 0.0;
+    while let Some(inputs) = playback.next_inputs(&mut game) {
+        t = inputs.t;
+        let mut kc = KRCanvas::new();
+        game.frame(&inputs, &mut kc);
+        game_audio.update(&mut mixer, &game);
+        render_until(&mut mixer, sink, seconds_to_frames(t), &mut block)?;
+    }
+    render_until(&mut mixer, sink, seconds_to_frames(t + tail), &mut block)
+}
+
+// gball render-audio <recording> <out.wav>
+pub fn render_audio_file(rec_path: &str, out: &str) -> Result<f64, String> {
+    let rec = Recording::load(rec_path)?;
+    let file = io::BufWriter::new(File::create(out).map_err(|e| format!("{}: {}", out, e))?);
+    let mut sink = WavSink::new(file).map_err(|e| e.to_string())?;
+    render_audio(&rec, &parse_patches(&parse_conf(DEFAULT_SFX)), &mut sink, 2.0).map_err(|e| e.to_string())?;
+    sink.finish().map_err(|e| e.to_string())?;
+    Ok(rec.duration())
+}
+
+#[test]
+pub fn test_replay_audio() {
+    // hold off a second, flip a few times, then fly into the floor
+    let mut frames = Vec::new();
+    for i in 0..240u32 {
+        let actions = if i % 50 == 10 {vec![(Action::Flip, KeyStatus::JustPressed)]} else {vec![]};
+        let edges = if i % 50 == 10 {vec![ActionEdge { action: Action::Flip, pressed: true, t: 0.0125 }]} else {vec![]};
+        frames.push(RecordedFrame { dt: 1.0 / 60.0, seed: khash(i), actions, edges, ..Default::default() });
+    }
+    let rec = Recording { seed: 1234, bindings: Bindings::builtin(), scores: HighScores::default(), frames };
+
+    assert_eq!(Recording::from_conf(&parse_conf(&rec.to_conf())).unwrap(), rec);
+    // old ones with the frames up top still load
+    let old = format!("seed = {}\n{}", rec.seed, rec.frames.iter().map(|f| format!("frame = {}\n", f.to_line())).collect::<String>());
+    assert_eq!(Recording::from_conf(&parse_conf(&old)).unwrap(), rec);
+
+    let render = || {
+        let mut sink = WavSink::new(io::Cursor::new(Vec::new())).unwrap();
+        render_audio(&rec, &parse_patches(&parse_conf(DEFAULT_SFX)), &mut sink, 0.5).unwrap();
+        sink.finish().unwrap().into_inner()
+    };
+    let a = render();
+    assert_eq!(a.len(), 44 + seconds_to_frames(4.5) as usize * 4);
+    assert!(a == render());
+    assert!(a[44..].iter().any(|b| *b != 0));
+}
+
+#[test]
+pub fn test_replay_round_trip() {
+    use crate::events::*;
+    use glutin::event::{ElementState::*, Event, VirtualKeyCode::*, WindowEvent};
+    use std::time::{Duration, Instant};
+
+    // live through the aggregator: lose focus for a bit, fall out the bottom,
+    // type a name on the death screen, return saves it and the next return restarts
+    let mut ea = EventAggregator::new(1600.0, 900.0, 1.0, PLAYFIELD_ASPECT);
+    let mut game = Game::new(3);
+    let start = Recording { seed: 3, bindings: ea.bindings().clone(), scores: game.high_scores().clone(), frames: Vec::new() };
+    game.skip_tutorial();
+    let at = |i: u64| Instant::now() + Duration::from_millis(16 * i);
+    ea.handle_event_at(&Event::MainEventsCleared, at(0));
+    let mut frames = Vec::new();
+    let mut live = Vec::new();
+    for i in 1..300 {
+        let events: Vec<Event<()>> = match i {
+            5 => vec![window_event(WindowEvent::Focused(false))],
+            30 => vec![window_event(WindowEvent::Focused(true))],
+            250 => "kev #1".chars().map(|c| window_event(WindowEvent::ReceivedCharacter(c))).chain([key_event(Return, Pressed)]).collect(),
+            251 => vec![key_event(Return, Released)],
+            260 => vec![key_event(Return, Pressed)],
+            _ => vec![],
+        };
+        for e in events.iter() {
+            ea.handle_event_at(e, at(i) - Duration::from_millis(8));
+        }
+        let inputs = ea.handle_event_at(&Event::MainEventsCleared, at(i)).unwrap();
+        game.update(&inputs);
+        frames.push(RecordedFrame::from_inputs(&inputs));
+        live.push((game.events().iter().copied().collect::<Vec<_>>(), game.cursor_mode()));
+        if i == 20 {
+            assert!(game.cursor_mode().visible);
+        }
+    }
+    assert_eq!(live.iter().filter(|(e, _)| e.iter().any(|e| matches!(e, GameEvent::Died { .. }))).count(), 1);
+    assert_eq!(game.high_scores().entries[0].name, "kev 1");
+    assert!(!live[260 - 1].1.visible);
+
+    // through the file and back, then played into a fresh game it does all the same things
+    let rec = Recording::from_conf(&parse_conf(&Recording { frames, ..start }.to_conf())).unwrap();
+    let mut replayed = rec.start();
+    replayed.skip_tutorial();
+    let mut playback = rec.playback();
+    let mut trace = Vec::new();
+    while let Some(inputs) = playback.next_inputs(&mut replayed) {
+        replayed.update(&inputs);
+        trace.push((replayed.events().iter().copied().collect::<Vec<_>>(), replayed.cursor_mode()));
+    }
+    assert!(trace == live);
+    assert_eq!(replayed.high_scores(), game.high_scores());
+}
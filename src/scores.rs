@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use crate::lib::kconf::*;
+
+// High score table. Lives in scores.conf next to wherever the game is run
+// from, same as controls.conf, one [score] section per entry best first. The
+// game only asks for a name when a run would make the table.
+
+pub const MAX_SCORES: usize = 10;
+pub const NAME_LEN: usize = 12;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HighScores {
+    pub entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    pub fn from_conf(sections: &[ConfSection]) -> HighScores {
+        let mut scores = HighScores::default();
+        for s in sections.iter().filter(|s| s.name == "score") {
+            if let Some(score) = s.get("score").and_then(|v| v.parse().ok()) {
+                scores.insert(s.str_or("name", ""), score);
+            }
+        }
+        scores
+    }
+
+    pub fn load(paths: &[&str]) -> HighScores {
+        HighScores::from_conf(&load_conf(paths, ""))
+    }
+
+    pub fn to_conf(&self) -> String {
+        let mut s = String::new();
+        for e in self.entries.iter() {
+            writeln!(s, "[score]\nname = {}\nscore = {}", e.name, e.score).unwrap();
+        }
+        s
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, self.to_conf())
+    }
+
+    pub fn qualifies(&self, score: u32) -> bool {
+        score > 0 && (self.entries.len() < MAX_SCORES || self.entries.last().map(|e| score > e.score).unwrap_or(true))
+    }
+
+    // # would start a comment in the file. ties go below whoever got there first
+    pub fn insert(&mut self, name: &str, score: u32) {
+        let name: String = name.chars().filter(|c| *c != '#' && !c.is_control()).take(NAME_LEN).collect();
+        let name = if name.trim().is_empty() {"???".to_owned()} else {name.trim().to_owned()};
+        let i = self.entries.iter().position(|e| score > e.score).unwrap_or(self.entries.len());
+        self.entries.insert(i, HighScore { name, score });
+        self.entries.truncate(MAX_SCORES);
+    }
+}
+
+#[test]
+pub fn test_high_scores() {
+    let mut hs = HighScores::default();
+    assert!(!hs.qualifies(0));
+    assert!(hs.qualifies(1));
+    hs.insert("ann", 500);
+    hs.insert("  ", 900);
+    hs.insert("bob # not a comment", 500);
+    let names: Vec<&str> = hs.entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["???", "ann", "bob  not a c"]);
+
+    // round trips through the file
+    assert_eq!(HighScores::from_conf(&parse_conf(&hs.to_conf())), hs);
+
+    // once full only beating the bottom gets in
+    for i in 0..MAX_SCORES as u32 {
+        hs.insert("x", 1000 + i);
+    }
+    assert_eq!(hs.entries.len(), MAX_SCORES);
+    assert_eq!(hs.entries[0].score, 1009);
+    assert!(!hs.qualifies(1000));
+    assert!(hs.qualifies(1001));
+}
+
+#[test]
+pub fn test_name_entry() {
+    use glutin::event::VirtualKeyCode::Return;
+    use crate::game::*;
+    use crate::replay::*;
+    use crate::lib::kinput::*;
+    use crate::lib::kaction::*;
+
+    // nothing pressed so it falls out the bottom, past the tutorial so thats the death screen.
+    // it asks once the score has moved, return saves, the next return restarts
+    let run = |scores: HighScores| {
+        let mut game = Game::new(1);
+        game.set_high_scores(scores);
+        game.skip_tutorial();
+        let idle = RecordedFrame { dt: 1.0 / 60.0, ..Default::default() };
+        let typed = RecordedFrame {
+            actions: vec![(Action::Restart, KeyStatus::JustPressed)],
+            inputs: vec![InputEdge { input: Input::Key(Return), pressed: true, t: 0.0 }],
+            typed: "kev".chars().map(Typed::Char).chain([Typed::Key { key: Return, ctrl: false, shift: false }]).collect(),
+            ..idle.clone()
+        };
+        let mut t = 0.0;
+        for (i, f) in std::iter::repeat_n(&idle, 200).chain([&typed]).enumerate() {
+            t += f.dt;
+            game.update(&f.to_inputs(i as u32, t));
+        }
+        let saved = game.take_high_scores();
+        let still_dead = game.cursor_mode().visible;
+        (game, saved, still_dead)
+    };
+
+    let (mut game, saved, still_dead) = run(HighScores::default());
+    let saved = saved.unwrap();
+    assert_eq!(saved.entries.len(), 1);
+    assert_eq!(saved.entries[0].name, "kev");
+    assert!(saved.entries[0].score > 0);
+    assert!(still_dead);
+    assert!(game.take_high_scores().is_none());
+    let restart = RecordedFrame { dt: 1.0 / 60.0, actions: vec![(Action::Restart, KeyStatus::JustPressed)], ..Default::default() };
+    game.update(&restart.to_inputs(202, 202.0 / 60.0));
+    assert!(!game.cursor_mode().visible);
+
+    // a table it wont get into doesnt ask, so that return restarts straight away
+    let mut full = HighScores::default();
+    for _ in 0..MAX_SCORES {
+        full.insert("best", 1000000);
+    }
+    let (_, saved, still_dead) = run(full);
+    assert!(saved.is_none());
+    assert!(!still_dead);
+}
@@ -0,0 +1,230 @@
+use std::f32::consts::PI;
+
+use crate::lib::kmath::*;
+use crate::krenderer::*;
+
+// Sky and ocean as a function of distance travelled. Keyframed palettes get
+// blended with kmath::gradient, the keyframe spacing gets jittered a bit per
+// run seed so runs look different but the same seed always gives the same sky.
+
+#[derive(Clone, Copy, Debug)]
+pub struct SkyKey {
+    pub d: f32,
+    pub sky_top: Vec3,
+    pub sky_bot: Vec3,
+    pub ocean_far: Vec3,
+    pub ocean_near: Vec3,
+    pub layer_tint: Vec3,   // multiplied into the parallax layers (clouds, hills...)
+    pub sun: Vec3,
+    pub stars: f32,
+}
+
+// dawn, day, sunset, night, space
+pub const SKY_KEYS: [SkyKey; 6] = [
+    SkyKey {
+        d: 0.0,
+        sky_top: Vec3::new(0.35, 0.3, 0.6),
+        sky_bot: Vec3::new(1.0, 0.65, 0.5),
+        ocean_far: Vec3::new(0.4, 0.3, 0.5),
+        ocean_near: Vec3::new(0.25, 0.2, 0.45),
+        layer_tint: Vec3::new(1.0, 0.85, 0.85),
+        sun: Vec3::new(1.0, 0.7, 0.3),
+        stars: 0.2,
+    },
+    SkyKey {
+        d: 15.0,
+        sky_top: Vec3::new(0.2, 0.2, 0.8),
+        sky_bot: Vec3::new(0.3, 0.3, 1.0),
+        ocean_far: Vec3::new(0.2, 0.2, 0.55),
+        ocean_near: Vec3::new(0.2, 0.2, 0.65),
+        layer_tint: Vec3::new(1.0, 1.0, 1.0),
+        sun: Vec3::new(1.0, 1.0, 0.8),
+        stars: 0.0,
+    },
+    SkyKey {
+        d: 40.0,
+        sky_top: Vec3::new(0.2, 0.2, 0.8),
+        sky_bot: Vec3::new(0.3, 0.3, 1.0),
+        ocean_far: Vec3::new(0.2, 0.2, 0.55),
+        ocean_near: Vec3::new(0.2, 0.2, 0.65),
+        layer_tint: Vec3::new(1.0, 1.0, 1.0),
+        sun: Vec3::new(1.0, 1.0, 0.8),
+        stars: 0.0,
+    },
+    SkyKey {
+        d: 55.0,
+        sky_top: Vec3::new(0.4, 0.2, 0.5),
+        sky_bot: Vec3::new(1.0, 0.45, 0.2),
+        ocean_far: Vec3::new(0.5, 0.25, 0.3),
+        ocean_near: Vec3::new(0.25, 0.15, 0.35),
+        layer_tint: Vec3::new(1.0, 0.7, 0.6),
+        sun: Vec3::new(1.0, 0.5, 0.2),
+        stars: 0.1,
+    },
+    SkyKey {
+        d: 70.0,
+        sky_top: Vec3::new(0.02, 0.02, 0.1),
+        sky_bot: Vec3::new(0.08, 0.08, 0.25),
+        ocean_far: Vec3::new(0.05, 0.05, 0.15),
+        ocean_near: Vec3::new(0.02, 0.02, 0.1),
+        layer_tint: Vec3::new(0.3, 0.3, 0.45),
+        sun: Vec3::new(0.9, 0.9, 1.0),
+        stars: 1.0,
+    },
+    SkyKey {
+        d: 100.0,
+        sky_top: Vec3::new(0.0, 0.0, 0.0),
+        sky_bot: Vec3::new(0.02, 0.0, 0.06),
+        ocean_far: Vec3::new(0.03, 0.0, 0.08),
+        ocean_near: Vec3::new(0.0, 0.0, 0.02),
+        layer_tint: Vec3::new(0.15, 0.1, 0.25),
+        sun: Vec3::new(0.9, 0.9, 1.0),
+        stars: 1.0,
+    },
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct SkyState {
+    pub sky_top: Vec4,
+    pub sky_bot: Vec4,
+    pub ocean_far: Vec4,
+    pub ocean_near: Vec4,
+    pub layer_tint: Vec4,
+    pub stars: f32,
+    pub sun: Option<(Vec2, Vec4)>,      // in units of the sky rect, 0..1
+    pub moon: Option<(Vec2, Vec4)>,
+}
+
+pub struct Sky {
+    pub keys: Vec<SkyKey>,
+    seed: u32,
+}
+
+fn arc_pos(p: f32) -> Vec2 {
+    // rises on the right, sets on the left since we are flying right
+    Vec2::new(lerp(0.9, 0.1, p), 1.0 - (p * PI).sin() * 0.8)
+}
+
+impl Sky {
+    pub fn new(seed: u32) -> Sky {
+        Sky::new_with_keys(seed, SKY_KEYS.to_vec())
+    }
+
+    pub fn new_with_keys(seed: u32, mut keys: Vec<SkyKey>) -> Sky {
+        // jitter everything but the first key by up to 10% of the gap before it
+        let mut prev_d = keys.first().map(|k| k.d).unwrap_or(0.0);
+        let mut shift = 0.0;
+        for (i, k) in keys.iter_mut().enumerate().skip(1) {
+            let gap = k.d - prev_d;
+            prev_d = k.d;
+            shift += kuniform(khash(seed.wrapping_add(i as u32 * 1231237)), -0.1, 0.1) * gap;
+            k.d += shift;
+        }
+        Sky { keys, seed }
+    }
+
+    fn channel(&self, d: f32, f: impl Fn(&SkyKey) -> Vec3) -> Vec4 {
+        let first = self.keys.first().unwrap().d;
+        let last = self.keys.last().unwrap().d;
+        let d = d.max(first).min(last);
+        gradient(d, self.keys.iter().map(|k| (f(k), k.d)).collect()).promote(1.0)
+    }
+
+    pub fn sample(&self, d: f32) -> SkyState {
+        let stars = self.channel(d, |k| Vec3::new(k.stars, 0.0, 0.0)).x;
+        let sun_col = self.channel(d, |k| k.sun);
+
+        // sun is up from dawn to the sunset key, moon from the night key onwards
+        let n = self.keys.len();
+        let sun = if n >= 4 {
+            let (start, end) = (self.keys[0].d, self.keys[n-3].d);
+            let p = unlerp(d, start - (self.keys[1].d - start), end);
+            if p > 0.0 && p < 1.0 {Some((arc_pos(p), sun_col))} else {None}
+        } else {
+            None
+        };
+        let moon = if n >= 2 {
+            let (start, end) = (self.keys[n-2].d, self.keys[n-1].d);
+            let p = unlerp(d, start, end) * 0.5; // hangs at the top once you get to space
+            if p > 0.0 {Some((arc_pos(p.min(0.5)), Vec4::new(0.9, 0.9, 1.0, 1.0)))} else {None}
+        } else {
+            None
+        };
+
+        SkyState {
+            sky_top: self.channel(d, |k| k.sky_top),
+            sky_bot: self.channel(d, |k| k.sky_bot),
+            ocean_far: self.channel(d, |k| k.ocean_far),
+            ocean_near: self.channel(d, |k| k.ocean_near),
+            layer_tint: self.channel(d, |k| k.layer_tint),
+            stars,
+            sun,
+            moon,
+        }
+    }
+
+    pub fn draw_sky(&self, kc: &mut KRCanvas, state: &SkyState, sky: Rect, t: f32) {
+        kc.set_layer(RenderLayer::Sky);
+        kc.grad_rect_ud(sky, state.sky_top, state.sky_bot);
+
+        if state.stars > 0.0 {
+            kc.set_layer(RenderLayer::Stars);
+            for i in 0..80 {
+                let s = khash(self.seed.wrapping_add(i * 2938479));
+                let twinkle = 0.7 + 0.3 * (t * kuniform(s.wrapping_mul(13), 1.0, 4.0) + krand(s.wrapping_mul(17)) * 2.0 * PI).sin();
+                // slow drift, wraps around
+                let x = (krand(s) - t * 0.002).rem_euclid(1.0);
+                let y = krand(s.wrapping_mul(5)) * 0.95;
+                let size = kuniform(s.wrapping_mul(11), 0.002, 0.005);
+                kc.set_colour(Vec4::new(1.0, 1.0, 1.0, state.stars * twinkle));
+                kc.rect(Rect::centered(Vec2::new(sky.x + x * sky.w, sky.y + y * sky.h), size, size));
+            }
+        }
+
+        kc.set_layer(RenderLayer::Sun);
+        if let Some((p, col)) = state.sun {
+            kc.set_colour(col);
+            kc.circle(Vec2::new(sky.x + p.x * sky.w, sky.y + p.y * sky.h), 0.05);
+        }
+        if let Some((p, col)) = state.moon {
+            let c = Vec2::new(sky.x + p.x * sky.w, sky.y + p.y * sky.h);
+            kc.set_colour(col);
+            kc.circle(c, 0.035);
+            // bite out of it, own layer so it still covers the moon while its fading in
+            kc.set_layer(RenderLayer::MoonShadow);
+            kc.set_colour(state.sky_top.lerp(state.sky_bot, p.y));
+            kc.circle(c + Vec2::new(0.015, -0.008), 0.03);
+        }
+    }
+
+    pub fn draw_ocean(&self, kc: &mut KRCanvas, state: &SkyState, sky: Rect, ocean: Rect, t: f32) {
+        kc.set_layer(RenderLayer::Ocean);
+        kc.grad_rect_ud(ocean, state.ocean_far, state.ocean_near);
+
+        // shimmery reflection column under whatever is in the sky
+        kc.set_layer(RenderLayer::Reflections);
+        for (p, col) in state.sun.iter().chain(state.moon.iter()) {
+            let x = sky.x + p.x * sky.w;
+            let strength = 1.0 - p.y * 0.5;
+            for i in 0..8 {
+                let fi = i as f32;
+                let y = ocean.y + ocean.h * (fi + 0.5) / 8.0;
+                let w = 0.08 * (1.0 + fi * 0.15) * (0.8 + 0.2 * (t * 3.0 + fi * 1.7).sin());
+                let mut c = *col;
+                c.w = strength * 0.5 * (1.0 - fi / 8.0);
+                kc.set_colour(c);
+                kc.rect(Rect::centered(Vec2::new(x, y), w, ocean.h / 24.0));
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_sky_deterministic() {
+    let a = Sky::new(1234).sample(50.0);
+    let b = Sky::new(1234).sample(50.0);
+    let c = Sky::new(4321).sample(50.0);
+    assert_eq!(a.sky_top, b.sky_top);
+    assert_eq!(a.ocean_near, b.ocean_near);
+    assert_ne!(a.sky_top, c.sky_top);
+}
@@ -0,0 +1,245 @@
+use crate::lib::kmath::*;
+use crate::lib::kconf::*;
+use crate::krenderer::*;
+use crate::parallax::CloudStyle;
+use crate::sky::*;
+
+// Art direction lives in themes/*.theme so it can be fiddled with without
+// recompiling. Press T in game to cycle, the directory gets re-read every time
+// so edits show up straight away.
+//
+// [theme] section has the flat colours and fills, then optional [sky] sections
+// are the day/night keyframes (see sky.rs), in order.
+
+pub const DEFAULT_THEME: &str = include_str!("../themes/default.theme");
+
+const THEME_DIRS: [&str; 2] = ["themes", "../../themes"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    Solid,
+    Stripes,
+    Checker,
+    Tartan,
+}
+
+impl Pattern {
+    pub fn from_name(s: &str) -> Pattern {
+        match s {
+            "stripes" => Pattern::Stripes,
+            "checker" => Pattern::Checker,
+            "tartan" => Pattern::Tartan,
+            _ => Pattern::Solid,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Fill {
+    pub pattern: Pattern,
+    pub a: Vec4,
+    pub b: Vec4,
+    pub scale: f32,
+}
+
+impl Fill {
+    // eg. for prefix "wall": wall, wall_pattern, wall_pattern_colour, wall_pattern_scale
+    pub fn from_conf(section: &ConfSection, prefix: &str, default: Vec4) -> Fill {
+        let a = section.vec4_or(prefix, default);
+        Fill {
+            pattern: Pattern::from_name(section.str_or(&format!("{}_pattern", prefix), "solid")),
+            a,
+            b: section.vec4_or(&format!("{}_pattern_colour", prefix), a * 0.7),
+            scale: section.f32_or(&format!("{}_pattern_scale", prefix), 0.04).max(0.001),
+        }
+    }
+
+    // pattern is anchored to the rect so it scrolls with whatever its on. clip
+    // keeps us from drawing thousands of stripes on the huge wall rects
+    pub fn draw(&self, kc: &mut KRCanvas, r: Rect, clip: Rect) {
+        let x0 = r.x.max(clip.x);
+        let y0 = r.y.max(clip.y);
+        let x1 = r.right().min(clip.right());
+        let y1 = r.bot().min(clip.bot());
+        if x1 <= x0 || y1 <= y0 {
+            return;
+        }
+        let vis = Rect::new(x0, y0, x1 - x0, y1 - y0);
+        kc.set_colour(self.a);
+        kc.rect(vis);
+        if self.pattern == Pattern::Solid {
+            return;
+        }
+
+        let s = self.scale;
+        // band index range that overlaps the visible bit, relative to the rect origin
+        let i0 = ((x0 - r.x) / s).floor() as i32;
+        let i1 = ((x1 - r.x) / s).ceil() as i32;
+        let j0 = ((y0 - r.y) / s).floor() as i32;
+        let j1 = ((y1 - r.y) / s).ceil() as i32;
+        let cell = |i: i32, j: i32| {
+            let c = Rect::new(r.x + i as f32 * s, r.y + j as f32 * s, s, s);
+            let cx0 = c.x.max(x0);
+            let cy0 = c.y.max(y0);
+            Rect::new(cx0, cy0, c.right().min(x1) - cx0, c.bot().min(y1) - cy0)
+        };
+        match self.pattern {
+            Pattern::Solid => {},
+            Pattern::Stripes => {
+                kc.set_colour(self.b);
+                for j in j0..j1 {
+                    if j.rem_euclid(2) == 0 {
+                        let c = cell(i0, j);
+                        kc.rect(Rect::new(x0, c.y, x1 - x0, c.h));
+                    }
+                }
+            },
+            Pattern::Checker => {
+                kc.set_colour(self.b);
+                for i in i0..i1 {
+                    for j in j0..j1 {
+                        if (i + j).rem_euclid(2) == 0 {
+                            kc.rect(cell(i, j));
+                        }
+                    }
+                }
+            },
+            Pattern::Tartan => {
+                // translucent bands both ways, wide and thin, crossings come out darker
+                let mut b = self.b;
+                b.w *= 0.5;
+                kc.set_colour(b);
+                for i in i0..i1 {
+                    let c = cell(i, j0);
+                    let w = if i.rem_euclid(3) == 0 {c.w} else {c.w * 0.2};
+                    kc.rect(Rect::new(c.x, y0, w.min(x1 - c.x), y1 - y0));
+                }
+                for j in j0..j1 {
+                    let c = cell(i0, j);
+                    let h = if j.rem_euclid(3) == 0 {c.h} else {c.h * 0.2};
+                    kc.rect(Rect::new(x0, c.y, x1 - x0, h.min(y1 - c.y)));
+                }
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub text: Vec4,
+    pub font: String,
+    pub player: Vec4,
+    pub player_slow: Vec4,
+    pub player_fast: Vec4,
+    pub pickup: Vec4,
+    pub wall: Fill,
+    pub ocean: Fill,
+    pub bars: Fill,
+    pub pause_overlay: Vec4,
+    pub cloud_style: CloudStyle,
+    pub cloud_tint: Vec4,
+    pub sky_keys: Vec<SkyKey>,
+}
+
+fn sky_key_from_conf(section: &ConfSection, fallback: &SkyKey) -> SkyKey {
+    let v3 = |key: &str, default: Vec3| {
+        let v = section.vec4_or(key, default.promote(1.0));
+        Vec3::new(v.x, v.y, v.z)
+    };
+    SkyKey {
+        d: section.f32_or("d", fallback.d),
+        sky_top: v3("sky_top", fallback.sky_top),
+        sky_bot: v3("sky_bot", fallback.sky_bot),
+        ocean_far: v3("ocean_far", fallback.ocean_far),
+        ocean_near: v3("ocean_near", fallback.ocean_near),
+        layer_tint: v3("layer_tint", fallback.layer_tint),
+        sun: v3("sun", fallback.sun),
+        stars: section.f32_or("stars", fallback.stars),
+    }
+}
+
+impl Theme {
+    pub fn from_conf(sections: &[ConfSection]) -> Theme {
+        let empty = ConfSection { name: "theme".to_owned(), entries: Vec::new() };
+        let t = sections.iter().find(|s| s.name == "theme").unwrap_or(&empty);
+
+        let mut sky_keys: Vec<SkyKey> = Vec::new();
+        for (i, s) in sections.iter().filter(|s| s.name == "sky").enumerate() {
+            let fallback = SKY_KEYS[i.min(SKY_KEYS.len() - 1)];
+            sky_keys.push(sky_key_from_conf(s, &fallback));
+        }
+        if sky_keys.len() < 2 {
+            sky_keys = SKY_KEYS.to_vec();
+        }
+
+        Theme {
+            name: t.str_or("name", "unnamed").to_owned(),
+            text: t.vec4_or("text", Vec4::new(1.0, 1.0, 1.0, 1.0)),
+            font: t.str_or("font", "").to_owned(),
+            player: t.vec4_or("player", Vec4::new(0.0, 0.9, 0.9, 1.0)),
+            player_slow: t.vec4_or("player_slow", Vec4::new(0.0, 0.0, 1.0, 1.0)),
+            player_fast: t.vec4_or("player_fast", Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            pickup: t.vec4_or("pickup", Vec4::new(0.8, 0.0, 0.0, 1.0)),
+            wall: Fill::from_conf(t, "wall", Vec4::new(0.4, 0.0, 0.0, 1.0)),
+            ocean: Fill::from_conf(t, "ocean", Vec4::new(1.0, 1.0, 1.0, 0.0)),
+            bars: Fill::from_conf(t, "bars", Vec4::new(0.0, 0.0, 0.0, 0.5)),
+            pause_overlay: t.vec4_or("pause_overlay", Vec4::new(1.0, 1.0, 1.0, 0.5)),
+            cloud_style: CloudStyle::from_name(t.str_or("cloud_style", "puffy")),
+            cloud_tint: t.vec4_or("cloud_tint", Vec4::new(1.0, 1.0, 1.0, 1.0)),
+            sky_keys,
+        }
+    }
+
+    pub fn builtin() -> Theme {
+        Theme::from_conf(&parse_conf(DEFAULT_THEME))
+    }
+
+    // built in default first, then anything in the themes dir that parses, by filename
+    pub fn load_all() -> Vec<Theme> {
+        let mut themes = vec![Theme::builtin()];
+        for dir in THEME_DIRS {
+            if let Ok(entries) = std::fs::read_dir(dir) {
+                let mut paths: Vec<_> = entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|p| p.extension().map(|e| e == "theme").unwrap_or(false))
+                    .collect();
+                paths.sort();
+                for path in paths {
+                    if let Ok(s) = std::fs::read_to_string(&path) {
+                        let theme = Theme::from_conf(&parse_conf(&s));
+                        // file version of default replaces the baked in one
+                        if let Some(existing) = themes.iter_mut().find(|t| t.name == theme.name) {
+                            *existing = theme;
+                        } else {
+                            themes.push(theme);
+                        }
+                    }
+                }
+                break;
+            }
+        }
+        themes
+    }
+
+    // reloads from disk and returns whatever comes after `current`
+    pub fn next_after(current: &str) -> Theme {
+        let themes = Theme::load_all();
+        let idx = themes.iter().position(|t| t.name == current).map(|i| (i + 1) % themes.len()).unwrap_or(0);
+        themes[idx].clone()
+    }
+
+    pub fn player_colour(&self, speed_t: f32) -> Vec4 {
+        self.player_slow.lerp(self.player_fast, speed_t.clamp(0.0, 1.0))
+    }
+}
+
+#[test]
+pub fn test_theme_defaults() {
+    let t = Theme::from_conf(&parse_conf("[theme]\nname = x\nwall = 0 1 0\nwall_pattern = tartan"));
+    assert_eq!(t.name, "x");
+    assert_eq!(t.wall.a, Vec4::new(0.0, 1.0, 0.0, 1.0));
+    assert_eq!(t.wall.pattern, Pattern::Tartan);
+    assert_eq!(t.sky_keys.len(), SKY_KEYS.len());
+}
@@ -7,6 +7,10 @@ uniform sampler2D atlas;
 
 void main() {
     frag_colour = texture(atlas, uv) * vert_colour;
+    // sprite cutouts shouldnt write depth
+    if (frag_colour.a == 0.0) {
+        discard;
+    }
 }
 
 